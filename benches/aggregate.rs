@@ -0,0 +1,141 @@
+//! Benchmarks of the aggregation alone, run with `cargo bench --bench aggregate`.
+//!
+//! fss is only a binary, so the modules the aggregator needs are compiled in here from `src/`.
+//! This target has its own allocator, which counts the allocations made by each thread: before
+//! timing anything, each grouping is checked to allocate once per group rather than once per
+//! file, and the allocations per file are printed.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/access.rs"]
+mod access;
+#[path = "../src/aggregate.rs"]
+mod aggregate;
+#[path = "../src/cli.rs"]
+mod cli;
+#[path = "../src/duplicates.rs"]
+mod duplicates;
+#[path = "../src/error_log.rs"]
+mod error_log;
+#[path = "../src/extents.rs"]
+mod extents;
+#[path = "../src/filesize.rs"]
+mod filesize;
+#[path = "../src/filter/mod.rs"]
+mod filter;
+#[path = "../src/group_map.rs"]
+mod group_map;
+#[path = "../src/groups.rs"]
+mod groups;
+#[path = "../src/long_path.rs"]
+mod long_path;
+#[path = "../src/progress.rs"]
+mod progress;
+#[path = "../src/reparse.rs"]
+mod reparse;
+#[path = "../src/sample.rs"]
+mod sample;
+#[path = "../src/threads.rs"]
+mod threads;
+#[path = "../src/unique_id.rs"]
+mod unique_id;
+#[path = "../src/walk.rs"]
+mod walk;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::path::PathBuf;
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+
+use aggregate::{Aggregator, FileEntry};
+use cli::GroupBy;
+use filesize::FilesizeType;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const FILES: usize = 100_000;
+/// Distinct names, directories and extensions among the files
+const GROUPS: usize = 100;
+
+/// Files whose names, directories and extensions repeat every `GROUPS` files
+fn entries() -> Vec<FileEntry> {
+    (0..FILES)
+        .map(|i| {
+            let path = format!("dir{}/name{}.ext{}", i % GROUPS, i % GROUPS, i % GROUPS);
+            FileEntry::new(PathBuf::from(path), 1)
+        })
+        .collect()
+}
+
+fn aggregate(c: &mut Criterion) {
+    let groupings = [
+        GroupBy::FileName,
+        GroupBy::Directory,
+        GroupBy::Extension,
+        GroupBy::Type,
+    ];
+
+    for group_by in groupings {
+        let entries = entries();
+        let mut aggregator = Aggregator::new(group_by);
+        let before = ALLOCATIONS.get();
+        for entry in entries {
+            aggregator.add_file(entry);
+        }
+        let allocations = ALLOCATIONS.get() - before;
+        println!(
+            "{group_by:?}: {allocations} allocations for {FILES} files in {GROUPS} groups \
+             ({:.4} per file)",
+            allocations as f64 / FILES as f64
+        );
+        // One per group and a few for growing the map, instead of one per file
+        assert!(
+            allocations < 2 * GROUPS as u64,
+            "{group_by:?}: {allocations}"
+        );
+        assert_eq!(aggregator.finish().total, FILES as u64);
+    }
+
+    let mut group = c.benchmark_group(format!("aggregate {FILES} files"));
+    for group_by in groupings {
+        group.bench_with_input(
+            BenchmarkId::new("group-by", format!("{group_by:?}")),
+            &group_by,
+            |b, &group_by| {
+                b.iter_batched(
+                    entries,
+                    |entries| {
+                        let mut aggregator = Aggregator::new(group_by);
+                        for entry in entries {
+                            aggregator.add_file(entry);
+                        }
+                        aggregator.finish()
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aggregate);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+//! Benchmarks of the walk, run with `cargo bench`.
+//!
+//! Each benchmark runs the release binary with `--bench-mode` over a tree generated once in a
+//! temporary directory, so only the walk and the grouping are measured. The tree is laid out
+//! like the one of `scripts/gen-bench-tree.sh`, smaller so that a run takes seconds, and is
+//! the same from one run to the next. `FSS_BENCH_DIRS`, `FSS_BENCH_FILES` and `FSS_BENCH_DEPTH`
+//! change its size, e.g. `FSS_BENCH_DIRS=1000 cargo bench` for a million files.
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+const EXTENSIONS: [&str; 19] = [
+    "txt", "jpg", "png", "rs", "py", "mp4", "tar", "gz", "csv", "log", "json", "md", "c", "h", "o",
+    "so", "bam", "fa", "vcf",
+];
+
+/// A deterministic tree of sparse files
+struct Fixture {
+    dirs: usize,
+    files_per_dir: usize,
+    /// Levels each directory is nested, 1 for none
+    depth: usize,
+}
+
+impl Fixture {
+    fn from_env() -> Self {
+        let var = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Fixture {
+            dirs: var("FSS_BENCH_DIRS", 100),
+            files_per_dir: var("FSS_BENCH_FILES", 1000),
+            depth: var("FSS_BENCH_DEPTH", 1),
+        }
+    }
+
+    fn file_count(&self) -> usize {
+        self.dirs * self.files_per_dir
+    }
+
+    /// Create the tree in `dest`: the directories spread over ten top ones, every file name
+    /// unique, and the sizes varying from one directory to the next
+    fn generate(&self, dest: &Path) -> io::Result<()> {
+        for d in 0..self.dirs {
+            let mut dir = dest.join(format!("dir{}", d % 10)).join(format!("sub{d}"));
+            for level in 1..self.depth {
+                dir.push(format!("level{level}"));
+            }
+            fs::create_dir_all(&dir)?;
+            let size = (d as u64 * 37) % 8192;
+            for f in 0..self.files_per_dir {
+                let ext = EXTENSIONS[(d + f) % EXTENSIONS.len()];
+                File::create(dir.join(format!("file{d}_{f}.{ext}")))?.set_len(size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fss(tree: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_fss"))
+        .arg("--bench-mode")
+        .arg("--no-config")
+        .args(args)
+        .arg(tree)
+        .output()
+        .expect("fss runs");
+    assert!(output.status.success(), "{output:?}");
+}
+
+fn walk(c: &mut Criterion) {
+    let fixture = Fixture::from_env();
+    let dir = tempfile::tempdir().expect("a temporary directory");
+    let tree: PathBuf = dir.path().join("tree");
+    fixture.generate(&tree).expect("the tree is generated");
+
+    let mut group = c.benchmark_group(format!("walk {} files", fixture.file_count()));
+    group.sample_size(10);
+    // The default is three threads per CPU, compared with fewer and more
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut thread_counts = vec![1, cpus, 3 * cpus, 6 * cpus];
+    thread_counts.dedup();
+    for threads in thread_counts {
+        let threads = threads.to_string();
+        group.bench_with_input(BenchmarkId::new("threads", &threads), &threads, |b, j| {
+            b.iter(|| fss(&tree, &["-b", "-j", j]))
+        });
+    }
+    for group_by in ["e", "f"] {
+        group.bench_with_input(BenchmarkId::new("group-by", group_by), group_by, |b, g| {
+            b.iter(|| fss(&tree, &["-b", "-g", g]))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, walk);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+#!/usr/bin/env bash
+# Generate a synthetic directory tree for benchmarking fss.
+#
+# Usage: scripts/gen-bench-tree.sh <DEST> [DIRS] [FILES_PER_DIR] [DEPTH]
+#
+# The defaults create 1000 directories with 1000 files each (one million files).
+# With a DEPTH above 1, each directory is nested that many levels deep, which makes
+# every path long and stresses path resolution.
+# Files are created sparse with `truncate`, so the tree takes almost no disk space,
+# but the apparent sizes vary so that `fss -b` has something to aggregate.
+# Every file name is unique, so that `fss -g f` makes one group per file.
+# The layout is deterministic: running the script twice produces the same tree.
+set -euo pipefail
+
+dest=${1:?usage: gen-bench-tree.sh <DEST> [DIRS] [FILES_PER_DIR] [DEPTH]}
+dirs=${2:-1000}
+files=${3:-1000}
+depth=${4:-1}
+exts=(txt jpg png rs py mp4 tar gz csv log json md c h o so bam fa vcf pdf)
+
+for ((d = 0; d < dirs; d++)); do
+    dir="$dest/dir$((d % 10))/sub$d"
+    for ((l = 1; l < depth; l++)); do
+        dir="$dir/level$l"
+    done
+    mkdir -p "$dir"
+    (
+        cd "$dir"
+        names=()
+        for ((f = 0; f < files; f++)); do
+            names+=("file${d}_$f.${exts[$(((d + f) % ${#exts[@]}))]}")
+        done
+        touch "${names[@]}"
+        truncate -s $(((d * 37) % 8192)) "${names[@]}"
+    )
+done
@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Print the peak resident memory of a command, in KiB, with its output discarded.
+#
+# Usage: scripts/peak-rss.sh <COMMAND> [ARGS]...
+#
+# e.g. scripts/peak-rss.sh ./target/release/fss -b -g f /tmp/benchtree
+# Relies on GNU time when installed, and on Python otherwise.
+set -euo pipefail
+
+if [[ $# -eq 0 ]]; then
+    echo "usage: peak-rss.sh <COMMAND> [ARGS]..." >&2
+    exit 1
+fi
+
+if [[ -x /usr/bin/time ]] && /usr/bin/time -f %M true >/dev/null 2>&1; then
+    /usr/bin/time -f %M "$@" 2>&1 >/dev/null | tail -n 1
+else
+    python3 - "$@" <<'PY'
+import resource, subprocess, sys
+subprocess.run(sys.argv[1:], stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL)
+# Kilobytes on Linux, bytes on macOS
+peak = resource.getrusage(resource.RUSAGE_CHILDREN).ru_maxrss
+print(peak // 1024 if sys.platform == "darwin" else peak)
+PY
+fi
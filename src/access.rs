@@ -0,0 +1,185 @@
+//! What the current user may do with a file, for `--group-by access`.
+//!
+//! The access is worked out from the permission bits and the owner in the metadata the walk
+//! already read, not by asking the kernel for each file: ACLs, read-only mounts and security
+//! modules are not taken into account. Files that anyone may write to are put apart whatever
+//! the user may do with them, they are what a permission audit looks for.
+use std::fmt::Display;
+use std::fs::Metadata;
+
+/// The access of a user to a file
+#[derive(Hash, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+    Neither,
+    /// Anyone may write to it
+    WorldWritable,
+    /// Not known: links that are not followed, files read from an inventory, or a platform
+    /// without Unix permissions
+    Unknown,
+}
+
+impl Access {
+    fn label(self) -> &'static str {
+        match self {
+            Access::ReadWrite => "Read-write",
+            Access::ReadOnly => "Read-only",
+            Access::WriteOnly => "Write-only",
+            Access::Neither => "No access",
+            Access::WorldWritable => "World-writable",
+            Access::Unknown => "Unknown",
+        }
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The user whose access is worked out, with the IDs that decide it
+#[derive(Debug, Clone)]
+pub struct User {
+    #[cfg(unix)]
+    uid: u32,
+    #[cfg(unix)]
+    gid: u32,
+    /// Supplementary groups
+    #[cfg(unix)]
+    groups: Vec<u32>,
+}
+
+#[cfg(unix)]
+impl User {
+    /// The user running the scan, with its effective IDs
+    pub fn current() -> Self {
+        // SAFETY: these calls cannot fail, and `getgroups` writes at most `groups.len()` IDs
+        unsafe {
+            let mut groups = vec![0; libc::getgroups(0, std::ptr::null_mut()).max(0) as usize];
+            let len = libc::getgroups(groups.len() as libc::c_int, groups.as_mut_ptr());
+            groups.truncate(len.max(0) as usize);
+            User {
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+                groups,
+            }
+        }
+    }
+
+    pub fn access(&self, metadata: &Metadata) -> Access {
+        use std::os::unix::fs::MetadataExt;
+
+        // The bits of a link say nothing about what it points to
+        if metadata.is_symlink() {
+            return Access::Unknown;
+        }
+        let mode = metadata.mode();
+        if mode & 0o002 != 0 {
+            return Access::WorldWritable;
+        }
+        // Root reads and writes anything
+        let bits = if self.uid == 0 {
+            0o6
+        } else if metadata.uid() == self.uid {
+            mode >> 6
+        } else if metadata.gid() == self.gid || self.groups.contains(&metadata.gid()) {
+            mode >> 3
+        } else {
+            mode
+        };
+        match (bits & 0o4 != 0, bits & 0o2 != 0) {
+            (true, true) => Access::ReadWrite,
+            (true, false) => Access::ReadOnly,
+            (false, true) => Access::WriteOnly,
+            (false, false) => Access::Neither,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl User {
+    pub fn current() -> Self {
+        User {}
+    }
+
+    pub fn access(&self, _metadata: &Metadata) -> Access {
+        Access::Unknown
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn access_follows_the_permission_bits() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            fs::symlink_metadata(&path).unwrap()
+        };
+        let files = [
+            metadata("rw", 0o640),
+            metadata("r", 0o464),
+            metadata("w", 0o240),
+            metadata("none", 0o070),
+            metadata("shared", 0o666),
+        ];
+        let owner_uid = files[0].uid();
+        let owner_gid = files[0].gid();
+        let owner = User {
+            uid: owner_uid,
+            gid: u32::MAX,
+            groups: Vec::new(),
+        };
+        let group_member = User {
+            uid: owner_uid.wrapping_add(1).max(1),
+            gid: u32::MAX,
+            groups: vec![owner_gid],
+        };
+        let other = User {
+            uid: owner_uid.wrapping_add(1).max(1),
+            gid: owner_gid.wrapping_add(1),
+            groups: Vec::new(),
+        };
+        let root = User {
+            uid: 0,
+            gid: 0,
+            groups: Vec::new(),
+        };
+
+        let classes =
+            |user: &User| -> Vec<Access> { files.iter().map(|file| user.access(file)).collect() };
+        use Access::*;
+        // Tests running as root own the files as root
+        if owner_uid != 0 {
+            assert_eq!(
+                classes(&owner),
+                [ReadWrite, ReadOnly, WriteOnly, Neither, WorldWritable]
+            );
+        }
+        assert_eq!(
+            classes(&group_member),
+            [ReadOnly, ReadWrite, ReadOnly, ReadWrite, WorldWritable]
+        );
+        assert_eq!(
+            classes(&other),
+            [Neither, ReadOnly, Neither, Neither, WorldWritable]
+        );
+        assert_eq!(
+            classes(&root),
+            [ReadWrite, ReadWrite, ReadWrite, ReadWrite, WorldWritable]
+        );
+
+        std::os::unix::fs::symlink("rw", dir.path().join("link")).unwrap();
+        let link = fs::symlink_metadata(dir.path().join("link")).unwrap();
+        assert_eq!(other.access(&link), Unknown);
+    }
+}
@@ -0,0 +1,1510 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::{
+    access::Access,
+    cli::GroupBy,
+    duplicates::{Candidate, Duplicates},
+    error_log::ErrorLog,
+    filesize::{Usage, round_up},
+    filter::SizeFilter,
+    group_map::GroupMap,
+    groups::{AgeBucket, FileType, FineType, canonical_ext, year_month},
+    long_path,
+    sample::Sampler,
+    unique_id::UniqueID,
+    walk::{Error, ErrorKind},
+};
+
+/// Append the extension of `path` used to group files to `key`. Nothing is appended when there
+/// is no extension.
+///
+/// - The extension is what follows the last dot of the file name, lowercased (also non-ASCII
+///   letters, so `FOTO.ÄPF` and `foto.äpf` end up together)
+/// - Dotfiles such as `.gitignore` have no extension, the dot is part of their name
+/// - A trailing dot does not start an extension: `foo.` and `archive.tar.` have none
+/// - Names made only of dots (`.`, `..`, `...`) have no extension
+/// - Bytes that aren't valid UTF-8 are replaced by U+FFFD, or escaped with `escape`, like in the
+///   other group keys
+#[inline(always)]
+pub fn push_ext(path: &Path, key: &mut String, escape: bool) {
+    let name = name_str(path.file_name().unwrap_or_default(), escape);
+    if let Some((stem, ext)) = name.rsplit_once('.') {
+        if stem.trim_start_matches('.').is_empty() {
+            return;
+        }
+        if ext.is_ascii() {
+            let start = key.len();
+            key.push_str(ext);
+            key[start..].make_ascii_lowercase();
+        } else {
+            key.extend(ext.chars().flat_map(char::to_lowercase));
+        }
+    }
+}
+
+/// Append the whole name of a dotfile without any other dot, like `.bashrc`, to `key`. Returns
+/// false, leaving `key` untouched, for any other name.
+#[inline(always)]
+fn push_dotfile_name(path: &Path, key: &mut String, escape: bool) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name_str(name, escape);
+    match name.strip_prefix('.') {
+        Some(rest) if !rest.is_empty() && !rest.contains('.') => {
+            key.push_str(&name);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[inline(always)]
+fn push_filename(path: &Path, key: &mut String, escape: bool) {
+    key.push_str(&name_str(path.file_name().unwrap_or_default(), escape));
+}
+
+#[inline(always)]
+fn push_dir_name(dir: &Path, key: &mut String, escape: bool) {
+    key.push_str(&name_str(dir.file_name().unwrap_or_default(), escape));
+}
+
+/// Push the whole path of `dir`
+fn push_dir_path(dir: &Path, key: &mut String, escape: bool) {
+    let dir = long_path::strip_extended(dir);
+    key.push_str(&name_str(dir.as_os_str(), escape));
+}
+
+/// The innermost root `dir` is in
+fn root_of<'a>(dir: &Path, roots: &'a [PathBuf]) -> Option<&'a Path> {
+    root_index(dir, roots).map(|i| roots[i].as_path())
+}
+
+/// The index of the innermost root `path` is in
+fn root_index(path: &Path, roots: &[PathBuf]) -> Option<usize> {
+    (roots.iter().enumerate())
+        .filter(|(_i, root)| path.starts_with(root))
+        .max_by_key(|(_i, root)| root.as_os_str().len())
+        .map(|(i, _root)| i)
+}
+
+/// Push `dir`, spelled from the name of the root it was found in so that `project/src` and
+/// `other/src` are apart, and return how many levels below that root it is. Directories below
+/// no root, as read from an inventory, are pushed whole.
+fn push_relative_dir(dir: &Path, roots: &[PathBuf], key: &mut String, escape: bool) -> u32 {
+    let (mut label, below) = match root_of(dir, roots) {
+        // A root without a name, like `/`, is spelled whole
+        Some(root) => match root.file_name() {
+            Some(name) => (PathBuf::from(name), dir.strip_prefix(root).unwrap_or(dir)),
+            None => (
+                long_path::strip_extended(root).into_owned(),
+                dir.strip_prefix(root).unwrap_or(dir),
+            ),
+        },
+        None => (PathBuf::new(), dir),
+    };
+    let mut depth = 0;
+    for component in below.components() {
+        if matches!(component, Component::Normal(_)) {
+            depth += 1;
+        }
+        label.push(component);
+    }
+    key.push_str(&name_str(label.as_os_str(), escape));
+    depth
+}
+
+/// `path` resolved to an absolute path without links, or joined to the current directory when
+/// it can't be resolved
+fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    })
+}
+
+/// `path` spelled from the absolute spelling of the root it was found in. Paths below no root,
+/// as read from an inventory, are joined to `cwd`, the current directory, unless already
+/// absolute.
+fn to_absolute(path: &Path, roots: &[PathBuf], absolute_roots: &[PathBuf], cwd: &Path) -> PathBuf {
+    let root = (roots.iter().zip(absolute_roots))
+        .filter(|(root, _absolute)| path.starts_with(root))
+        .max_by_key(|(root, _absolute)| root.as_os_str().len());
+    match root {
+        // Joining nothing would add a trailing separator
+        Some((root, absolute)) if path == root => absolute.clone(),
+        Some((root, absolute)) => absolute.join(path.strip_prefix(root).unwrap_or(path)),
+        None if path.is_absolute() => path.to_path_buf(),
+        None => cwd.join(path),
+    }
+}
+
+/// Push `dir`, or its ancestor `depth` levels below the root it was found in, spelled starting
+/// with that root like `du` does. Directories below no root, as read from an inventory, count
+/// their levels from the start.
+fn push_ancestor(dir: &Path, roots: &[PathBuf], depth: usize, key: &mut String, escape: bool) {
+    let root = root_of(dir, roots).unwrap_or(Path::new(""));
+    let mut ancestor = root.to_path_buf();
+    let mut levels = 0;
+    for component in dir.strip_prefix(root).unwrap_or(dir).components() {
+        if levels == depth {
+            break;
+        }
+        if matches!(component, Component::Normal(_)) {
+            levels += 1;
+        }
+        ancestor.push(component);
+    }
+    let ancestor = long_path::strip_extended(&ancestor);
+    key.push_str(&name_str(ancestor.as_os_str(), escape));
+}
+
+/// `name` as text. Bytes that aren't valid UTF-8 are replaced by U+FFFD, which can merge distinct
+/// names. With `escape` they are written as `\xNN` instead (unpaired surrogates as `\u{NNNN}` on
+/// Windows) and backslashes are doubled, so that every name gets its own key.
+#[inline(always)]
+fn name_str(name: &OsStr, escape: bool) -> Cow<'_, str> {
+    match name.to_str() {
+        Some(name) if !(escape && name.contains('\\')) => Cow::Borrowed(name),
+        _ if escape => Cow::Owned(escape_name(name)),
+        _ => name.to_string_lossy(),
+    }
+}
+
+/// The extension of `path`, and the types it maps to with `-g type` and `-g type2`
+pub fn classify(path: &Path) -> (String, FileType, FineType) {
+    let mut ext = String::new();
+    push_ext(path, &mut ext, false);
+    let file_type = FileType::get_filetype(&ext);
+    let fine_type = FineType::get_finetype(&ext);
+    (ext, file_type, fine_type)
+}
+
+/// `path` as text, with its bytes that aren't valid UTF-8 escaped like [`name_str`] does, so
+/// that no two paths look the same
+pub fn path_str(path: &Path) -> Cow<'_, str> {
+    name_str(path.as_os_str(), path.to_str().is_none())
+}
+
+#[cfg(unix)]
+fn escape_name(name: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut escaped = String::with_capacity(name.len());
+    for chunk in name.as_bytes().utf8_chunks() {
+        escaped.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            let _ = write!(escaped, "\\x{byte:02X}");
+        }
+    }
+    escaped
+}
+
+#[cfg(windows)]
+fn escape_name(name: &OsStr) -> String {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut escaped = String::with_capacity(name.len());
+    for c in char::decode_utf16(name.encode_wide()) {
+        match c {
+            Ok('\\') => escaped.push_str("\\\\"),
+            Ok(c) => escaped.push(c),
+            Err(err) => {
+                let _ = write!(escaped, "\\u{{{:04X}}}", err.unpaired_surrogate());
+            }
+        }
+    }
+    escaped
+}
+
+/// Add `size` to the group `key`. Looking the key up by reference only copies it for new
+/// groups. Returns false if the size of the group saturated.
+#[inline(always)]
+fn add_to_group(sizes: &mut GroupMap<u64>, key: &str, size: u64) -> bool {
+    match sizes.get_mut(key) {
+        Some(group_size) => saturating_add(group_size, size),
+        None => {
+            sizes.insert(key, size);
+            true
+        }
+    }
+}
+
+/// Returns false if one of the sizes of the group saturated
+fn add_usage_to_group(sizes: &mut GroupMap<Usage>, key: &str, usage: Usage) -> bool {
+    match sizes.get_mut(key) {
+        Some(group) => {
+            saturating_add(&mut group.apparent, usage.apparent)
+                & saturating_add(&mut group.allocated, usage.allocated)
+        }
+        None => {
+            sizes.insert(key, usage);
+            true
+        }
+    }
+}
+
+/// Add `n` to `sum`, stopping at `u64::MAX` instead of wrapping around. Returns false if it
+/// saturated.
+fn saturating_add(sum: &mut u64, n: u64) -> bool {
+    match sum.checked_add(n) {
+        Some(new) => {
+            *sum = new;
+            true
+        }
+        None => {
+            *sum = u64::MAX;
+            false
+        }
+    }
+}
+
+/// The group of the files whose own group came after `--max-groups` groups. The parentheses keep
+/// it apart from real groups such as the `Other` file type.
+pub const OTHER_GROUPS: &str = "(other groups)";
+
+/// The group of the sizes of directories themselves, only counted with `--du-compat`
+pub const DIRECTORIES: &str = "(directories)";
+
+/// A file found by a walk or read from an inventory
+#[derive(Debug)]
+pub struct FileEntry {
+    /// Identifies hardlinks to the same file, `None` if unknown
+    pub unique_id: Option<UniqueID>,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Last modification time, `None` if the filesystem does not record it
+    pub modified: Option<SystemTime>,
+    /// Creation time, `None` if the platform or the filesystem does not record it
+    pub created: Option<SystemTime>,
+    /// Bytes stored in extents shared with other files (reflinks), `None` if unknown
+    pub shared: Option<u64>,
+    /// Bytes stored beside the data of the file, in extended attributes on macOS or alternate
+    /// data streams on Windows. Already included in `size`.
+    pub streams: u64,
+    /// Both the apparent size and the disk usage, `None` if not measured
+    pub usage: Option<Usage>,
+    /// The entry is a directory, whose own size is counted but which is not a file
+    pub is_dir: bool,
+    /// What the current user may do with it, only worked out for `GroupBy::Access`
+    pub access: Access,
+}
+
+impl FileEntry {
+    /// An entry with nothing more known than its path and size
+    pub fn new(path: PathBuf, size: u64) -> Self {
+        FileEntry {
+            unique_id: None,
+            path,
+            size,
+            modified: None,
+            created: None,
+            shared: None,
+            streams: 0,
+            usage: None,
+            is_dir: false,
+            access: Access::Unknown,
+        }
+    }
+}
+
+/// Everything gathered by a scan, ready to be printed.
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    /// How the files were grouped
+    pub group_by: GroupBy,
+    /// The groups of the other `--group-by`, made from the same files. Only the groups, the
+    /// totals and the counts are filled.
+    pub sections: Vec<ScanResult>,
+    /// Sum of the sizes of all counted files
+    pub total: u64,
+    /// Number of counted files (hardlinks to the same file are counted once)
+    pub file_count: u64,
+    /// Size of each group
+    pub sizes: GroupMap<u64>,
+    /// Number of files of each group, only counted for `--top-by count:N`
+    pub file_counts: GroupMap<u64>,
+    /// How many levels below its root each group is, with `-g path`
+    pub depths: GroupMap<u32>,
+    /// The errors, unless they went to `--error-log`
+    pub errors: Vec<Error>,
+    /// Errors grouped by kind, in order of first appearance
+    pub error_summary: Vec<ErrorSummary>,
+    /// Why writing to `--error-log` failed
+    pub error_log_failure: Option<std::io::Error>,
+    /// Number of inputs that could not be read at all
+    pub unreadable_roots: usize,
+    /// Number of entries that disappeared while the walk was running
+    pub vanished: u64,
+    /// Number of hardlinks skipped because their file had already been counted
+    pub deduplicated: u64,
+    /// Number of threads used for the walk
+    pub threads: usize,
+    /// With `--dedupe-extents`, the part of each group stored in shared extents
+    pub shared_sizes: GroupMap<u64>,
+    pub shared_total: u64,
+    /// With `--dedupe-extents`, files whose extents could not be read
+    pub unknown_extents: u64,
+    /// With `--include-xattrs` or `--include-ads`, the part of the total stored beside the data
+    /// of the files
+    pub stream_total: u64,
+    /// With `--stats`, the apparent size and the disk usage of all the files whose both sizes
+    /// are known, and how many of them take less space than their length
+    pub apparent_total: u64,
+    pub allocated_total: u64,
+    pub sparse_files: u64,
+    /// Both sizes of each group, for the groups whose files had both sizes measured
+    pub usage_sizes: GroupMap<Usage>,
+    /// Whether the walk was stopped before reaching every file
+    pub partial: bool,
+    /// Whether it was stopped by Ctrl-C
+    pub interrupted: bool,
+    /// With `--sample`, how the files were sampled. The sizes are then estimates scaled up
+    /// from the sampled files, while `file_count` is the number of sampled files.
+    pub sampler: Option<Sampler>,
+    /// Whether a sum exceeded `u64::MAX` and was capped, the sizes are then lower bounds
+    pub saturated: bool,
+    /// FIFOs, sockets and devices found by the walk, skipped with `--regular-only`
+    pub special_files: u64,
+    /// With `--compare`, the size of each group in the saved scan
+    pub baseline: Option<GroupMap<u64>>,
+    /// With `--list-files`, the files kept, smallest first
+    pub listed: Vec<ListedFile>,
+    /// With `--duplicates`, every file, to be compared
+    pub candidates: Vec<Candidate>,
+    /// With `--duplicates`, the files found to be identical
+    pub duplicates: Option<Duplicates>,
+    /// With `--per-root`, the size of each root. A file reached from several roots through
+    /// hardlinks is counted in the first of them in the order of the roots, so that they add up
+    /// to the total.
+    pub root_sizes: Vec<(PathBuf, u64)>,
+}
+
+/// A file kept by `--list-files`, with the group it was added to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListedFile {
+    pub size: u64,
+    pub path: PathBuf,
+    pub group: String,
+}
+
+/// Which files to keep for `--list-files`
+#[derive(Debug, Clone, Default)]
+pub struct FileList {
+    /// Keep only the largest files, this many
+    pub top: Option<usize>,
+    /// Keep only the files whose size is within all of these
+    pub size: Vec<SizeFilter>,
+    /// Keep only the first files found, this many, and ask the walk to stop then
+    pub limit: Option<u64>,
+}
+
+/// How many errors of one kind happened during a scan
+#[derive(Debug)]
+pub struct ErrorSummary {
+    pub kind: ErrorKind,
+    pub count: u64,
+    /// Path of the first error of this kind
+    pub first: PathBuf,
+}
+
+impl ScanResult {
+    /// How many errors happened, including the ones that only went to `--error-log`
+    pub fn error_count(&self) -> u64 {
+        self.error_summary.iter().map(|summary| summary.count).sum()
+    }
+}
+
+/// Initial capacity of the set of hardlinked files already counted. Unless the walk is told to
+/// track every file, only files with more than one link end up in there, so this is usually
+/// plenty and avoids the first few rehashes.
+const ID_SET_CAPACITY: usize = 1024;
+
+/// Folds the files found by a walk (or read from an inventory) into a [`ScanResult`]
+///
+/// The group map and the set of IDs hash with FNV like the other maps of the crate, not with
+/// the default SipHash, though the walk costs too much for the benchmarks to see a difference.
+/// FNV gives no protection against hash flooding: someone able to create many file names that
+/// collide could slow a scan down. They would need write access to the scanned tree, where they
+/// can already slow it down by creating lots of files.
+pub struct Aggregator {
+    group_by: GroupBy,
+    dedup: bool,
+    dotfile_as_ext: bool,
+    normalize_ext: bool,
+    escape_nonutf8: bool,
+    block_size: Option<u64>,
+    dedupe_extents: bool,
+    sampler: Option<Sampler>,
+    max_groups: Option<usize>,
+    count_files: bool,
+    sizes_as_counts: bool,
+    group_dirs: bool,
+    flat_depth: Option<usize>,
+    split_outliers: Option<u64>,
+    absolute: bool,
+    per_root: bool,
+    /// The roots spelled as absolute paths, in the same order, with `absolute`
+    absolute_roots: Vec<PathBuf>,
+    /// With `absolute`, the current directory resolved once, for the paths below no root
+    cwd: PathBuf,
+    /// Groups made by `split_outliers`, which don't count against `max_groups`
+    outlier_groups: usize,
+    list_files: Option<FileList>,
+    find_duplicates: bool,
+    error_log: Option<ErrorLog>,
+    /// Aggregators grouping the same files differently
+    sections: Vec<Aggregator>,
+    /// The files kept for `list_files`, a min-heap so that the smallest is dropped first
+    listed: BinaryHeap<Reverse<ListedFile>>,
+    /// Set once the `limit` of `list_files` is reached, later files are ignored and make the
+    /// results partial
+    done: Arc<AtomicBool>,
+    roots: Vec<PathBuf>,
+    ids: FnvHashSet<UniqueID>,
+    /// With `per_root`, the files with hardlinks, held until the end of the scan in the link of
+    /// the first root reaching them
+    held: FnvHashMap<UniqueID, FileEntry>,
+    /// Reference for the age of files, so that the buckets don't shift during a long scan
+    now: SystemTime,
+    /// Reused to build the group key of each file, so that only new groups allocate
+    key: String,
+    result: ScanResult,
+}
+
+impl Aggregator {
+    pub fn new(group_by: GroupBy) -> Self {
+        let mut result = ScanResult {
+            group_by,
+            ..Default::default()
+        };
+        result.sizes.reserve(group_by.expected_groups());
+
+        Aggregator {
+            group_by,
+            dedup: true,
+            dotfile_as_ext: false,
+            normalize_ext: false,
+            escape_nonutf8: false,
+            block_size: None,
+            dedupe_extents: false,
+            sampler: None,
+            max_groups: None,
+            count_files: false,
+            sizes_as_counts: false,
+            group_dirs: false,
+            flat_depth: None,
+            split_outliers: None,
+            absolute: false,
+            per_root: false,
+            absolute_roots: Vec::new(),
+            cwd: PathBuf::new(),
+            outlier_groups: 0,
+            list_files: None,
+            find_duplicates: false,
+            error_log: None,
+            sections: Vec::new(),
+            listed: BinaryHeap::new(),
+            done: Arc::new(AtomicBool::new(false)),
+            roots: Vec::new(),
+            ids: FnvHashSet::with_capacity_and_hasher(ID_SET_CAPACITY, Default::default()),
+            held: FnvHashMap::default(),
+            now: SystemTime::now(),
+            key: String::new(),
+            result,
+        }
+    }
+
+    /// Count files with several hardlinks only once. Enabled by default.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Group dotfiles like `.bashrc` under their whole name instead of with the files without
+    /// extension
+    pub fn dotfile_as_ext(mut self, dotfile_as_ext: bool) -> Self {
+        self.dotfile_as_ext = dotfile_as_ext;
+        self
+    }
+
+    /// Merge the groups of extensions that are other spellings of a same one, like `jpeg` and
+    /// `jpg`
+    pub fn normalize_ext(mut self, normalize_ext: bool) -> Self {
+        self.normalize_ext = normalize_ext;
+        self
+    }
+
+    /// Write the bytes of names that aren't valid UTF-8 as `\xNN` instead of replacing them, so
+    /// that distinct names don't share a group
+    pub fn escape_nonutf8(mut self, escape_nonutf8: bool) -> Self {
+        self.escape_nonutf8 = escape_nonutf8;
+        self
+    }
+
+    /// Round the size of every file up to a multiple of `block_size`
+    pub fn block_size(mut self, block_size: Option<u64>) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Once this many groups exist, add the files of new groups to [`OTHER_GROUPS`] instead
+    pub fn max_groups(mut self, max_groups: Option<usize>) -> Self {
+        self.max_groups = max_groups;
+        self
+    }
+
+    /// When grouping by directory or path, spell the groups as absolute paths. Set before the
+    /// roots, which are resolved once.
+    pub fn absolute(mut self, absolute: bool) -> Self {
+        self.absolute = absolute;
+        if absolute {
+            self.cwd = absolute_path(Path::new("."));
+        }
+        self
+    }
+
+    /// Also sum the size of each root. Set before the roots.
+    pub fn per_root(mut self, per_root: bool) -> Self {
+        self.per_root = per_root;
+        self
+    }
+
+    /// When grouping by directory, add every file to its ancestor this many levels below its root
+    /// instead of to its parent
+    pub fn flat_depth(mut self, flat_depth: Option<usize>) -> Self {
+        self.flat_depth = flat_depth;
+        self
+    }
+
+    /// Give every file of at least this size a group of its own, named after its group and its
+    /// path, e.g. `Video: /home/me/movie.mkv`
+    pub fn split_outliers(mut self, split_outliers: Option<u64>) -> Self {
+        self.split_outliers = split_outliers;
+        self
+    }
+
+    /// Keep the files themselves, not only the size of their group. With a `top`, only that many
+    /// are held at any time.
+    pub fn list_files(mut self, list_files: Option<FileList>) -> Self {
+        self.list_files = list_files;
+        self
+    }
+
+    /// Set once the aggregator needs no more files, for the walk to stop early
+    pub fn done_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.done)
+    }
+
+    /// Keep every file, with the length of its contents, to look for duplicates once the scan
+    /// is over
+    pub fn find_duplicates(mut self, find_duplicates: bool) -> Self {
+        self.find_duplicates = find_duplicates;
+        self
+    }
+
+    /// Also add every file to these aggregators, which group them differently. Hardlinks are
+    /// only told apart by this one.
+    pub fn sections(mut self, sections: Vec<Aggregator>) -> Self {
+        self.sections = sections;
+        self
+    }
+
+    /// Write the errors to this log as they come instead of keeping them
+    pub fn error_log(mut self, error_log: Option<ErrorLog>) -> Self {
+        self.error_log = error_log;
+        self
+    }
+
+    /// Count the files of each group, not only their size
+    pub fn count_files(mut self, count_files: bool) -> Self {
+        self.count_files = count_files;
+        self
+    }
+
+    /// Count every file and directory as 1 instead of its size, so that the groups and the total
+    /// are numbers of files, or of inodes when the walk sends the directories too
+    pub fn sizes_as_counts(mut self, sizes_as_counts: bool) -> Self {
+        self.sizes_as_counts = sizes_as_counts;
+        self
+    }
+
+    /// Group each directory sent by the walk on its own with `GroupBy::Directory` and
+    /// `GroupBy::Path`, instead of in `(directories)` like with the other groupings
+    pub fn group_dirs(mut self, group_dirs: bool) -> Self {
+        self.group_dirs = group_dirs;
+        self
+    }
+
+    /// Keep track of the data stored in extents shared between files
+    pub fn dedupe_extents(mut self, dedupe_extents: bool) -> Self {
+        self.dedupe_extents = dedupe_extents;
+        self
+    }
+
+    pub fn is_dedup(&self) -> bool {
+        self.dedup
+    }
+
+    pub fn is_dedupe_extents(&self) -> bool {
+        self.dedupe_extents
+    }
+
+    /// Whether this aggregator or one of its sections groups the files by `group_by`
+    pub fn groups_by(&self, group_by: GroupBy) -> bool {
+        self.group_by == group_by || self.sections.iter().any(|s| s.groups_by(group_by))
+    }
+
+    /// The inputs of the scan. A missing root is reported as an error, while any other missing
+    /// entry is assumed to have been deleted during the scan.
+    pub fn roots(mut self, roots: Vec<PathBuf>) -> Self {
+        self.sections = (self.sections.into_iter())
+            .map(|section| section.roots(roots.clone()))
+            .collect();
+        if self.absolute {
+            self.absolute_roots = roots.iter().map(|root| absolute_path(root)).collect();
+        }
+        if self.per_root {
+            self.result.root_sizes = roots.iter().map(|root| (root.clone(), 0)).collect();
+        }
+        self.roots = roots;
+        self
+    }
+
+    pub fn add_file(&mut self, entry: FileEntry) {
+        // The walk may still send the files it was reading when it was asked to stop, which are
+        // left out of the results
+        if self.done.load(Ordering::Relaxed) {
+            self.result.partial = true;
+            return;
+        }
+        if entry.unique_id.is_some() && self.dedup && self.per_root {
+            self.hold(entry);
+            return;
+        }
+        if let Some(unique_id) = &entry.unique_id
+            && self.dedup
+        {
+            // Only count this entry if the ID has not been seen
+            if self.ids.contains(unique_id) {
+                self.result.deduplicated += 1;
+                return;
+            }
+        }
+        self.add_new_file(entry);
+    }
+
+    /// Hold `entry`, a file with hardlinks, until the end of the scan, in place of the link held
+    /// already if its root comes first among the roots. Whatever the order the threads find the
+    /// links in, the file is then counted in the same root and in the same group.
+    fn hold(&mut self, entry: FileEntry) {
+        let Some(unique_id) = &entry.unique_id else {
+            return;
+        };
+        let root = |entry: &FileEntry| root_index(&entry.path, &self.roots).unwrap_or(usize::MAX);
+        match self.held.get(unique_id) {
+            Some(held) => {
+                self.result.deduplicated += 1;
+                if root(&entry) < root(held) {
+                    self.held.insert(unique_id.clone(), entry);
+                }
+            }
+            None => {
+                self.held.insert(unique_id.clone(), entry);
+            }
+        }
+    }
+
+    /// Count `entry`, a file not counted yet
+    fn add_new_file(&mut self, entry: FileEntry) {
+        for section in &mut self.sections {
+            section.add_file(FileEntry {
+                unique_id: None,
+                path: entry.path.clone(),
+                ..entry
+            });
+        }
+
+        let FileEntry {
+            unique_id,
+            path,
+            size,
+            modified,
+            created,
+            shared,
+            streams,
+            usage,
+            is_dir,
+            access,
+        } = entry;
+        let unique_id = unique_id.filter(|_| self.dedup);
+
+        let (size, streams) = if self.sizes_as_counts {
+            (1, 0)
+        } else {
+            match self.block_size {
+                Some(block_size) => (round_up(size, block_size), streams),
+                None => (size, streams),
+            }
+        };
+        let mut fits = saturating_add(&mut self.result.total, size);
+        if self.per_root
+            && let Some(i) = root_index(&path, &self.roots)
+        {
+            fits &= saturating_add(&mut self.result.root_sizes[i].1, size);
+        }
+        if let Some(unique_id) = unique_id {
+            self.ids.insert(unique_id);
+        }
+        fits &= saturating_add(&mut self.result.stream_total, streams);
+        if !is_dir {
+            self.result.file_count += 1;
+        }
+        if let Some(usage) = usage {
+            fits &= saturating_add(&mut self.result.apparent_total, usage.apparent);
+            fits &= saturating_add(&mut self.result.allocated_total, usage.allocated);
+            if usage.is_sparse() {
+                self.result.sparse_files += 1;
+            }
+        }
+
+        let mut key = std::mem::take(&mut self.key);
+        key.clear();
+        let mut depth = None;
+        let own_group = is_dir
+            && self.group_dirs
+            && matches!(self.group_by, GroupBy::Directory | GroupBy::Path);
+        // The directory grouping a file, or the directory itself in its own group
+        let dir = if own_group {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(Path::new(""))
+        };
+        match self.group_by {
+            _ if is_dir && !own_group => key.push_str(DIRECTORIES),
+            GroupBy::Type => {
+                push_ext(&path, &mut key, false);
+                let file_type = FileType::get_filetype(&key);
+                key.clear();
+                let _ = write!(key, "{file_type}");
+            }
+            GroupBy::FineType => {
+                push_ext(&path, &mut key, false);
+                let fine_type = FineType::get_finetype(&key);
+                key.clear();
+                let _ = write!(key, "{fine_type}");
+            }
+            GroupBy::Extension => {
+                if !(self.dotfile_as_ext && push_dotfile_name(&path, &mut key, self.escape_nonutf8))
+                {
+                    push_ext(&path, &mut key, self.escape_nonutf8);
+                    if self.normalize_ext
+                        && let Some(canonical) = canonical_ext(&key)
+                    {
+                        key.clear();
+                        key.push_str(canonical);
+                    }
+                }
+            }
+            GroupBy::FileName => push_filename(&path, &mut key, self.escape_nonutf8),
+            GroupBy::Directory if self.absolute => {
+                let dir = to_absolute(dir, &self.roots, &self.absolute_roots, &self.cwd);
+                match self.flat_depth {
+                    Some(depth) => push_ancestor(
+                        &dir,
+                        &self.absolute_roots,
+                        depth,
+                        &mut key,
+                        self.escape_nonutf8,
+                    ),
+                    None => push_dir_path(&dir, &mut key, self.escape_nonutf8),
+                }
+            }
+            GroupBy::Directory => match self.flat_depth {
+                Some(depth) => {
+                    push_ancestor(dir, &self.roots, depth, &mut key, self.escape_nonutf8)
+                }
+                None => push_dir_name(dir, &mut key, self.escape_nonutf8),
+            },
+            GroupBy::Path => {
+                depth = Some(push_relative_dir(
+                    dir,
+                    &self.roots,
+                    &mut key,
+                    self.escape_nonutf8,
+                ));
+                if self.absolute {
+                    key.clear();
+                    let dir = to_absolute(dir, &self.roots, &self.absolute_roots, &self.cwd);
+                    push_dir_path(&dir, &mut key, self.escape_nonutf8);
+                }
+            }
+            GroupBy::AgeBucket => {
+                // A modification time in the future is an age of zero
+                let age = modified.map(|m| self.now.duration_since(m).unwrap_or_default());
+                let _ = write!(key, "{}", AgeBucket::from_age(age));
+            }
+            GroupBy::CreatedYear | GroupBy::CreatedMonth => match created.map(year_month) {
+                Some((year, month)) if self.group_by == GroupBy::CreatedMonth => {
+                    let _ = write!(key, "{year}-{month:02}");
+                }
+                Some((year, _month)) => {
+                    let _ = write!(key, "{year}");
+                }
+                None => {
+                    let _ = write!(key, "{}", AgeBucket::Unknown);
+                }
+            },
+            GroupBy::Access => {
+                let _ = write!(key, "{access}");
+            }
+        }
+
+        let is_outlier = self
+            .split_outliers
+            .is_some_and(|threshold| !is_dir && size >= threshold);
+        if is_outlier {
+            key.push_str(": ");
+            let path = long_path::strip_extended(&path);
+            key.push_str(&name_str(path.as_os_str(), self.escape_nonutf8));
+            depth = None;
+            if !self.result.sizes.contains_key(&key) {
+                self.outlier_groups += 1;
+            }
+        } else if let Some(max_groups) = self.max_groups {
+            // Only the groups seen first get their own entry, whatever their size. The few
+            // outliers are kept apart, they are what was asked for.
+            let groups = self.result.sizes.len() - self.outlier_groups;
+            if groups >= max_groups && !self.result.sizes.contains_key(&key) {
+                key.clear();
+                key.push_str(OTHER_GROUPS);
+                depth = None;
+            }
+        }
+        fits &= add_to_group(&mut self.result.sizes, &key, size);
+        if let Some(depth) = depth
+            && !self.result.depths.contains_key(&key)
+        {
+            self.result.depths.insert(&key, depth);
+        }
+        if self.count_files && !is_dir {
+            add_to_group(&mut self.result.file_counts, &key, 1);
+        }
+        if self.dedupe_extents {
+            match shared {
+                Some(shared) => {
+                    let shared = shared.min(size);
+                    fits &= saturating_add(&mut self.result.shared_total, shared);
+                    fits &= add_to_group(&mut self.result.shared_sizes, &key, shared);
+                }
+                None => self.result.unknown_extents += 1,
+            }
+        }
+        if let Some(usage) = usage {
+            fits &= add_usage_to_group(&mut self.result.usage_sizes, &key, usage);
+        }
+        self.result.saturated |= !fits;
+        if self.find_duplicates && !is_dir {
+            self.result.candidates.push(Candidate {
+                len: usage.map_or(size, |usage| usage.apparent),
+                size,
+                path: path.clone(),
+                group: key.clone(),
+            });
+        }
+        if let Some(list) = &self.list_files
+            && !is_dir
+            && list.size.iter().all(|filter| filter.is_within(size))
+        {
+            let smallest_kept = self.listed.peek().map(|Reverse(file)| file.size);
+            let is_full = list.top.is_some_and(|top| self.listed.len() >= top);
+            // Smaller than every file kept, no need to copy its path
+            if !(is_full && smallest_kept.is_some_and(|smallest| size < smallest)) {
+                self.listed.push(Reverse(ListedFile {
+                    size,
+                    path,
+                    group: key.clone(),
+                }));
+                if is_full {
+                    self.listed.pop();
+                }
+            }
+            if list
+                .limit
+                .is_some_and(|limit| self.listed.len() as u64 >= limit)
+            {
+                self.done.store(true, Ordering::Relaxed);
+            }
+        }
+        self.key = key;
+    }
+
+    pub fn add_error(&mut self, error: Error) {
+        if let Some(log) = &mut self.error_log {
+            log.log(&error);
+        }
+        // Files deleted while we scan are expected on a live filesystem,
+        // but a missing input is a real error
+        let is_root = self.roots.iter().any(|r| r == error.path());
+        if error.kind() == ErrorKind::NotFound && !is_root {
+            self.result.vanished += 1;
+        } else {
+            if is_root && matches!(error, Error::NoMetadataForPath(..)) {
+                self.result.unreadable_roots += 1;
+            }
+            let kind = error.kind();
+            match self
+                .result
+                .error_summary
+                .iter_mut()
+                .find(|s| s.kind == kind)
+            {
+                Some(summary) => summary.count += 1,
+                None => self.result.error_summary.push(ErrorSummary {
+                    kind,
+                    count: 1,
+                    first: error.path().to_path_buf(),
+                }),
+            }
+            if self.error_log.is_none() {
+                self.result.errors.push(error);
+            }
+        }
+    }
+
+    /// Only a fraction of the files are added, scale the sums up when finishing
+    pub fn sampler(mut self, sampler: Option<Sampler>) -> Self {
+        self.sections = (self.sections.into_iter())
+            .map(|section| section.sampler(sampler))
+            .collect();
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn finish(mut self) -> ScanResult {
+        // In the order of their paths, for the groups seen first to be the same every time
+        let mut held: Vec<FileEntry> = std::mem::take(&mut self.held).into_values().collect();
+        held.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        for entry in held {
+            if self.done.load(Ordering::Relaxed) {
+                self.result.partial = true;
+                break;
+            }
+            self.add_new_file(entry);
+        }
+        self.result.sections = std::mem::take(&mut self.sections)
+            .into_iter()
+            .map(Aggregator::finish)
+            .collect();
+        if let Some(log) = self.error_log.take() {
+            self.result.error_log_failure = log.finish().err();
+        }
+        // The heap of `Reverse` sorts the largest first, reversed again they are smallest first
+        self.result.listed = std::mem::take(&mut self.listed)
+            .into_sorted_vec()
+            .into_iter()
+            .rev()
+            .map(|Reverse(file)| file)
+            .collect();
+        if let Some(sampler) = self.sampler {
+            let result = &mut self.result;
+            for sum in [
+                &mut result.total,
+                &mut result.shared_total,
+                &mut result.stream_total,
+                &mut result.apparent_total,
+                &mut result.allocated_total,
+            ] {
+                *sum = sampler.scale(*sum);
+            }
+            for size in result
+                .sizes
+                .values_mut()
+                .chain(result.shared_sizes.values_mut())
+            {
+                *size = sampler.scale(*size);
+            }
+            for count in result.file_counts.values_mut() {
+                *count = sampler.scale(*count);
+            }
+            for (_root, size) in &mut result.root_sizes {
+                *size = sampler.scale(*size);
+            }
+            for usage in result.usage_sizes.values_mut() {
+                usage.apparent = sampler.scale(usage.apparent);
+                usage.allocated = sampler.scale(usage.allocated);
+            }
+            result.sampler = Some(sampler);
+        }
+        self.result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_ext(path: &Path) -> String {
+        let mut key = String::new();
+        push_ext(path, &mut key, false);
+        key
+    }
+
+    #[test]
+    fn extension_rules() {
+        let ext = |name: &str| get_ext(Path::new(name));
+
+        assert_eq!(ext("photo.JPG"), "jpg");
+        assert_eq!(ext("dir/archive.tar.gz"), "gz");
+        assert_eq!(ext(".config.yaml"), "yaml");
+        assert_eq!(ext("Makefile"), "");
+        assert_eq!(ext(".gitignore"), "");
+        assert_eq!(ext("foo."), "");
+        assert_eq!(ext("archive.tar."), "");
+        assert_eq!(ext("."), "");
+        assert_eq!(ext(".."), "");
+        assert_eq!(ext("..."), "");
+        assert_eq!(ext("..hidden"), "");
+        assert_eq!(ext("FOTO.ÄPF"), "äpf");
+        assert_eq!(ext("文档.文本"), "文本");
+    }
+
+    #[test]
+    fn directories_can_be_spelled_absolute() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        fs::create_dir_all(root.join("output")).unwrap();
+        let canonical = root.canonicalize().unwrap();
+        let missing = PathBuf::from("no/such/root");
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+
+        for group_by in [GroupBy::Directory, GroupBy::Path] {
+            let mut aggregator = Aggregator::new(group_by)
+                .absolute(true)
+                .roots(vec![root.clone(), missing.clone()]);
+            aggregator.add_file(FileEntry::new(root.join("output/a.bin"), 10));
+            aggregator.add_file(FileEntry::new(missing.join("b.bin"), 5));
+            let result = aggregator.finish();
+            let mut keys: Vec<String> = result.sizes.into_iter().map(|(k, _v)| k).collect();
+            keys.sort();
+            let mut expected = [
+                canonical.join("output").display().to_string(),
+                cwd.join(&missing).display().to_string(),
+            ];
+            expected.sort();
+            assert_eq!(keys, expected, "{group_by:?}");
+        }
+
+        let mut aggregator = Aggregator::new(GroupBy::Directory)
+            .absolute(true)
+            .flat_depth(Some(0))
+            .roots(vec![root.clone()]);
+        aggregator.add_file(FileEntry::new(root.join("output/a.bin"), 10));
+        let keys: Vec<String> = aggregator
+            .finish()
+            .sizes
+            .into_iter()
+            .map(|(k, _v)| k)
+            .collect();
+        assert_eq!(keys, [canonical.display().to_string()]);
+
+        // Without roots, as read from an inventory, paths start from the current directory
+        let mut aggregator = Aggregator::new(GroupBy::Directory).absolute(true);
+        aggregator.add_file(FileEntry::new(PathBuf::from("photos/a.jpg"), 10));
+        assert!(
+            (aggregator.finish().sizes).contains_key(&cwd.join("photos").display().to_string())
+        );
+
+        // Other groupings are left alone
+        let mut aggregator = Aggregator::new(GroupBy::Extension)
+            .absolute(true)
+            .roots(vec![root.clone()]);
+        aggregator.add_file(FileEntry::new(root.join("output/a.bin"), 10));
+        assert!(aggregator.finish().sizes.contains_key("bin"));
+    }
+
+    #[test]
+    fn sections_group_the_same_files() {
+        let mut aggregator = Aggregator::new(GroupBy::Extension)
+            .sections(vec![Aggregator::new(GroupBy::Type)])
+            .sampler(Some(Sampler { rate: 0.5, seed: 1 }));
+        for (name, size) in [("a.jpg", 10), ("b.png", 20), ("c.rs", 5)] {
+            aggregator.add_file(FileEntry::new(PathBuf::from(name), size));
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.group_by, GroupBy::Extension);
+        assert_eq!(result.sizes.len(), 3);
+        assert_eq!(result.sections.len(), 1);
+        let by_type = &result.sections[0];
+        assert_eq!(by_type.group_by, GroupBy::Type);
+        assert_eq!(by_type.sizes["Image"], 60);
+        assert_eq!(by_type.sizes["Code"], 10);
+        assert_eq!(by_type.total, result.total);
+        assert_eq!(by_type.file_count, 3);
+    }
+
+    #[test]
+    fn aliases_are_merged_only_when_normalizing() {
+        let keys = |normalize_ext| {
+            let mut aggregator = Aggregator::new(GroupBy::Extension).normalize_ext(normalize_ext);
+            for name in ["a.jpg", "b.JPEG", "c.jpeg", "d.png", "e.htm", "f.html"] {
+                aggregator.add_file(FileEntry::new(PathBuf::from(name), 1));
+            }
+            let mut keys: Vec<_> = aggregator.finish().sizes.into_iter().collect();
+            keys.sort();
+            keys
+        };
+        let group = |ext: &str, size| (ext.to_string(), size);
+        assert_eq!(
+            keys(false),
+            [
+                group("htm", 1),
+                group("html", 1),
+                group("jpeg", 2),
+                group("jpg", 1),
+                group("png", 1)
+            ]
+        );
+        assert_eq!(
+            keys(true),
+            [group("html", 2), group("jpg", 3), group("png", 1)]
+        );
+    }
+
+    #[test]
+    fn dotfiles_can_be_their_own_extension() {
+        let keys = |dotfile_as_ext| {
+            let mut aggregator = Aggregator::new(GroupBy::Extension).dotfile_as_ext(dotfile_as_ext);
+            for name in [
+                "home/.bashrc",
+                "home/.config.yaml",
+                "repo/.gitignore",
+                "Makefile",
+                "..",
+            ] {
+                aggregator.add_file(FileEntry::new(PathBuf::from(name), 1));
+            }
+            let mut keys: Vec<_> = aggregator.finish().sizes.into_iter().collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(false), [("".to_string(), 4), ("yaml".to_string(), 1)]);
+        assert_eq!(
+            keys(true),
+            [
+                ("".to_string(), 2),
+                (".bashrc".to_string(), 1),
+                (".gitignore".to_string(), 1),
+                ("yaml".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_are_rounded_to_the_block_size() {
+        let mut aggregator = Aggregator::new(GroupBy::Extension).block_size(Some(1 << 20));
+        for (name, size) in [
+            ("a.bin", 0),
+            ("b.bin", 1),
+            ("c.bin", 1 << 20),
+            ("d.txt", 3 << 19),
+        ] {
+            aggregator.add_file(FileEntry::new(PathBuf::from(name), size));
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.sizes["bin"], 2 << 20);
+        assert_eq!(result.sizes["txt"], 2 << 20);
+        assert_eq!(result.total, 4 << 20);
+    }
+
+    #[test]
+    fn sizes_can_be_numbers_of_files() {
+        let mut aggregator = Aggregator::new(GroupBy::Extension).sizes_as_counts(true);
+        for (name, size) in [("a.txt", 10), ("b.txt", 0), ("c.png", 1 << 30)] {
+            aggregator.add_file(FileEntry {
+                streams: 5,
+                ..FileEntry::new(PathBuf::from(name), size)
+            });
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.sizes["txt"], 2);
+        assert_eq!(result.sizes["png"], 1);
+        assert_eq!(result.total, 3);
+        assert_eq!(result.stream_total, 0);
+    }
+
+    #[test]
+    fn hardlinks_are_counted_in_the_first_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, [0; 100]).unwrap();
+        let unique_id = || {
+            let metadata = fs::metadata(&file).unwrap();
+            crate::unique_id::generate_unique_id(&file, &metadata, true)
+        };
+        let roots = vec![PathBuf::from("first"), PathBuf::from("second")];
+        let mut aggregator = Aggregator::new(GroupBy::Directory)
+            .per_root(true)
+            .roots(roots);
+        // The link in the second root is found before the one in the first
+        for (path, size) in [
+            ("second/link", 100),
+            ("first/other", 10),
+            ("first/link", 100),
+        ] {
+            aggregator.add_file(FileEntry {
+                unique_id: unique_id().filter(|_| size == 100),
+                ..FileEntry::new(PathBuf::from(path), size)
+            });
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.total, 110);
+        assert_eq!(result.deduplicated, 1);
+        let sizes: Vec<u64> = result
+            .root_sizes
+            .iter()
+            .map(|(_root, size)| *size)
+            .collect();
+        assert_eq!(sizes, [110, 0]);
+        // The group of the file follows the root it is counted in
+        assert_eq!(result.sizes.len(), 1);
+        assert_eq!(result.sizes["first"], 110);
+    }
+
+    #[test]
+    fn directories_get_their_own_group_on_request() {
+        let groups = |group_by, group_dirs| {
+            let mut aggregator = Aggregator::new(group_by).group_dirs(group_dirs);
+            aggregator.add_file(FileEntry::new(PathBuf::from("src/main.rs"), 10));
+            aggregator.add_file(FileEntry {
+                is_dir: true,
+                ..FileEntry::new(PathBuf::from("src"), 4096)
+            });
+            let result = aggregator.finish();
+            let mut groups: Vec<(String, u64)> = result
+                .sizes
+                .iter()
+                .map(|(group, size)| (group.to_string(), *size))
+                .collect();
+            groups.sort();
+            groups
+        };
+        let apart = [(DIRECTORIES.to_string(), 4096), ("src".to_string(), 10)];
+        assert_eq!(groups(GroupBy::Directory, false), apart);
+        assert_eq!(
+            groups(GroupBy::Directory, true),
+            [("src".to_string(), 4106)]
+        );
+        assert_eq!(
+            groups(GroupBy::Extension, true),
+            [(DIRECTORIES.to_string(), 4096), ("rs".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn files_are_counted_per_group_on_request() {
+        let add = |aggregator: &mut Aggregator| {
+            for name in ["a.txt", "b.txt", "c.png"] {
+                aggregator.add_file(FileEntry::new(PathBuf::from(name), 1));
+            }
+        };
+        let mut aggregator = Aggregator::new(GroupBy::Extension).count_files(true);
+        add(&mut aggregator);
+        let result = aggregator.finish();
+        assert_eq!(result.file_counts["txt"], 2);
+        assert_eq!(result.file_counts["png"], 1);
+
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        add(&mut aggregator);
+        assert_eq!(aggregator.finish().file_counts.len(), 0);
+    }
+
+    #[test]
+    fn groups_past_the_cap_are_merged() {
+        let mut aggregator = Aggregator::new(GroupBy::FileName).max_groups(Some(2));
+        for (name, size) in [("a", 1), ("b", 2), ("c", 4), ("a", 8), ("d", 16)] {
+            aggregator.add_file(FileEntry::new(PathBuf::from(name), size));
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.sizes.len(), 3);
+        assert_eq!(result.sizes["a"], 9);
+        assert_eq!(result.sizes["b"], 2);
+        assert_eq!(result.sizes[OTHER_GROUPS], 20);
+        assert_eq!(result.total, 31);
+    }
+
+    #[test]
+    fn outliers_get_a_row_of_their_own() {
+        let mut aggregator = Aggregator::new(GroupBy::Type)
+            .split_outliers(Some(1000))
+            .max_groups(Some(1));
+        for (path, size) in [
+            ("movies/a.mkv", 5000),
+            ("movies/b.mkv", 300),
+            ("clips/c.mp4", 200),
+            ("notes.txt", 1000),
+            ("photo.jpg", 10),
+        ] {
+            aggregator.add_file(FileEntry::new(PathBuf::from(path), size));
+        }
+        let result = aggregator.finish();
+        assert_eq!(result.sizes.len(), 4);
+        assert_eq!(result.sizes["Video"], 500);
+        assert_eq!(result.sizes["Video: movies/a.mkv"], 5000);
+        assert_eq!(result.sizes["Document: notes.txt"], 1000);
+        assert_eq!(result.sizes[OTHER_GROUPS], 10);
+        assert_eq!(result.total, 6510);
+    }
+
+    #[test]
+    fn only_the_largest_files_are_listed() {
+        let files = [
+            ("a.mkv", 5000),
+            ("b.txt", 30),
+            ("c.jpg", 700),
+            ("d.txt", 2),
+            ("e.jpg", 700),
+        ];
+        let listed = |list| {
+            let mut aggregator = Aggregator::new(GroupBy::Extension).list_files(Some(list));
+            for (path, size) in files {
+                aggregator.add_file(FileEntry::new(PathBuf::from(path), size));
+            }
+            let result = aggregator.finish();
+            assert_eq!(result.total, 6432);
+            result
+                .listed
+                .into_iter()
+                .map(|file| {
+                    (
+                        file.size,
+                        file.path.to_string_lossy().into_owned(),
+                        file.group,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let file = |size, path: &str, group: &str| (size, path.to_string(), group.to_string());
+
+        assert_eq!(
+            listed(FileList {
+                top: Some(3),
+                ..Default::default()
+            }),
+            [
+                file(700, "c.jpg", "jpg"),
+                file(700, "e.jpg", "jpg"),
+                file(5000, "a.mkv", "mkv")
+            ]
+        );
+        assert_eq!(
+            listed(FileList {
+                size: vec![SizeFilter::from_string("-100b").unwrap()],
+                ..Default::default()
+            }),
+            [file(2, "d.txt", "txt"), file(30, "b.txt", "txt")]
+        );
+    }
+
+    #[test]
+    fn files_are_classified_like_they_are_grouped() {
+        for (path, ext) in [
+            ("photos/IMG_1.JPG", "jpg"),
+            ("src/main.rs", "rs"),
+            ("report.docx", "docx"),
+            ("Makefile", ""),
+            (".bashrc", ""),
+            ("archive.tar.zst", "zst"),
+        ] {
+            let (found, file_type, fine_type) = classify(Path::new(path));
+            assert_eq!(found, ext, "{path}");
+            assert_eq!(file_type, FileType::get_filetype(&ext), "{path}");
+            assert_eq!(fine_type, FineType::get_finetype(&ext), "{path}");
+        }
+    }
+
+    #[test]
+    fn sums_saturate_instead_of_wrapping() {
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        aggregator.add_file(FileEntry::new(PathBuf::from("a.img"), u64::MAX - 10));
+        aggregator.add_file(FileEntry::new(PathBuf::from("b.txt"), 5));
+        let result = aggregator.finish();
+        assert_eq!(result.total, u64::MAX - 5);
+        assert!(!result.saturated);
+
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        aggregator.add_file(FileEntry::new(PathBuf::from("a.img"), u64::MAX - 10));
+        aggregator.add_file(FileEntry::new(PathBuf::from("b.img"), 100));
+        aggregator.add_file(FileEntry::new(PathBuf::from("c.txt"), 5));
+        let result = aggregator.finish();
+        assert_eq!(result.total, u64::MAX);
+        assert_eq!(result.sizes["img"], u64::MAX);
+        assert_eq!(result.sizes["txt"], 5);
+        assert!(result.saturated);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_names_keep_a_visible_key() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let latin1 = Path::new(OsStr::from_bytes(b"/nas/caf\xe9/r\xe9sum\xe9.TXT"));
+        assert_eq!(get_ext(latin1), "txt");
+        let mut key = String::new();
+        push_filename(latin1, &mut key, false);
+        assert_eq!(key, "r\u{fffd}sum\u{fffd}.TXT");
+        key.clear();
+        push_dir_name(latin1.parent().unwrap(), &mut key, false);
+        assert_eq!(key, "caf\u{fffd}");
+
+        let bad_ext = Path::new(OsStr::from_bytes(b"photo.jp\xe9g"));
+        assert_eq!(get_ext(bad_ext), "jp\u{fffd}g");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_names_can_be_escaped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let keys = |escape_nonutf8| {
+            let mut aggregator = Aggregator::new(GroupBy::FileName).escape_nonutf8(escape_nonutf8);
+            for name in [&b"caf\xe9"[..], b"caf\xe8", b"caf\\xE9", b"caf\xc3\xa9"] {
+                let path = PathBuf::from(OsStr::from_bytes(name));
+                aggregator.add_file(FileEntry::new(path, 1));
+            }
+            let mut keys: Vec<_> = aggregator.finish().sizes.into_iter().collect();
+            keys.sort();
+            keys
+        };
+        let own = |v: &[(&str, u64)]| -> Vec<(String, u64)> {
+            v.iter().map(|(k, s)| (k.to_string(), *s)).collect()
+        };
+        // Both Latin-1 names end up in one group
+        assert_eq!(
+            keys(false),
+            own(&[("caf\\xE9", 1), ("café", 1), ("caf\u{fffd}", 2)])
+        );
+        assert_eq!(
+            keys(true),
+            own(&[
+                ("caf\\\\xE9", 1),
+                ("caf\\xE8", 1),
+                ("caf\\xE9", 1),
+                ("café", 1)
+            ])
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use crate::filter::SizeFilter;
+use crate::filter::{SizeFilter, parse_size};
+use std::borrow::Cow;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{ArgAction, Parser, ValueEnum, builder::styling, value_parser};
-use humansize::format_size;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum, builder::styling, value_parser};
+use humansize::{FormatSizeOptions, format_size};
 
 const STYLES: styling::Styles = styling::Styles::styled()
     .header(styling::AnsiColor::Green.on_default().bold())
@@ -10,20 +12,74 @@ const STYLES: styling::Styles = styling::Styles::styled()
     .literal(styling::AnsiColor::Blue.on_default().bold())
     .placeholder(styling::AnsiColor::Cyan.on_default());
 
+const EXIT_STATUS: &str = "\
+Exit status:
+  0  Success
+  1  Hard failure: invalid arguments, an input does not exist or none could be read
+  2  With --strict, some files or directories could not be read (results are still printed)
+  3  The scan was stopped by --max-files, --limit or --timeout (partial results are printed)
+  4  With --fail-on-empty, the total is 0 bytes
+  5  --ext, --exclude-ext or --size were given and nothing matched them
+  130  The scan was interrupted by Ctrl-C (partial results are printed)";
+
 /// Computes disk-usage for the given entries and groups them by extension or file types
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 #[command(name = "fss")]
 #[command(styles=STYLES)]
+#[command(after_long_help = EXIT_STATUS)]
 pub struct Cli {
-    /// Select how to group the files sizes. [values: e, t, f, d]
+    /// Select how to group the files sizes. [values: e, t, type2, f, d, a, p, created-year,
+    /// created-month, access]
     ///
     ///     'e': extension
     ///     't': file type, eg. Images, Videos, Documents...
+    ///     'type2': file type with documents split in Office/Text/Ebook
+    ///              and code split in Web/Script/Source
     ///     'f': file name
     ///     'd': parent directory
-    #[arg(short, long, default_value="extension", value_parser=parse_group_by, verbatim_doc_comment)]
-    pub group_by: GroupBy,
+    ///     'a': time since last modification: last 7 days, last 30 days,
+    ///          last year or older
+    ///     'p': parent directory, as its path from the input it is in,
+    ///          shown with its depth below the input
+    ///     'created-year', 'created-month': when files were created, in UTC.
+    ///          Files whose creation time is not recorded are 'Unknown':
+    ///          Linux needs kernel 4.11 and a filesystem recording it
+    ///          (ext4, btrfs, XFS v5...); network filesystems often don't
+    ///     'access': what you may do with files, from their permissions:
+    ///          Read-write, Read-only, Write-only, No access, or
+    ///          World-writable when anyone may write to them (Unix)
+    ///
+    /// Repeat it to group the files several ways in one scan, each in
+    /// its own section, e.g. '-g e -g t'.
+    #[arg(short, long, default_value="extension", value_parser=parse_group_by,
+        action=ArgAction::Append, verbatim_doc_comment)]
+    pub group_by: Vec<GroupBy>,
+
+    /// With '-g extension', group dotfiles without any other dot (.bashrc, .gitignore...) under
+    /// their whole name instead of with the files without extension
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub dotfile_as_ext: bool,
+
+    /// With '-g extension', merge the extensions that are other spellings of a same format
+    /// (jpeg into jpg, htm into html, yml into yaml...)
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub normalize_ext: bool,
+
+    /// Write the bytes of names that aren't valid UTF-8 as '\xNN' in the group names
+    ///
+    /// By default they are replaced by '�', which can merge distinct names into one group. With
+    /// this flag backslashes are doubled, so every name is spelled unambiguously.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub escape_nonutf8: bool,
+
+    /// Print the names of the groups in lower or upper case, e.g. 'PNG' with 'upper'
+    ///
+    /// Only the printed names change, once the files are grouped: 'png' and 'PNG' stay apart
+    /// unless grouped by extension, which already ignores the case. Names like 'Other' and
+    /// '(other groups)' change too.
+    #[arg(long, value_name = "CASE", default_value_t = LabelCase::AsIs, value_enum)]
+    pub label_case: LabelCase,
 
     /// Limit results based on the size of files using the format <+-><NUM><UNIT>.
     ///    '+': file size must be greater than or equal to this
@@ -42,40 +98,681 @@ pub struct Cli {
     ///     'mi': mebibytes
     ///     'gi': gibibytes
     ///     'ti': tebibytes
+    ///
+    /// With '--count-mode files', the groups are filtered by their number of files instead, the
+    /// unit multiplying it: '+50b' for the groups of at least 50 files, '+1k' for 1000.
     #[arg(short = 'S', long, value_parser = SizeFilter::from_string, allow_hyphen_values = true,
         help = "Limit results based on the size of files", verbatim_doc_comment)]
     pub size: Vec<SizeFilter>,
 
-    /// Output format for file sizes (decimal: base-10 MB, binary: base 2 MiB, bytes: raw byte count B)
+    /// Print every file with its size and its group instead of the groups, smallest first
+    ///
+    /// The filters and the deduplication of hardlinks apply, and -S/--size selects the files by
+    /// their own size. Use --top to keep only the largest ones.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["compact", "chart", "compare", "top_by", "both_sizes", "dedupe_extents"])]
+    pub list_files: bool,
+
+    /// Print every file with its extension and the types it maps to, to see why a file is
+    /// grouped where it is, e.g. in 'Other'
+    ///
+    /// Like --list-files, which it can be combined with to use --top, with the extension, the
+    /// type (-g type) and the finer type (-g type2) before the path.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["compact", "chart", "compare", "top_by", "both_sizes", "dedupe_extents"])]
+    pub explain: bool,
+
+    /// With --list-files, list only the N largest files
+    ///
+    /// Only N files are held in memory, however large the tree.
+    #[arg(long, value_name = "N", requires = "list_files")]
+    pub top: Option<usize>,
+
+    /// With --list-files, stop the scan once N files are listed
+    ///
+    /// The files listed are the first ones found, in the order the threads read them: a sample,
+    /// not the largest ones. If the scan finds more than N files, the results are marked as partial
+    /// and the exit status is 3.
+    #[arg(long, value_name = "N", requires = "list_files", conflicts_with = "top",
+        value_parser = value_parser!(u64).range(1..))]
+    pub limit: Option<u64>,
+
+    /// Print the sets of files with the same contents instead of the groups, and the space the
+    /// extra copies take, in total and in each group
+    ///
+    /// Files of the same length are hashed with BLAKE3, on --threads threads, which reads them
+    /// whole. Hardlinks to a file are not copies of it, unless --no-dedup is given.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["compact", "chart", "compare", "top_by", "both_sizes", "dedupe_extents",
+            "list_files", "explain", "largest", "sample", "from_inventory"])]
+    pub duplicates: bool,
+
+    /// After the groups, print the N largest files with their size, whatever their group
+    ///
+    /// A file with several hardlinks is listed once. Only N files are held in memory.
+    #[arg(long, value_name = "N", conflicts_with_all = ["list_files", "explain", "compact"])]
+    pub largest: Option<usize>,
+
+    /// Give every file of at least SIZE a row of its own, e.g. 'Video: movies/a.mkv', instead of
+    /// adding it to its group
+    ///
+    /// Shows which few files make a group large. SIZE uses the same units as --size.
+    #[arg(long, value_name = "SIZE", value_parser = parse_threshold)]
+    pub split_outliers: Option<u64>,
+
+    /// With '-g directory', add every file to its ancestor N levels below the input instead of to
+    /// its parent, e.g. 1 for the size of each top folder
+    ///
+    /// The groups are spelled starting with the input, like 'du --max-depth' does. Files closer
+    /// to the input than N levels are added to their own directory.
+    #[arg(long, value_name = "N")]
+    pub flat_depth: Option<usize>,
+
+    /// Print the paths of files relative to BASE, e.g. with --list-files, --largest or
+    /// --duplicates
+    ///
+    /// Paths outside of BASE are printed as absolute paths. The groups are left as they are.
+    #[arg(long, value_name = "BASE")]
+    pub relative_to: Option<PathBuf>,
+
+    /// With '-g directory' and '-g path', spell the groups as absolute paths, e.g.
+    /// '/home/me/project/output' instead of 'output'
+    ///
+    /// The inputs are resolved once, following links; an input that can't be resolved is joined
+    /// to the current directory. Other groupings are left as they are.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub absolute: bool,
+
+    /// After the total, print the size of each input
+    ///
+    /// A file hardlinked from several inputs is counted once, in the first of them on the command
+    /// line, and in the group of its link there. The sizes of the inputs then add up to the
+    /// total. A file inside nested inputs is counted in the innermost one.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["compact", "from_inventory"])]
+    pub per_root: bool,
+
+    /// With '-g path', list the groups by depth below the input, then by size, instead of by
+    /// size alone
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, conflicts_with = "top_by")]
+    pub by_depth: bool,
+
+    /// Keep at most N groups, the files of any later group are added to '(other groups)'
+    ///
+    /// Bounds the memory used when grouping millions of distinct names. The groups that keep
+    /// their own line are the first ones met during the scan, which depends on the order the
+    /// threads read the files in, not on their size.
+    #[arg(long, value_name = "N")]
+    pub max_groups: Option<usize>,
+
+    /// Only count files with one of these extensions, e.g. --ext jpg,png
+    ///
+    /// Extensions are matched like -g extension groups them, without the dot and whatever the
+    /// case. Files left out don't count toward the total.
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    pub ext: Vec<String>,
+
+    /// Don't count files with one of these extensions, e.g. --exclude-ext log,tmp
+    ///
+    /// Applied after --ext, so an extension given to both is left out.
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    pub exclude_ext: Vec<String>,
+
+    /// What each group adds up: the size of its files, or their number with 'files'
+    ///
+    /// Counting files shows where inodes go when a filesystem runs out of them rather than out
+    /// of space. Every file then counts as 1: the groups, the total, the sorting, -S/--size,
+    /// --top-by, --highlight and --warn all work on numbers of files, printed as plain integers.
+    #[arg(long, value_name = "MODE", default_value_t = CountMode::Bytes, value_enum)]
+    pub count_mode: CountMode,
+
+    /// Count inodes instead of bytes, like 'du --inodes': files and directories alike, a file
+    /// with several hardlinks once
+    ///
+    /// This is the number inode quotas limit. Directories are counted in their own group with
+    /// '-g directory' and '-g path', and in a '(directories)' group otherwise. Like
+    /// '--count-mode files', the groups and the total are then printed as plain integers.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["count_mode", "no_dedup"])]
+    pub inodes: bool,
+
+    /// Output format for file sizes (decimal: base-10 MB, binary: base 2 MiB, bytes: raw byte count B,
+    /// blocks: count of --output-block-size blocks rounded up, like du -k)
     #[arg(short, long, default_value_t = FormatOption::Decimal, value_enum)]
     pub size_format: FormatOption,
 
+    /// Number of decimals of human-readable sizes, from 0 to 6 [default: 2]
+    ///
+    /// Applies to the decimal and binary formats and to --unit, to every group and to the total.
+    #[arg(long, value_name = "DIGITS", value_parser = value_parser!(u8).range(0..=6))]
+    pub precision: Option<u8>,
+
+    /// Print every size as a number of this unit, without suffix, e.g. '1536.00' with 'kib'
+    ///
+    /// Rows and total are then directly comparable, e.g. in a spreadsheet. Overrides
+    /// --size-format.
+    #[arg(long, value_enum)]
+    pub unit: Option<Unit>,
+
+    /// With --unit, write the unit after every size
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, requires = "unit")]
+    pub unit_suffix: bool,
+
+    /// Add the exact number of bytes in parentheses after every size, e.g. '1.40 GB (1402349821)'
+    ///
+    /// Left out where sizes are already in bytes, and from the single line of --compact.
+    #[arg(long, visible_alias = "both-units", default_value_t = false, action=ArgAction::SetTrue)]
+    pub show_bytes: bool,
+
+    /// Separate thousands in byte counts and with --unit, e.g. '139,482,003,948'
+    ///
+    /// SEP is ',' when not given, or one of '_', '.', ' ' and "'" for parsers that expect them.
+    #[arg(long, value_name = "SEP", num_args = 0..=1, default_missing_value = ",",
+        require_equals = true, value_parser = parse_digit_separator)]
+    pub group_digits: Option<char>,
+
+    /// With '-s blocks', the size of a block, e.g. '1Mi' for the blocks of du -m
+    ///
+    /// Only the printed sizes are divided and rounded up, unlike with --block-size. SIZE uses the
+    /// same units as --size.
+    #[arg(long, value_name = "SIZE", default_value = "1Ki", value_parser = parse_block_size)]
+    pub output_block_size: u64,
+
+    /// Round every printed size to the nearest multiple of SIZE, e.g. '100M' for tidy reports
+    ///
+    /// Halfway sizes are rounded up. Only the printed sizes are rounded: --size still filters
+    /// the exact ones, and --show-bytes still adds the exact number of bytes. SIZE uses the
+    /// same units as --size.
+    #[arg(long, value_name = "SIZE", value_parser = parse_block_size)]
+    pub round_to: Option<u64>,
+
+    /// Round the size of every file up to a multiple of SIZE, e.g. '1Mi'
+    ///
+    /// Predicts the usage on a filesystem with SIZE allocation units, such as an exFAT SD card
+    /// or an object storage gateway. Combine with --apparent-size to ignore the allocation of
+    /// the scanned filesystem. SIZE uses the same units as --size.
+    #[arg(long, value_name = "SIZE", value_parser = parse_block_size)]
+    pub block_size: Option<u64>,
+
+    /// Do not descend into the mount point (or any directory) at PATH. Can be repeated
+    ///
+    /// Useful to skip /proc, /sys or network shares when scanning /.
+    #[arg(long, value_name = "PATH")]
+    pub exclude_mount: Vec<PathBuf>,
+
+    /// Count what symbolic links and junctions point to instead of the links themselves
+    ///
+    /// By default no link is followed, not even an input that is a link: each link is counted as
+    /// a file, by its own size, and a linked directory is never descended into. With -L, each
+    /// target is counted once, and not at all if it is inside one of the inputs. Links that
+    /// can't be followed are counted as links.
+    #[arg(short = 'L', long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub follow_links: bool,
+
     /// Compute apparent size instead of disk usage
-    #[cfg(not(windows))]
     #[arg(short='b', long, default_value_t = false, action=ArgAction::SetTrue)]
     pub apparent_size: bool,
 
     /// Set the number of threads to use. Default 3 x num cores
+    ///
+    /// Use 0 or 'auto' to pick the number based on where the inputs are stored: one thread per
+    /// core for network filesystems and rotational disks, two per core for everything else.
     // Setting the number of threads to 3x the number of cores is a good tradeoff between
     // cold-cache and warm-cache runs. For a cold disk cache, we are limited by disk IO and
     // therefore want the number of threads to be rather large in order for the IO scheduler to
     // plan ahead. On the other hand, the number of threads shouldn't be too high for warm disk
     // caches where we would otherwise pay a higher synchronization overhead.
-    #[arg(short = 'j', long, default_value_t =3 * num_cpus::get())]
+    #[arg(short = 'j', long, default_value_t =3 * num_cpus::get(), value_parser=parse_threads)]
     pub threads: usize,
 
-    /// Do not hide filesystem errors
+    /// Print only the total size and the number of files on a single line, e.g. "1.2 GB (847 files)"
+    ///
+    /// The format is '<SIZE> (<COUNT> files)' and is kept stable for use in scripts,
+    /// shell prompts and status bars. --size-format still applies to <SIZE>.
     #[arg(short, long, default_value_t = false, action=ArgAction::SetTrue)]
-    pub verbose: bool,
+    pub compact: bool,
+
+    /// Only show the N largest groups by size or by number of files, e.g. 'size:10' or 'count:5'
+    ///
+    /// Applied after -S/--size. The groups are listed in the order of the chosen key.
+    #[arg(long, value_name = "KEY:N", value_parser = parse_top_by)]
+    pub top_by: Option<TopBy>,
+
+    /// Write the size of each group to FILE, to compare a later scan with it using --compare
+    #[arg(long, value_name = "FILE")]
+    pub save_groups: Option<PathBuf>,
+
+    /// Save the whole scan to FILE: the size and the number of files of each group, the total,
+    /// the errors, the inputs, how the files were counted and grouped, the time and the version
+    /// of fss
+    ///
+    /// The file is a versioned text format, one record per line, small enough to keep one per
+    /// night. --compare and 'fss diff' read it like the groups of --save-groups.
+    #[arg(long, value_name = "FILE")]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Show how each group changed since the scan saved in FILE by --save-groups, e.g. '↑ +12%'
+    ///
+    /// Groups missing from the saved scan are shown as 'new', and groups only found in it as
+    /// 'removed'. Use the same --group-by and size options for both scans, which is checked when
+    /// FILE is a snapshot of --save-snapshot.
+    #[arg(long, value_name = "FILE")]
+    pub compare: Option<PathBuf>,
+
+    /// Print the groups of at least SIZE in bold red, e.g. '10G' or '500Mi'
+    ///
+    /// Compared with the size of each group once the filters are applied. Follows --color.
+    #[arg(long, value_name = "SIZE", value_parser = parse_threshold)]
+    pub highlight: Option<u64>,
+
+    /// Print the groups of at least SIZE in yellow, a lower tier than --highlight
+    #[arg(long, value_name = "SIZE", value_parser = parse_threshold)]
+    pub warn: Option<u64>,
+
+    /// Chart the N largest groups as bars, with their share of the total. N is 10 when not given
+    ///
+    /// Each group gets a line like 'jpg │ ████████ 42.1% (1.20 GB)', fitted to the terminal.
+    /// Applied after -S/--size and --top-by.
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10",
+        require_equals = true, value_parser = value_parser!(u64).range(1..),
+        conflicts_with_all = ["compact", "dedupe_extents", "both_sizes", "compare"])]
+    pub chart: Option<u64>,
+
+    /// Write the total and the notes below it to stderr, and only the groups to stdout
+    ///
+    /// With 'fss ... > groups.txt' the summary still shows in the terminal. A table keeps its
+    /// total row.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub summary_to_stderr: bool,
+
+    /// Report the total of the groups left by the filters instead of the grand total
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub filtered_total: bool,
+
+    /// When to color the output. 'auto' colors it when stdout is a terminal, unless NO_COLOR is
+    /// set, or whenever CLICOLOR_FORCE is set
+    ///
+    /// Grouped by type or extension, the groups are colored by file type. The colors can be
+    /// changed with FSS_COLORS, e.g. FSS_COLORS='image=bright green:video=none'.
+    #[arg(long, value_name = "WHEN", default_value_t = ColorWhen::Auto, value_enum)]
+    pub color: ColorWhen,
+
+    /// How to lay out the groups. 'table' draws a bordered table fitted to the terminal, and
+    /// falls back to 'plain' when the output is not a terminal. Listed files, duplicates, charts
+    /// and the compact line have no table.
+    #[arg(short, long, default_value_t = OutputFormat::Plain, value_enum)]
+    pub output: OutputFormat,
+
+    /// Put STR between the columns of the plain output instead of a tab, e.g. ',' or ' | '
+    ///
+    /// The sizes are still padded to line up. With '-s bytes', 'cut -d' can read the groups.
+    #[arg(long, value_name = "STR")]
+    pub separator: Option<String>,
+
+    /// Split the usage of each group into data of its own and data shared with other files
+    ///
+    /// Files cloned with reflinks (cp --reflink) on btrfs or XFS share their data, which is
+    /// otherwise counted once per file. Finding the shared data costs an open and an ioctl
+    /// (FIEMAP) per file. Files on filesystems without FIEMAP are counted as exclusive.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, conflicts_with = "apparent_size")]
+    pub dedupe_extents: bool,
+
+    /// Show the apparent size and the disk usage of each group side by side
+    ///
+    /// The ratio of the two is below 100% for groups of sparse or compressed files, like disk
+    /// images, and above it for groups of small files. Both are read at once, the groups are
+    /// still sorted and filtered by the size selected with -b/--apparent-size. The single line of
+    /// --compact has no room for the second size.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["dedupe_extents", "compact"])]
+    pub both_sizes: bool,
+
+    /// Show how many entries were scanned so far while scanning
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub progress: bool,
+
+    /// Count the entries first, to show the progress as a percentage with the time left
+    ///
+    /// This does a first pass listing every directory before the real scan, which adds about
+    /// half the time of a scan with a warm cache. Without it, the progress is only a count of
+    /// the entries scanned so far.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub accurate_progress: bool,
+
+    /// Also count the extended attributes of each file, including resource forks
+    ///
+    /// Finder info, quarantine flags and old style resource forks are stored next to the data
+    /// of a file and left out of its size. Reading them costs a few system calls per file.
+    #[cfg(target_os = "macos")]
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub include_xattrs: bool,
 
-    /// List of paths
+    /// Also count the alternate data streams of each file
+    ///
+    /// Streams like Zone.Identifier, or anything an application hides in a file, are left out
+    /// of its size. Listing them costs a handle per file.
+    #[cfg(windows)]
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub include_ads: bool,
+
+    /// Only count regular files and symlinks, skipping FIFOs, sockets and devices
+    ///
+    /// The sizes of special files mean nothing, and device files can report the size of a
+    /// whole disk. How many were skipped is printed on stderr.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub regular_only: bool,
+
+    /// Skip the files and directories managed by the OS, like .DS_Store and Thumbs.db
+    ///
+    /// They are counted unless asked: on Windows they include the paging and hibernation files
+    /// and the recycle bin, often among the largest files of a drive. macOS and Windows skip
+    /// their own system files, elsewhere the ones of both are skipped, as found on shared drives.
+    /// --system-names changes the list.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, overrides_with = "count_system")]
+    pub skip_system: bool,
+
+    /// Count the files and directories managed by the OS, the default. Undoes a --skip-system
+    /// set in the config file.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, overrides_with = "skip_system")]
+    pub count_system: bool,
+
+    /// The names skipped by --skip-system, separated by commas, instead of the usual system files
+    ///
+    /// Names are matched ignoring case, and a name ending with '*' matches every name starting
+    /// with it, e.g. '._*'. Implies --skip-system.
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    pub system_names: Option<Vec<String>>,
+
+    /// Only count the files tracked by git, as listed by 'git ls-files'
+    ///
+    /// Ignored files and untracked files that are not ignored yet are both left out, as are
+    /// build outputs and caches. Files of submodules are included. Every input must be inside a
+    /// git work tree.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, conflicts_with = "from_inventory")]
+    pub git_tracked: bool,
+
+    /// Count every hardlink to a file instead of counting the file once
+    ///
+    /// Hardlinked files may then be counted several times. By default only files with more than
+    /// one link are tracked, so this mostly saves memory on trees with many of them.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub no_dedup: bool,
+
+    /// Track every file to count it once, not only the ones with several hardlinks
+    ///
+    /// Catches the files reached twice through bind mounts or overlapping inputs, e.g.
+    /// 'fss data data/logs', at the cost of remembering every file. On Windows, files are opened
+    /// to read their ID either way.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, conflicts_with = "no_dedup")]
+    pub force_dedup: bool,
+
+    /// Count sizes like GNU `du --apparent-size --block-size=1` (du -b)
+    ///
+    /// Implies -b/--apparent-size, counts hardlinked files once, and adds the size of every
+    /// directory itself (inputs included) in a '(directories)' group. With '-c -s bytes' the total
+    /// is the one of 'du -sb' on the same inputs, and with -L the one of 'du -sbL'. Unlike du,
+    /// a directory reached twice through bind mounts is counted twice.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["no_dedup", "block_size", "dedupe_extents"])]
+    pub du_compat: bool,
+
+    /// Exit with status 2 if any file or directory could not be read
+    ///
+    /// The results are printed anyway, but a partial total is not mistaken for a complete one.
+    /// With --quiet, this status is the only sign of the errors.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub strict: bool,
+
+    /// Exit with status 4 if the total is 0 bytes, which usually means a wrong path was scanned
+    ///
+    /// Independent of --strict: unreadable files don't count as empty, and an empty scan fails
+    /// even when everything could be read. A stopped scan exits with 3 first.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub fail_on_empty: bool,
+
+    /// Stop the scan after counting N files and print what was found so far
+    ///
+    /// The results are marked as partial and the exit status is 3.
+    #[arg(long, value_name = "N")]
+    pub max_files: Option<u64>,
+
+    /// Stop the scan after this long, e.g. 30s, 5m or 1h, and print what was found so far
+    ///
+    /// A number without unit is in seconds. The results are marked as partial and the exit
+    /// status is 3.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Only read a fraction of the files, e.g. 0.1, and estimate the sizes from them
+    ///
+    /// Every directory is still listed, but only about this fraction of the files in each is
+    /// read. The sizes are scaled up and shown as estimates. Which files are read depends on
+    /// --seed.
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    pub sample: Option<f64>,
+
+    /// Seed picking the files read by --sample, the same seed reads the same files
+    ///
+    /// A different seed is used for every run when it is not given, it is printed with the
+    /// results.
+    #[arg(long, value_name = "N", requires = "sample")]
+    pub seed: Option<u64>,
+
+    /// Read the inputs from FILE, one path per line, instead of from the arguments. FILE can be
+    /// '-' for stdin, e.g. 'fd -e mkv | fss --files-from -'
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["inputs", "files0_from", "from_inventory"])]
+    pub files_from: Option<PathBuf>,
+
+    /// Like --files-from, with the paths separated by NUL characters, e.g. 'fd -0 | fss
+    /// --files0-from -'
+    ///
+    /// Any path can be read this way, even one with a newline in its name. Like the arguments,
+    /// directories are scanned and files are counted as they are.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["inputs", "from_inventory"])]
+    pub files0_from: Option<PathBuf>,
+
+    /// Skip inputs that don't exist instead of failing
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub ignore_missing: bool,
+
+    /// Print statistics about the scan after the results
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub stats: bool,
+
+    /// Print how long the walk and the output took, and how many files and bytes were read per
+    /// second, to stderr after the results
+    ///
+    /// The walk includes grouping the files, which happens while they are read. A slow walk
+    /// with a fast output means the time went into reading the filesystem.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub timings: bool,
+
+    /// Do not hide filesystem errors
+    ///
+    /// Errors are summarized by kind, repeat the flag (-vv) to print every single one.
+    #[arg(short, long, action=ArgAction::Count)]
+    pub verbose: u8,
+
+    /// How to print the filesystem errors on stderr. 'json' prints every error as a JSON object
+    /// per line, whatever -v/--verbose, for log collectors
+    ///
+    /// Each object has the 'kind' of the error (permission_denied, not_found,
+    /// too_many_open_files, changed or other), the 'path' and the 'message'. A path that is not
+    /// valid UTF-8 is lossily converted, and its exact bytes follow in base64 as 'path_bytes'.
+    #[arg(long, value_name = "FORMAT", default_value_t = ErrorFormat::Human, value_enum)]
+    pub errors: ErrorFormat,
+
+    /// Append every error of the walk to FILE, whatever -v/--verbose, as it happens
+    ///
+    /// Each error is a '<TIME>\t<KIND>\t<PATH>' line, the time in UTC like
+    /// '2024-02-29T23:59:59Z' and the kinds those of --errors json. The errors are then only
+    /// counted in memory, so -vv and --errors json print none of them.
+    #[arg(long, value_name = "FILE")]
+    pub error_log: Option<PathBuf>,
+
+    /// Print no warnings on stderr, like the count of errors, and no progress
+    ///
+    /// For scripts that handle errors with the exit status: --strict still exits with 2 when
+    /// something could not be read. Errors that stop fss are still printed.
+    #[arg(short, long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["verbose", "progress", "accurate_progress"])]
+    pub quiet: bool,
+
+    /// Scan, then print only the number of files, bytes and errors and how long the walk took
+    ///
+    /// For benchmarks, see benches/walk.rs. The groups are made but not printed, and the exit
+    /// status is 0 whatever happened.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue, hide = true)]
+    pub bench_mode: bool,
+
+    /// Aggregate a per-file inventory instead of scanning the filesystem
+    ///
+    /// The inventory has one '<SIZE>\t<PATH>' line per file, with the size in bytes.
+    #[arg(long, value_name = "PATH", hide = true)]
+    pub from_inventory: Option<PathBuf>,
+
+    /// Group the sizes read from stdin instead of scanning the filesystem, e.g.
+    /// 'my-tool | fss --stdin-sizes -g type'
+    ///
+    /// Each line is '<SIZE>\t<PATH>', with the size in bytes, and empty lines are skipped. A
+    /// malformed line stops fss with its number. The paths are only grouped, never read, so
+    /// nothing is deduplicated.
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue,
+        conflicts_with_all = ["inputs", "from_inventory", "files_from", "files0_from", "git_tracked",
+            "duplicates", "per_root"])]
+    pub stdin_sizes: bool,
+
+    /// Read the default options from FILE instead of the usual config file
+    ///
+    /// The usual one is 'fss/config.toml' in the config directory: $XDG_CONFIG_HOME or ~/.config,
+    /// '~/Library/Application Support' on macOS and %APPDATA% on Windows. It has one
+    /// 'option = value' line per option, named like the long flags, e.g. 'group_by = "type"',
+    /// 'apparent_size = true' or 'exclude_ext = ["tmp", "log"]'. A '.fss.toml' in the current
+    /// directory, or in the closest of its parents that has one, wins over it. An option is also
+    /// read from FSS_<OPTION>, e.g. FSS_GROUP_BY=type, which wins over the files, and FSS_OPTS
+    /// can hold flags, e.g. FSS_OPTS="-s binary --exclude-ext tmp", which win over all of these.
+    /// Flags given on the command line win over everything.
+    #[arg(long, value_name = "FILE", conflicts_with = "no_config")]
+    pub config: Option<PathBuf>,
+
+    /// Ignore the config files, the FSS_<OPTION> variables and FSS_OPTS
+    #[arg(long, default_value_t = false, action=ArgAction::SetTrue)]
+    pub no_config: bool,
+
+    /// List of paths. A path called like a command, e.g. 'types', is written './types'
     #[arg(default_value = ".", value_parser=value_parser!(PathBuf))]
     pub inputs: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Whether the files are read from an inventory instead of found by a walk
+    pub fn reads_inventory(&self) -> bool {
+        self.from_inventory.is_some() || self.stdin_sizes
+    }
+
+    /// Whether the groups add up numbers of files or inodes instead of sizes
+    pub fn counts_entries(&self) -> bool {
+        self.inodes || self.count_mode == CountMode::Files
+    }
+}
+
+/// Upper limit for --threads, anything above this is surely a typo
+const MAX_THREADS: usize = 4096;
+
+/// Parse the number of threads. 'auto' is returned as 0.
+fn parse_threads(s: &str) -> Result<usize, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+    let threads: usize = s
+        .parse()
+        .map_err(|_| format!("'{s}' is not a number of threads or 'auto'"))?;
+    if threads > MAX_THREADS {
+        return Err(format!(
+            "{threads} threads is too many, the maximum is {MAX_THREADS}"
+        ));
+    }
+    Ok(threads)
+}
+
+fn parse_block_size(s: &str) -> Result<u64, String> {
+    match parse_size(s) {
+        Some(0) => Err("the block size can't be 0".to_string()),
+        Some(size) => Ok(size),
+        None => Err(format!("'{s}' is not a size such as 4k or 1Mi")),
+    }
+}
+
+fn parse_threshold(s: &str) -> Result<u64, String> {
+    parse_size(s).ok_or_else(|| format!("'{s}' is not a size such as 10G or 500Mi"))
+}
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("'{s}' is not a fraction above 0 and at most 1")),
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let seconds_per_unit = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("'{s}' is not a duration such as 30s, 5m or 1h")),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * seconds_per_unit).ok())
+        .ok_or_else(|| format!("'{s}' is not a duration such as 30s, 5m or 1h"))
+}
+
+fn parse_digit_separator(s: &str) -> Result<char, String> {
+    match s {
+        "," | "_" | "." | " " | "'" => Ok(s.chars().next().unwrap()),
+        _ => Err(format!(
+            "'{s}' is not a digit separator, expected one of , _ . ' or a space"
+        )),
+    }
+}
+
+fn parse_top_by(s: &str) -> Result<TopBy, String> {
+    let Some((key, n)) = s.split_once(':') else {
+        return Err(format!(
+            "'{s}' is not of the form <KEY>:<N>, e.g. 'size:10'"
+        ));
+    };
+    let key = match key {
+        "size" => TopKey::Size,
+        "count" => TopKey::Count,
+        _ => return Err(format!("unknown key '{key}', expected 'size' or 'count'")),
+    };
+    match n.parse() {
+        Ok(n) if n > 0 => Ok(TopBy { key, n }),
+        _ => Err(format!("'{n}' is not a number of groups above 0")),
+    }
 }
 
 fn parse_group_by(s: &str) -> Result<GroupBy, String> {
     let s = s.to_ascii_lowercase();
-    if "extension".starts_with(&s) {
+    if s == "type2" {
+        Ok(GroupBy::FineType)
+    } else if s == "created-year" {
+        Ok(GroupBy::CreatedYear)
+    } else if s == "created-month" {
+        Ok(GroupBy::CreatedMonth)
+    } else if s == "access" {
+        Ok(GroupBy::Access)
+    } else if "extension".starts_with(&s) {
         Ok(GroupBy::Extension)
     } else if "type".starts_with(&s) {
         Ok(GroupBy::Type)
@@ -83,8 +780,16 @@ fn parse_group_by(s: &str) -> Result<GroupBy, String> {
         Ok(GroupBy::FileName)
     } else if "directory".starts_with(&s) {
         Ok(GroupBy::Directory)
+    } else if "age".starts_with(&s) {
+        Ok(GroupBy::AgeBucket)
+    } else if "path".starts_with(&s) {
+        Ok(GroupBy::Path)
     } else {
-        Err("Group does is not one of [extension, type, filename, directory]".to_string())
+        Err(
+            "Group does is not one of [extension, type, type2, filename, directory, age, path, \
+             created-year, created-month, access]"
+                .to_string(),
+        )
     }
 }
 
@@ -97,11 +802,126 @@ pub enum GroupBy {
     /// Groups by file type. e.g. Images, Videos, Documents...
     Type,
 
+    /// Groups by file type, splitting documents and code into subtypes
+    FineType,
+
     /// Groups by file name
     FileName,
 
     /// Groups by parent directory
     Directory,
+
+    /// Groups by how long ago files were modified, e.g. last 7 days, last year...
+    AgeBucket,
+
+    /// Groups by parent directory, spelled from the input it is in
+    Path,
+
+    /// Groups by the year files were created in
+    CreatedYear,
+
+    /// Groups by the month files were created in
+    CreatedMonth,
+
+    /// Groups by what the current user may do with files: read, write, both or neither
+    Access,
+}
+
+impl GroupBy {
+    /// Rough number of distinct groups to expect, used to pre-size the aggregation map.
+    pub fn expected_groups(&self) -> usize {
+        match self {
+            GroupBy::Type
+            | GroupBy::FineType
+            | GroupBy::AgeBucket
+            | GroupBy::CreatedYear
+            | GroupBy::Access => 16,
+            GroupBy::CreatedMonth => 128,
+            GroupBy::Extension => 256,
+            GroupBy::FileName | GroupBy::Directory | GroupBy::Path => 4096,
+        }
+    }
+}
+
+/// What `--top-by` ranks the groups by
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TopKey {
+    Size,
+    Count,
+}
+
+/// The `--top-by` spec: keep the `n` largest groups by `key`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TopBy {
+    pub key: TopKey,
+    pub n: usize,
+}
+
+/// What to do instead of scanning the inputs
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Print the extensions fss knows, with the type and the finer type of their files
+    Types,
+    /// Compare two scans saved with --save-groups or --save-snapshot, like --compare compares
+    /// the current scan
+    ///
+    /// Two snapshots must have been saved with the same settings, whatever the flags given here.
+    Diff {
+        /// The earlier scan
+        old: PathBuf,
+        /// The later scan
+        new: PathBuf,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+/// What the groups add up, for `--count-mode`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum CountMode {
+    /// The size of the files
+    Bytes,
+    /// The number of files
+    Files,
+}
+
+/// How the names of the groups are printed, for `--label-case`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LabelCase {
+    AsIs,
+    Lower,
+    Upper,
+}
+
+impl LabelCase {
+    pub fn apply<'a>(self, label: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            LabelCase::AsIs => label,
+            LabelCase::Lower => Cow::Owned(label.to_lowercase()),
+            LabelCase::Upper => Cow::Owned(label.to_uppercase()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputFormat {
+    /// One group per line, size and name separated by a tab or --separator
+    Plain,
+    /// A table with box-drawing borders
+    Table,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// A count of the errors, or messages with -v/--verbose
+    Human,
+    /// One JSON object per error
+    Json,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -109,21 +929,170 @@ pub enum FormatOption {
     Decimal,
     Binary,
     Bytes,
+    Blocks,
     Auto,
 }
-impl FormatOption {
-    pub fn format(&self, size: u64) -> String {
+
+/// A fixed unit for `--unit`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Unit {
+    Kb,
+    Kib,
+    Mb,
+    Mib,
+    Gb,
+    Gib,
+}
+
+impl Unit {
+    pub fn bytes(&self) -> u64 {
         match self {
-            FormatOption::Decimal => format_size(size, humansize::DECIMAL),
-            FormatOption::Binary => format_size(size, humansize::BINARY),
-            FormatOption::Bytes => format!("{}", size),
+            Unit::Kb => 1000,
+            Unit::Kib => 1 << 10,
+            Unit::Mb => 1000 * 1000,
+            Unit::Mib => 1 << 20,
+            Unit::Gb => 1000 * 1000 * 1000,
+            Unit::Gib => 1 << 30,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Unit::Kb => "kB",
+            Unit::Kib => "KiB",
+            Unit::Mb => "MB",
+            Unit::Mib => "MiB",
+            Unit::Gb => "GB",
+            Unit::Gib => "GiB",
+        }
+    }
+}
+
+/// How sizes are printed: the format, the decimals of human-readable sizes, and the fixed unit
+/// that overrides the format
+#[derive(Debug, Copy, Clone)]
+pub struct SizeFormat {
+    pub option: FormatOption,
+    pub precision: Option<u8>,
+    pub unit: Option<Unit>,
+    pub unit_suffix: bool,
+    pub digit_separator: Option<char>,
+    /// Size of the blocks counted by `FormatOption::Blocks`
+    pub output_block: u64,
+    /// Add the exact number of bytes after sizes that are not one already
+    pub show_bytes: bool,
+    /// Round the sizes to the nearest multiple of this before formatting them
+    pub round_to: Option<u64>,
+}
+
+impl SizeFormat {
+    pub fn new(cli: &Cli) -> Self {
+        if cli.counts_entries() {
+            return SizeFormat::counts(cli.group_digits);
+        }
+        SizeFormat {
+            option: cli.size_format,
+            precision: cli.precision,
+            unit: cli.unit,
+            unit_suffix: cli.unit_suffix,
+            digit_separator: cli.group_digits,
+            output_block: cli.output_block_size,
+            show_bytes: cli.show_bytes,
+            round_to: cli.round_to,
+        }
+    }
+
+    /// Numbers of files, printed as plain integers
+    pub fn counts(digit_separator: Option<char>) -> Self {
+        SizeFormat {
+            option: FormatOption::Bytes,
+            precision: None,
+            unit: None,
+            unit_suffix: false,
+            digit_separator,
+            output_block: 1,
+            show_bytes: false,
+            round_to: None,
+        }
+    }
+
+    pub fn format(&self, size: u64) -> String {
+        let formatted = self.format_value(self.round(size));
+        if self.show_bytes && !self.is_bytes() {
+            format!("{formatted} ({})", self.group_digits(size.to_string()))
+        } else {
+            formatted
+        }
+    }
+
+    /// `size` to the nearest multiple of `round_to`, halfway sizes up
+    fn round(&self, size: u64) -> u64 {
+        let Some(unit) = self.round_to else {
+            return size;
+        };
+        let down = size - size % unit;
+        if size - down >= unit - (size - down) {
+            down.saturating_add(unit)
+        } else {
+            down
+        }
+    }
+
+    /// Whether sizes are written as their number of bytes
+    fn is_bytes(&self) -> bool {
+        self.unit.is_none()
+            && match self.option {
+                FormatOption::Bytes => true,
+                FormatOption::Auto => !atty::is(atty::Stream::Stdout),
+                FormatOption::Decimal | FormatOption::Binary | FormatOption::Blocks => false,
+            }
+    }
+
+    fn format_value(&self, size: u64) -> String {
+        if let Some(unit) = self.unit {
+            let precision = self.precision.unwrap_or(2).into();
+            let value =
+                self.group_digits(format!("{:.precision$}", size as f64 / unit.bytes() as f64));
+            return if self.unit_suffix {
+                format!("{value} {}", unit.suffix())
+            } else {
+                value
+            };
+        }
+
+        let human = |options: FormatSizeOptions| match self.precision {
+            Some(precision) => format_size(size, options.decimal_places(precision.into())),
+            None => format_size(size, options),
+        };
+        match self.option {
+            FormatOption::Decimal => human(humansize::DECIMAL),
+            FormatOption::Binary => human(humansize::BINARY),
+            FormatOption::Bytes => self.group_digits(size.to_string()),
+            FormatOption::Blocks => self.group_digits(size.div_ceil(self.output_block).to_string()),
             FormatOption::Auto => {
                 if atty::is(atty::Stream::Stdout) {
-                    format_size(size, humansize::DECIMAL)
+                    human(humansize::DECIMAL)
                 } else {
-                    format!("{}", size)
+                    self.group_digits(size.to_string())
                 }
             }
         }
     }
+
+    /// Insert the separator every three digits of the integer part of `number`
+    fn group_digits(&self, number: String) -> String {
+        let Some(separator) = self.digit_separator else {
+            return number;
+        };
+        let int_len = number.find('.').unwrap_or(number.len());
+        let mut grouped = String::with_capacity(number.len() + int_len / 3);
+        for (i, digit) in number[..int_len].chars().enumerate() {
+            if i > 0 && (int_len - i).is_multiple_of(3) {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped.push_str(&number[int_len..]);
+        grouped
+    }
 }
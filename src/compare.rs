@@ -0,0 +1,125 @@
+//! Saving the size of each group, and comparing a later scan with it.
+//!
+//! A saved scan has one group per line, as its size in bytes and its name separated by a tab:
+//! `<SIZE>\t<GROUP>`, the same layout as an inventory.
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use anyhow::{Context, anyhow};
+
+use crate::aggregate::ScanResult;
+use crate::group_map::GroupMap;
+
+/// Write the size of each group of `result`. Groups whose name spans several lines can't be
+/// read back and are left out.
+pub fn write_groups<W: Write>(out: &mut W, result: &ScanResult) -> io::Result<()> {
+    for (group, size) in result.sizes.iter() {
+        if !group.contains('\n') {
+            writeln!(out, "{size}\t{group}")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_groups<R: BufRead>(reader: R) -> anyhow::Result<GroupMap<u64>> {
+    let mut groups = GroupMap::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.context("could not read the saved scan")?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let (size, group) = line
+            .split_once('\t')
+            .and_then(|(size, group)| Some((size.trim().parse::<u64>().ok()?, group)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "line {}: expected '<SIZE>\\t<GROUP>', got '{}'",
+                    idx + 1,
+                    line
+                )
+            })?;
+        groups.insert(group, size);
+    }
+    Ok(groups)
+}
+
+/// How a group changed since the saved scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Same,
+    /// Grew by this percentage, rounded
+    Grew(u64),
+    /// Shrank by this percentage, rounded
+    Shrank(u64),
+    /// Not in the saved scan, or empty in it
+    New,
+    /// Only in the saved scan
+    Removed,
+}
+
+impl Trend {
+    pub fn new(old: Option<u64>, new: Option<u64>) -> Self {
+        match (old, new) {
+            (Some(_), None) => Trend::Removed,
+            (None, Some(_)) | (Some(0), Some(1..)) => Trend::New,
+            (Some(old), Some(new)) if new > old => Trend::Grew(percent(new - old, old)),
+            (Some(old), Some(new)) if new < old => Trend::Shrank(percent(old - new, old)),
+            _ => Trend::Same,
+        }
+    }
+}
+
+fn percent(change: u64, of: u64) -> u64 {
+    (change as f64 / of as f64 * 100.0).round() as u64
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trend::Same => write!(f, "="),
+            Trend::Grew(percent) => write!(f, "↑ +{percent}%"),
+            Trend::Shrank(percent) => write!(f, "↓ -{percent}%"),
+            Trend::New => write!(f, "new"),
+            Trend::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trends_compare_with_the_saved_size() {
+        assert_eq!(Trend::new(Some(100), Some(112)), Trend::Grew(12));
+        assert_eq!(Trend::new(Some(100), Some(96)), Trend::Shrank(4));
+        assert_eq!(Trend::new(Some(100), Some(100)), Trend::Same);
+        assert_eq!(Trend::new(None, Some(5)), Trend::New);
+        assert_eq!(Trend::new(Some(0), Some(5)), Trend::New);
+        assert_eq!(Trend::new(Some(5), None), Trend::Removed);
+
+        assert_eq!(Trend::Grew(12).to_string(), "↑ +12%");
+        assert_eq!(Trend::Shrank(4).to_string(), "↓ -4%");
+    }
+
+    #[test]
+    fn saved_groups_are_read_back() {
+        let mut result = ScanResult::default();
+        result.sizes.insert("jpg", 350);
+        result.sizes.insert("", 7);
+        result.sizes.insert("two\nlines", 1);
+        result.sizes.insert("tab\tin name", 2);
+
+        let mut saved = Vec::new();
+        write_groups(&mut saved, &result).unwrap();
+        let groups = read_groups(saved.as_slice()).unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups["jpg"], 350);
+        assert_eq!(groups[""], 7);
+        assert_eq!(groups["tab\tin name"], 2);
+
+        let err = read_groups("350 jpg".as_bytes()).unwrap_err();
+        assert!(err.to_string().starts_with("line 1:"), "{err}");
+    }
+}
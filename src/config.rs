@@ -0,0 +1,703 @@
+//! Default options read from the config files, from `FSS_<OPTION>` environment variables and
+//! from the flags in `FSS_OPTS`.
+//!
+//! The config file is a small subset of TOML: one `option = value` line per option, where the
+//! option is named like its long flag, with '_' or '-', and the value is a string, a number, a
+//! boolean or a one-line array. Comments start with '#'.
+//!
+//! The defaults are turned into flags put before the ones on the command line. An option given
+//! on the command line drops its default, and so does any option conflicting with it, so the
+//! precedence is built-in < user file < project file < `FSS_<OPTION>` < `FSS_OPTS` < command
+//! line.
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow, bail};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory};
+
+use crate::cli::Cli;
+
+/// Options that make no sense as defaults
+const NOT_CONFIGURABLE: &[&str] = &["inputs", "config", "no_config", "help", "version"];
+
+/// Options naming a file to read or write, which the project file can't set: it comes with
+/// whatever directory fss is run in, which may be a checkout nobody reviewed
+const NOT_PROJECT_CONFIGURABLE: &[&str] = &[
+    "relative_to",
+    "exclude_mount",
+    "save_groups",
+    "save_snapshot",
+    "compare",
+    "files_from",
+    "files0_from",
+    "error_log",
+    "from_inventory",
+];
+
+const ENV_PREFIX: &str = "FSS_";
+
+/// Name of the project config file, looked for in the current directory and its ancestors
+const PROJECT_FILE: &str = ".fss.toml";
+
+/// Default flags, split like a shell would, e.g. `FSS_OPTS="-s binary --exclude-ext tmp,log"`
+const OPTS_VAR: &str = "FSS_OPTS";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bool(bool),
+    /// A string, or a number kept as written
+    Scalar(String),
+    List(Vec<String>),
+}
+
+/// The default of the option `key`, and where it was set
+struct Setting {
+    key: String,
+    value: Value,
+    origin: String,
+}
+
+/// The usual config file: `fss/config.toml` in the config directory of the platform
+pub fn default_path() -> Option<PathBuf> {
+    let env_dir = |var| {
+        std::env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+    };
+    let home = || env_dir(if cfg!(windows) { "USERPROFILE" } else { "HOME" });
+    let dir = if let Some(dir) = env_dir("XDG_CONFIG_HOME") {
+        dir
+    } else if cfg!(windows) {
+        env_dir("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        home()?.join("Library/Application Support")
+    } else {
+        home()?.join(".config")
+    };
+    Some(dir.join("fss").join("config.toml"))
+}
+
+/// The project config file: `.fss.toml` in `dir` or in its closest ancestor that has one
+fn project_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The command line `args` with the defaults of the config files and the environment put in
+/// front. The arguments are returned as they are when they don't parse, for clap to report it.
+pub fn with_defaults(args: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let Ok(matches) = Cli::command().try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    if matches.get_flag("no_config") {
+        return Ok(args);
+    }
+    let mut files = Vec::new();
+    match matches.get_one::<PathBuf>("config") {
+        Some(path) => files.push((path.clone(), read(path)?)),
+        // A missing usual file is not an error, nobody asked for it
+        None => files.extend(default_path().and_then(|path| {
+            let text = std::fs::read_to_string(&path).ok()?;
+            Some((path, text))
+        })),
+    }
+    let cwd = std::env::current_dir().ok();
+    if let Some(path) = cwd.as_deref().and_then(project_path) {
+        let text = read(&path)?;
+        check_project(&text, &path.display().to_string())?;
+        files.push((path, text));
+    }
+    let files: Vec<(&Path, &str)> = files
+        .iter()
+        .map(|(path, text)| (path.as_path(), text.as_str()))
+        .collect();
+    merge(args, &matches, &files, |var| std::env::var(var).ok())
+}
+
+/// Refuse the options of the project file `origin` that it can't set
+fn check_project(text: &str, origin: &str) -> anyhow::Result<()> {
+    for setting in parse(text, origin)? {
+        if NOT_PROJECT_CONFIGURABLE.contains(&setting.key.as_str()) {
+            bail!(
+                "{}: '{}' names a file, which {PROJECT_FILE} can't set, set it in the user \
+                 config file, in {ENV_PREFIX}{} or on the command line",
+                setting.origin,
+                setting.key,
+                setting.key.to_uppercase()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("could not read config file '{}'", path.display()))
+}
+
+/// Put the defaults of `files` and `env` in front of `args`, which parsed into `matches`. An
+/// option set in several files takes the value of the last one.
+fn merge(
+    args: Vec<OsString>,
+    matches: &ArgMatches,
+    files: &[(&Path, &str)],
+    env: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<Vec<OsString>> {
+    let mut defaults: Vec<Setting> = Vec::new();
+    for (path, text) in files {
+        for setting in parse(text, &path.display().to_string())? {
+            defaults.retain(|default| default.key != setting.key);
+            defaults.push(setting);
+        }
+    }
+    for id in configurable() {
+        let var = format!("{ENV_PREFIX}{}", id.to_ascii_uppercase());
+        if let Some(value) = env(&var) {
+            defaults.retain(|setting| setting.key != id);
+            defaults.push(Setting {
+                key: id,
+                value: Value::Scalar(value),
+                origin: var,
+            });
+        }
+    }
+    if let Some(opts) = env(OPTS_VAR) {
+        for setting in parse_opts(&opts)? {
+            defaults.retain(|default| default.key != setting.key);
+            defaults.push(setting);
+        }
+    }
+
+    let command = Cli::command();
+    let given: Vec<&clap::Arg> = command
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .collect();
+    let mut merged = vec![args.first().cloned().unwrap_or_else(|| "fss".into())];
+    for setting in defaults {
+        let overridden = given.iter().any(|arg| {
+            arg.get_id() == setting.key.as_str()
+                || command
+                    .get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == setting.key.as_str())
+        });
+        if !overridden {
+            merged.extend(to_flags(&setting)?);
+        }
+    }
+    merged.extend(args.into_iter().skip(1));
+    Ok(merged)
+}
+
+/// Read the flags of `FSS_OPTS` as settings, so that they are checked and overridden like the
+/// other defaults
+fn parse_opts(opts: &str) -> anyhow::Result<Vec<Setting>> {
+    let invalid = |reason: &str| anyhow!("{OPTS_VAR}: {reason}");
+    let words = split_shell(opts).map_err(|err| invalid(&err))?;
+    let matches = Cli::command()
+        .try_get_matches_from(std::iter::once("fss".to_string()).chain(words))
+        .map_err(|err| invalid(&clap_reason(&err)))?;
+
+    let mut settings = Vec::new();
+    for arg in Cli::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if NOT_CONFIGURABLE.contains(&id) {
+            return Err(match arg.get_long() {
+                Some(long) => invalid(&format!("'--{long}' can only be given on the command line")),
+                None => invalid("the inputs can only be given on the command line"),
+            });
+        }
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => Value::Bool(true),
+            ArgAction::Count => Value::Scalar(matches.get_count(id).to_string()),
+            action => {
+                let mut values = matches
+                    .get_raw(id)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| value.to_string_lossy().into_owned());
+                if let ArgAction::Append = action {
+                    Value::List(values.collect())
+                } else {
+                    Value::Scalar(values.next().unwrap_or_default())
+                }
+            }
+        };
+        settings.push(Setting {
+            key: id.to_string(),
+            value,
+            origin: OPTS_VAR.to_string(),
+        });
+    }
+    Ok(settings)
+}
+
+/// Split `text` into words like a POSIX shell: words are separated by whitespace, can be quoted
+/// with '' or "", and a backslash escapes the next character outside of single quotes
+fn split_shell(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or("nothing to escape after the last '\\'")?;
+                word.get_or_insert_default().push(escaped);
+            }
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // Elsewhere the backslash stays, like in a shell
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => word.extend(['\\', c]),
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// The options that can have a default
+fn configurable() -> Vec<String> {
+    Cli::command()
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some())
+        .map(|arg| arg.get_id().to_string())
+        .filter(|id| !NOT_CONFIGURABLE.contains(&id.as_str()))
+        .collect()
+}
+
+/// The flags giving `setting`, checked on their own so that a bad value is blamed on its key
+fn to_flags(setting: &Setting) -> anyhow::Result<Vec<OsString>> {
+    let command = Cli::command();
+    let arg = command
+        .get_arguments()
+        .find(|arg| arg.get_id() == setting.key.as_str())
+        .expect("configurable options are arguments");
+    let long = format!(
+        "--{}",
+        arg.get_long().expect("configurable options are long")
+    );
+    let invalid = |reason: &str| {
+        anyhow!(
+            "{}: invalid value for '{}': {reason}",
+            setting.origin,
+            setting.key
+        )
+    };
+
+    let flags: Vec<String> = match (arg.get_action(), &setting.value) {
+        (ArgAction::SetTrue, value) => match parse_bool(value) {
+            Some(true) => vec![long],
+            Some(false) => vec![],
+            None => return Err(invalid("expected true or false")),
+        },
+        (ArgAction::Count, Value::Scalar(n)) => {
+            let n: usize = n.parse().map_err(|_| invalid("expected a number"))?;
+            vec![long; n]
+        }
+        (ArgAction::Append, Value::List(values)) => values
+            .iter()
+            .map(|value| format!("{long}={value}"))
+            .collect(),
+        (_, Value::Scalar(value)) => vec![format!("{long}={value}")],
+        (_, Value::Bool(_)) => return Err(invalid("expected a value, not true or false")),
+        (_, Value::List(_)) => return Err(invalid("expected a single value")),
+    };
+
+    if let Err(err) = Cli::command()
+        .try_get_matches_from(std::iter::once("fss").chain(flags.iter().map(String::as_str)))
+    {
+        return Err(invalid(&clap_reason(&err)));
+    }
+    Ok(flags.into_iter().map(OsString::from).collect())
+}
+
+fn parse_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::Scalar(s) => match s.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" => Some(true),
+            "false" | "0" | "no" => Some(false),
+            _ => None,
+        },
+        Value::List(_) => None,
+    }
+}
+
+/// The first line of a clap error, without its 'error: ' prefix
+fn clap_reason(err: &clap::Error) -> String {
+    let text = err.render().to_string();
+    let line = text.lines().next().unwrap_or_default();
+    line.strip_prefix("error: ").unwrap_or(line).to_string()
+}
+
+/// Read the `option = value` lines of a config file called `origin`
+fn parse(text: &str, origin: &str) -> anyhow::Result<Vec<Setting>> {
+    let keys = configurable();
+    let mut defaults: Vec<Setting> = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let at = format!("{origin}:{}", idx + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            bail!("{at}: tables are not supported, options go at the top level");
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("{at}: expected 'option = value', got '{line}'"))?;
+        let key = key.trim().replace('-', "_");
+        if !keys.contains(&key) {
+            bail!("{at}: unknown option '{key}'");
+        }
+        let value = parse_value(value.trim()).map_err(|err| anyhow!("{at}: '{key}': {err}"))?;
+        defaults.retain(|setting| setting.key != key);
+        defaults.push(Setting {
+            key,
+            value,
+            origin: at,
+        });
+    }
+    Ok(defaults)
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let (value, rest) = if let Some(list) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        let mut rest = list.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                break (Value::List(values), after);
+            }
+            let (value, after) = parse_scalar(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err("expected ',' or ']' in the array".to_string());
+            }
+        }
+    } else {
+        let quoted = text.starts_with(['"', '\'']);
+        match parse_scalar(text)? {
+            (s, rest) if s == "true" && !quoted => (Value::Bool(true), rest),
+            (s, rest) if s == "false" && !quoted => (Value::Bool(false), rest),
+            (s, rest) => (Value::Scalar(s), rest),
+        }
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected '{rest}' after the value"));
+    }
+    Ok(value)
+}
+
+/// Read a string or a bare value at the start of `text`, returning the rest
+fn parse_scalar(text: &str) -> Result<(String, &str), String> {
+    if let Some(literal) = text.strip_prefix('\'') {
+        let end = literal.find('\'').ok_or("unterminated string")?;
+        return Ok((literal[..end].to_string(), &literal[end + 1..]));
+    }
+    if let Some(basic) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = basic.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => return Ok((value, &basic[idx + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(c) => return Err(format!("unknown escape '\\{c}'")),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+        .unwrap_or(text.len());
+    if end == 0 {
+        return Err("missing value".to_string());
+    }
+    // Numbers may be written 1_000 in TOML
+    Ok((text[..end].replace('_', ""), &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{FormatOption, GroupBy};
+    use clap::FromArgMatches;
+
+    /// Parse `args` with the defaults of the config file `text` and the environment `env`
+    fn parse_with(args: &[&str], text: Option<&str>, env: &[(&str, &str)]) -> anyhow::Result<Cli> {
+        let files: Vec<(&Path, &str)> = text
+            .map(|text| (Path::new("config.toml"), text))
+            .into_iter()
+            .collect();
+        parse_with_files(args, &files, env)
+    }
+
+    /// Like [`parse_with`], with the defaults of several config files
+    fn parse_with_files(
+        args: &[&str],
+        files: &[(&Path, &str)],
+        env: &[(&str, &str)],
+    ) -> anyhow::Result<Cli> {
+        let args: Vec<OsString> = std::iter::once("fss")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let matches = Cli::command().try_get_matches_from(&args).unwrap();
+        let env = |var: &str| {
+            env.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        };
+        let merged = merge(args, &matches, files, env)?;
+        let matches = Cli::command().try_get_matches_from(merged)?;
+        Ok(Cli::from_arg_matches(&matches)?)
+    }
+
+    #[test]
+    fn flags_win_over_the_environment_over_the_file() {
+        let file = "size_format = \"binary\"\ngroup-by = 'type'\nthreads = 8 # fixed\n";
+
+        let cli = parse_with(&[], None, &[]).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Decimal);
+        assert_eq!(cli.group_by, [GroupBy::Extension]);
+
+        let cli = parse_with(&[], Some(file), &[]).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Binary);
+        assert_eq!(cli.group_by, [GroupBy::Type]);
+        assert_eq!(cli.threads, 8);
+
+        let env = [("FSS_GROUP_BY", "directory"), ("FSS_THREADS", "2")];
+        let cli = parse_with(&[], Some(file), &env).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Binary);
+        assert_eq!(cli.group_by, [GroupBy::Directory]);
+        assert_eq!(cli.threads, 2);
+
+        let cli = parse_with(&["-g", "f", "-j", "1"], Some(file), &env).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Binary);
+        assert_eq!(cli.group_by, [GroupBy::FileName]);
+        assert_eq!(cli.threads, 1);
+
+        let cli = parse_with(&[], Some("group_by = [\"e\", \"t\"]"), &[]).unwrap();
+        assert_eq!(cli.group_by, [GroupBy::Extension, GroupBy::Type]);
+    }
+
+    #[test]
+    fn the_project_file_wins_over_the_user_file() {
+        let user = (
+            Path::new("config.toml"),
+            "size_format = 'binary'\ngroup_by = 'type'\nthreads = 8",
+        );
+        let project = (
+            Path::new(".fss.toml"),
+            "group_by = 'directory'\nthreads = 4",
+        );
+
+        let cli = parse_with_files(&[], &[user, project], &[]).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Binary);
+        assert_eq!(cli.group_by, [GroupBy::Directory]);
+        assert_eq!(cli.threads, 4);
+
+        let env = [("FSS_THREADS", "2")];
+        let cli = parse_with_files(&["-g", "f"], &[user, project], &env).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Binary);
+        assert_eq!(cli.group_by, [GroupBy::FileName]);
+        assert_eq!(cli.threads, 2);
+
+        let message = parse_with_files(&[], &[user, (Path::new(".fss.toml"), "thread = 1")], &[])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(message, ".fss.toml:1: unknown option 'thread'");
+    }
+
+    #[test]
+    fn the_project_file_cant_name_files() {
+        assert!(check_project("group_by = 'type'\nthreads = 4", ".fss.toml").is_ok());
+        let message = check_project("threads = 4\nsave-groups = '/tmp/x'", ".fss.toml")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            message,
+            ".fss.toml:2: 'save_groups' names a file, which .fss.toml can't set, set it in the \
+             user config file, in FSS_SAVE_GROUPS or on the command line"
+        );
+        for key in NOT_PROJECT_CONFIGURABLE {
+            assert!(configurable().contains(&key.to_string()), "{key}");
+        }
+    }
+
+    #[test]
+    fn the_project_file_is_looked_for_in_the_ancestors() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(project_path(&nested), None);
+
+        std::fs::write(dir.path().join(PROJECT_FILE), "").unwrap();
+        assert_eq!(project_path(&nested), Some(dir.path().join(PROJECT_FILE)));
+
+        std::fs::write(nested.join(PROJECT_FILE), "").unwrap();
+        assert_eq!(project_path(&nested), Some(nested.join(PROJECT_FILE)));
+    }
+
+    #[test]
+    fn flags_drop_the_defaults_they_conflict_with() {
+        let file = "compact = true\nexclude_ext = [\"tmp\", \"log\"]\nskip_system = true\n";
+        let cli = parse_with(&[], Some(file), &[]).unwrap();
+        assert!(cli.compact);
+        assert_eq!(cli.exclude_ext, ["tmp", "log"]);
+
+        let cli = parse_with(
+            &["--chart", "--exclude-ext", "bak", "--count-system"],
+            Some(file),
+            &[],
+        )
+        .unwrap();
+        assert!(!cli.compact);
+        assert_eq!(cli.exclude_ext, ["bak"]);
+        assert!(cli.count_system && !cli.skip_system);
+
+        let cli = parse_with(&[], Some("verbose = 2\napparent_size = false"), &[]).unwrap();
+        assert_eq!(cli.verbose, 2);
+        assert!(!cli.apparent_size);
+    }
+
+    #[test]
+    fn words_are_split_like_a_shell() {
+        assert_eq!(
+            split_shell(
+                r#"  -s binary --separator ' | '  --system-names="Thumbs.db,My Files" a\ b"#
+            )
+            .unwrap(),
+            [
+                "-s",
+                "binary",
+                "--separator",
+                " | ",
+                "--system-names=Thumbs.db,My Files",
+                "a b"
+            ]
+        );
+        assert_eq!(
+            split_shell(r#""a\"b\n" '' x"#).unwrap(),
+            ["a\"b\\n", "", "x"]
+        );
+        assert_eq!(split_shell("").unwrap(), Vec::<String>::new());
+        assert!(split_shell("--separator 'oops").is_err());
+        assert!(split_shell("--separator \"oops").is_err());
+    }
+
+    #[test]
+    fn fss_opts_come_after_the_other_defaults() {
+        let opts = [("FSS_OPTS", "-s bytes -vv --exclude-ext 'tmp,log' -c")];
+        let cli = parse_with(&[], Some("size_format = 'binary'\nthreads = 3"), &opts).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Bytes);
+        assert_eq!(cli.threads, 3);
+        assert_eq!(cli.verbose, 2);
+        assert_eq!(cli.exclude_ext, ["tmp", "log"]);
+        assert!(cli.compact);
+
+        let cli = parse_with(&["-s", "decimal", "--chart"], None, &opts).unwrap();
+        assert_eq!(cli.size_format, FormatOption::Decimal);
+        assert!(!cli.compact);
+        assert_eq!(cli.chart, Some(10));
+    }
+
+    #[test]
+    fn errors_name_the_option() {
+        let err = |text, env: &[(&str, &str)]| {
+            parse_with(&[], Some(text), env)
+                .err()
+                .map(|err| err.to_string())
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            err("# fine\n\nsize_fromat = \"binary\"", &[]),
+            "config.toml:3: unknown option 'size_fromat'"
+        );
+        let message = err("threads = 8\nsize_format = \"huge\"", &[]);
+        assert!(
+            message.starts_with(
+                "config.toml:2: invalid value for 'size_format': invalid value 'huge'"
+            ),
+            "{message}"
+        );
+        assert_eq!(
+            err("compact = \"yes please\"", &[]),
+            "config.toml:1: invalid value for 'compact': expected true or false"
+        );
+        assert_eq!(
+            err("size_format = [\"binary\"]", &[]),
+            "config.toml:1: invalid value for 'size_format': expected a single value"
+        );
+        assert_eq!(
+            err("group_by = \"type", &[]),
+            "config.toml:1: 'group_by': unterminated string"
+        );
+        assert_eq!(
+            err("[output]", &[]),
+            "config.toml:1: tables are not supported, options go at the top level"
+        );
+        assert_eq!(
+            err("", &[("FSS_OPTS", "-s binary --exclude .git")]),
+            "FSS_OPTS: unexpected argument '--exclude' found"
+        );
+        assert_eq!(
+            err("", &[("FSS_OPTS", "-s binary src")]),
+            "FSS_OPTS: the inputs can only be given on the command line"
+        );
+        assert_eq!(
+            err("", &[("FSS_OPTS", "--no-config")]),
+            "FSS_OPTS: '--no-config' can only be given on the command line"
+        );
+        assert_eq!(
+            err("", &[("FSS_OPTS", "--separator 'oops")]),
+            "FSS_OPTS: unterminated single quote"
+        );
+        let message = err("", &[("FSS_THREADS", "many")]);
+        assert!(
+            message.starts_with("FSS_THREADS: invalid value for 'threads'"),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Finding files with the same contents, for `--duplicates`.
+//!
+//! Only files of the same length can be identical, so the files are first bucketed by length,
+//! which the walk already measured. The files sharing a length are then hashed with BLAKE3, on
+//! as many threads as the walk used. Files that can't be read any more, deleted during the scan
+//! for instance, are left out.
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use fnv::FnvHashMap;
+use rayon::prelude::*;
+
+use crate::group_map::GroupMap;
+
+/// A file that may have copies
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Length of the contents, the apparent size
+    pub len: u64,
+    /// Size as counted in the groups
+    pub size: u64,
+    pub path: PathBuf,
+    pub group: String,
+}
+
+/// Files with the same contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSet {
+    /// Size of each copy, as counted in the groups
+    pub size: u64,
+    /// Sorted, so that the output doesn't depend on the order of the walk
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateSet {
+    /// The bytes that removing every copy but one would free
+    pub fn wasted(&self) -> u64 {
+        self.size
+            .saturating_mul(self.paths.len().saturating_sub(1) as u64)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    /// The sets of identical files, least wasteful first like the groups
+    pub sets: Vec<DuplicateSet>,
+    pub wasted: u64,
+    /// The wasted bytes of each group, a set being in the group of its first file
+    pub wasted_by_group: GroupMap<u64>,
+    /// Files that could not be read to be hashed
+    pub unreadable: u64,
+}
+
+/// Find the sets of identical files among `candidates`, hashing them on `num_threads` threads.
+/// Once `cancel` is set, the files not hashed yet are left out, without counting as unreadable.
+pub fn find_duplicates(
+    candidates: Vec<Candidate>,
+    num_threads: usize,
+    cancel: &AtomicBool,
+) -> io::Result<Duplicates> {
+    let mut by_len: FnvHashMap<u64, Vec<Candidate>> = FnvHashMap::default();
+    for candidate in candidates {
+        // Empty files are all the same, and waste nothing
+        if candidate.len > 0 {
+            by_len.entry(candidate.len).or_default().push(candidate);
+        }
+    }
+    let to_hash: Vec<Candidate> = by_len
+        .into_values()
+        .filter(|same_len| same_len.len() > 1)
+        .flatten()
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(io::Error::other)?;
+    let hashes: Vec<Option<io::Result<blake3::Hash>>> = pool.install(|| {
+        to_hash
+            .par_iter()
+            .map(|c| (!cancel.load(Ordering::Relaxed)).then(|| hash_file(&c.path)))
+            .collect()
+    });
+
+    let mut duplicates = Duplicates::default();
+    let mut by_hash: FnvHashMap<(u64, [u8; 32]), Vec<Candidate>> = FnvHashMap::default();
+    for (candidate, hash) in to_hash.into_iter().zip(hashes) {
+        match hash {
+            Some(Ok(hash)) => by_hash
+                .entry((candidate.len, *hash.as_bytes()))
+                .or_default()
+                .push(candidate),
+            Some(Err(_)) => duplicates.unreadable += 1,
+            None => {}
+        }
+    }
+
+    for mut copies in by_hash.into_values().filter(|copies| copies.len() > 1) {
+        copies.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        let set = DuplicateSet {
+            size: copies[0].size,
+            paths: copies.iter().map(|copy| copy.path.clone()).collect(),
+        };
+        let wasted = set.wasted();
+        duplicates.wasted = duplicates.wasted.saturating_add(wasted);
+        match duplicates.wasted_by_group.get_mut(&copies[0].group) {
+            Some(sum) => *sum = sum.saturating_add(wasted),
+            None => {
+                duplicates.wasted_by_group.insert(&copies[0].group, wasted);
+            }
+        }
+        duplicates.sets.push(set);
+    }
+    duplicates.sets.sort_unstable_by(|a, b| {
+        a.wasted()
+            .cmp(&b.wasted())
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    Ok(duplicates)
+}
+
+fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn identical_files_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidate = |name: &str, contents: &str, group: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            Candidate {
+                len: contents.len() as u64,
+                size: 4096,
+                path,
+                group: group.to_string(),
+            }
+        };
+        let mut candidates = vec![
+            candidate("a.jpg", "same photo", "jpg"),
+            candidate("b.jpg", "same photo", "jpg"),
+            candidate("c.jpeg", "same photo", "jpeg"),
+            // Same length, other contents
+            candidate("d.jpg", "else photo", "jpg"),
+            candidate("e.txt", "notes", "txt"),
+            candidate("f.txt", "notes", "txt"),
+            candidate("g.txt", "", "txt"),
+            candidate("h.txt", "", "txt"),
+        ];
+        // Deleted since the walk
+        let vanished = candidate("i.jpg", "same photo", "jpg");
+        fs::remove_file(&vanished.path).unwrap();
+        candidates.push(vanished);
+
+        let cancelled = find_duplicates(candidates.clone(), 2, &AtomicBool::new(true)).unwrap();
+        assert!(cancelled.sets.is_empty());
+        assert_eq!(cancelled.unreadable, 0);
+
+        let duplicates = find_duplicates(candidates, 2, &AtomicBool::new(false)).unwrap();
+        let names = |set: &DuplicateSet| -> Vec<String> {
+            let names = set.paths.iter().map(|p| p.file_name().unwrap());
+            names.map(|n| n.to_string_lossy().into_owned()).collect()
+        };
+        assert_eq!(duplicates.sets.len(), 2);
+        assert_eq!(names(&duplicates.sets[0]), ["e.txt", "f.txt"]);
+        assert_eq!(names(&duplicates.sets[1]), ["a.jpg", "b.jpg", "c.jpeg"]);
+        assert_eq!(duplicates.sets[1].wasted(), 8192);
+        assert_eq!(duplicates.wasted, 12288);
+        assert_eq!(duplicates.wasted_by_group["jpg"], 8192);
+        assert_eq!(duplicates.wasted_by_group["txt"], 4096);
+        assert_eq!(duplicates.unreadable, 1);
+    }
+}
@@ -0,0 +1,102 @@
+//! The error log of `--error-log`, where every error of the walk is appended as it happens.
+//!
+//! Each error is a `<TIMESTAMP>\t<KIND>\t<PATH>` line, with the time in UTC like
+//! `2024-02-29T23:59:59Z` and the kinds of `--errors json`. The errors written to the log are
+//! not kept in memory, only counted by kind.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::aggregate::path_str;
+use crate::groups::{DAY, civil_date, unix_secs};
+use crate::long_path;
+use crate::walk::Error;
+
+pub struct ErrorLog {
+    out: BufWriter<File>,
+    /// The first write that failed, the next ones are not attempted
+    failure: Option<io::Error>,
+}
+
+impl ErrorLog {
+    /// Open `path` to append to it, creating it if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ErrorLog {
+            out: BufWriter::new(file),
+            failure: None,
+        })
+    }
+
+    pub fn log(&mut self, error: &Error) {
+        if self.failure.is_some() {
+            return;
+        }
+        let path = long_path::strip_extended(error.path());
+        if let Err(err) = writeln!(
+            self.out,
+            "{}\t{}\t{}",
+            timestamp(SystemTime::now()),
+            error.kind().id(),
+            path_str(&path).replace(['\t', '\n'], " ")
+        ) {
+            self.failure = Some(err);
+        }
+    }
+
+    /// Flush the log, returning the first error that happened while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.failure.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// `time` in UTC, to the second, as RFC 3339
+pub fn timestamp(time: SystemTime) -> String {
+    let (year, month, day) = civil_date(time);
+    let secs = unix_secs(time).rem_euclid(DAY as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn errors_are_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("errors.log");
+        std::fs::write(&path, "earlier\n").unwrap();
+
+        let mut log = ErrorLog::open(&path).unwrap();
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        log.log(&Error::CouldNotReadDir(PathBuf::from("/var/lib"), denied));
+        log.log(&Error::ChangedDuringScan(PathBuf::from("/tmp/a\tb")));
+        log.finish().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Vec<&str>> = contents.lines().map(|l| l.split('\t').collect()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], ["earlier"]);
+        assert_eq!(lines[1][1..], ["permission_denied", "/var/lib"]);
+        assert_eq!(lines[2][1..], ["changed", "/tmp/a b"]);
+        assert!(lines[1][0].ends_with('Z'));
+    }
+
+    #[test]
+    fn timestamps_are_in_utc() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(timestamp(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(at(1_709_251_199)), "2024-02-29T23:59:59Z");
+    }
+}
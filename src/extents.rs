@@ -0,0 +1,146 @@
+//! btrfs and XFS share data between files cloned with reflinks (`cp --reflink`), and `st_blocks`
+//! counts the shared data once per file. The FIEMAP ioctl lists the extents of a file and flags
+//! the shared ones, which tells how much of a file is really its own. It costs an open and an
+//! ioctl per file, so it is only done on request.
+use std::io;
+use std::path::Path;
+
+/// Extents requested per ioctl
+#[cfg(target_os = "linux")]
+const EXTENTS_PER_CALL: usize = 64;
+
+/// `_IOWR('f', 11, struct fiemap)`
+#[cfg(target_os = "linux")]
+const FS_IOC_FIEMAP: u64 = 0xC020_660B;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const FIEMAP_EXTENT_LAST: u32 = 0x0000_0001;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const FIEMAP_EXTENT_SHARED: u32 = 0x0000_2000;
+
+/// `struct fiemap_extent` from linux/fiemap.h
+#[allow(dead_code)] // Filled by the kernel
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct FiemapExtent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+/// `struct fiemap` from linux/fiemap.h, followed by room for the extents
+#[cfg(target_os = "linux")]
+#[allow(dead_code)] // Read by the kernel
+#[repr(C)]
+struct Fiemap {
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+    extents: [FiemapExtent; EXTENTS_PER_CALL],
+}
+
+/// Sum of the lengths of the shared extents, and whether the last extent of the file is
+/// among them
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn sum_shared(extents: &[FiemapExtent]) -> (u64, bool) {
+    let shared = extents
+        .iter()
+        .filter(|e| e.flags & FIEMAP_EXTENT_SHARED != 0)
+        .map(|e| e.length)
+        .sum();
+    let last = extents.iter().any(|e| e.flags & FIEMAP_EXTENT_LAST != 0);
+    (shared, last)
+}
+
+/// Number of bytes of the file at `path` stored in extents shared with other files. Fails with
+/// [`io::ErrorKind::Unsupported`] where FIEMAP is not available.
+#[cfg(target_os = "linux")]
+pub fn shared_bytes(path: &Path) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    let mut shared = 0;
+    let mut start = 0;
+    loop {
+        let mut fiemap = Fiemap {
+            start,
+            length: u64::MAX - start,
+            flags: 0,
+            mapped_extents: 0,
+            extent_count: EXTENTS_PER_CALL as u32,
+            reserved: 0,
+            extents: [FiemapExtent::default(); EXTENTS_PER_CALL],
+        };
+        // SAFETY: `fiemap` is a valid `struct fiemap` with room for `extent_count` extents
+        let ret = unsafe {
+            libc::ioctl(
+                file.as_raw_fd(),
+                FS_IOC_FIEMAP as libc::Ioctl,
+                &mut fiemap as *mut Fiemap,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP | libc::ENOTTY) => io::ErrorKind::Unsupported.into(),
+                _ => err,
+            });
+        }
+
+        let extents = &fiemap.extents[..fiemap.mapped_extents as usize];
+        let (extents_shared, last) = sum_shared(extents);
+        shared += extents_shared;
+        match extents.last() {
+            Some(extent) if !last => start = extent.logical + extent.length,
+            _ => return Ok(shared),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn shared_bytes(_path: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(length: u64, flags: u32) -> FiemapExtent {
+        FiemapExtent {
+            length,
+            flags,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_shared_extents_are_summed() {
+        let extents = [
+            extent(4096, 0),
+            extent(8192, FIEMAP_EXTENT_SHARED),
+            extent(4096, FIEMAP_EXTENT_SHARED | FIEMAP_EXTENT_LAST),
+        ];
+        assert_eq!(sum_shared(&extents), (12288, true));
+        assert_eq!(sum_shared(&extents[..2]), (8192, false));
+        assert_eq!(sum_shared(&[]), (0, false));
+    }
+
+    #[test]
+    fn a_new_file_shares_nothing() {
+        use std::io::Write;
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[1; 100_000]).unwrap();
+        file.as_file().sync_all().unwrap();
+        match shared_bytes(file.path()) {
+            Ok(shared) => assert_eq!(shared, 0),
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+        }
+    }
+}
@@ -1,25 +1,382 @@
+/// Unit of `st_blocks`, which is always 512 bytes regardless of the filesystem block size,
+/// see stat(2)
+#[cfg(not(windows))]
+const STAT_BLOCK_SIZE: u64 = 512;
+
+use std::io;
+use std::path::Path;
+
 #[derive(Debug, Clone, Copy)]
 pub enum FilesizeType {
+    /// Space allocated on disk, like `du`
+    ///
+    /// The filesystem already accounts every file in whole allocation units, so `st_blocks`
+    /// includes the rounding up of partial blocks (a 1 byte file on ext4 uses 4096 bytes) and
+    /// excludes the holes of sparse files. Summing it per file gives the same number as `du`,
+    /// no further rounding is needed. Tiny files stored inline in the inode (btrfs, ext4 with
+    /// inline_data) legitimately use 0 blocks.
     DiskUsage,
+    /// Length of the file in bytes, like `du --apparent-size` or `ls -l`
     ApparentSize,
 }
 
 impl FilesizeType {
     #[cfg(not(windows))]
     #[inline]
-    pub fn size(self, metadata: &std::fs::Metadata) -> u64 {
+    pub fn size(self, _path: &Path, metadata: &std::fs::Metadata) -> u64 {
         use std::os::unix::fs::MetadataExt;
 
         match self {
             FilesizeType::ApparentSize => metadata.len(),
-            // block size is always 512 byte, see stat(2) manpage
-            FilesizeType::DiskUsage => metadata.blocks() * 512,
+            FilesizeType::DiskUsage => metadata.blocks() * STAT_BLOCK_SIZE,
         }
     }
 
+    /// On Windows, the disk usage is the compressed size (which also leaves out the holes of
+    /// sparse files) rounded up to whole clusters
     #[cfg(windows)]
     #[inline]
-    pub fn size(self, metadata: &std::fs::Metadata) -> u64 {
-        metadata.len()
+    pub fn size(self, path: &Path, metadata: &std::fs::Metadata) -> u64 {
+        match self {
+            FilesizeType::ApparentSize => metadata.len(),
+            FilesizeType::DiskUsage => {
+                let size = windows::compressed_size(path).unwrap_or(metadata.len());
+                match windows::cluster_size(path) {
+                    Some(cluster) => round_up(size, cluster),
+                    None => size,
+                }
+            }
+        }
+    }
+
+    /// Size of a cloud placeholder whose data is not on disk: nothing, unless the logical size
+    /// is requested
+    #[inline]
+    pub fn dehydrated_size(self, metadata: &std::fs::Metadata) -> u64 {
+        match self {
+            FilesizeType::ApparentSize => metadata.len(),
+            FilesizeType::DiskUsage => 0,
+        }
+    }
+}
+
+/// Both sizes of a file or group
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub apparent: u64,
+    pub allocated: u64,
+}
+
+impl Usage {
+    /// Whether the file takes less space than its length, because it is sparse or compressed
+    pub fn is_sparse(&self) -> bool {
+        self.allocated < self.apparent
+    }
+}
+
+/// `size` rounded up to a multiple of `unit`, or the largest multiple that fits in a u64
+pub fn round_up(size: u64, unit: u64) -> u64 {
+    let units = size.div_ceil(unit);
+    units.checked_mul(unit).unwrap_or((u64::MAX / unit) * unit)
+}
+
+/// Bytes stored beside the data of `path`: the values of its extended attributes, without
+/// following links. Resource forks are the `com.apple.ResourceFork` attribute, so they are
+/// included.
+#[cfg(target_os = "macos")]
+pub fn stream_size(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::ptr;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let names = loop {
+        // SAFETY: `path` is NUL terminated, a null buffer asks for the length of the list
+        let len =
+            unsafe { libc::listxattr(path.as_ptr(), ptr::null_mut(), 0, libc::XATTR_NOFOLLOW) };
+        if len <= 0 {
+            return if len == 0 {
+                Ok(0)
+            } else {
+                Err(io::Error::last_os_error())
+            };
+        }
+        let mut names = vec![0u8; len as usize];
+        // SAFETY: `names` has room for `names.len()` bytes
+        let len = unsafe {
+            libc::listxattr(
+                path.as_ptr(),
+                names.as_mut_ptr().cast(),
+                names.len(),
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        if len >= 0 {
+            names.truncate(len as usize);
+            break names;
+        }
+        let err = io::Error::last_os_error();
+        // Otherwise an attribute was added since the first call, try again
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    };
+
+    let mut total = 0;
+    // The list is made of NUL terminated names
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name)?;
+        // SAFETY: both strings are NUL terminated, a null buffer asks for the length of the value
+        let len = unsafe {
+            libc::getxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                ptr::null_mut(),
+                0,
+                0,
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        // An attribute removed since the listing has no size
+        if len > 0 {
+            total += len as u64;
+        }
+    }
+    Ok(total)
+}
+
+/// Bytes stored beside the data of `path`: the sizes of its alternate data streams
+#[cfg(windows)]
+pub fn stream_size(path: &Path) -> io::Result<u64> {
+    windows::alternate_streams_size(path)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn stream_size(_path: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::io;
+    use std::path::{Component, Path, PathBuf};
+    use std::sync::Mutex;
+
+    use fnv::FnvHashMap;
+    use lazy_static::lazy_static;
+    use windows_sys::Win32::Foundation::{
+        ERROR_HANDLE_EOF, GetLastError, INVALID_HANDLE_VALUE, NO_ERROR,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        GetCompressedFileSizeW, GetDiskFreeSpaceW, INVALID_FILE_SIZE, WIN32_FIND_STREAM_DATA,
+    };
+
+    use crate::long_path::to_wide;
+
+    lazy_static! {
+        /// Cluster size of each volume seen so far, `None` if it could not be read
+        static ref CLUSTER_SIZES: Mutex<FnvHashMap<PathBuf, Option<u64>>> =
+            Mutex::new(FnvHashMap::default());
+    }
+
+    /// Bytes actually stored for the file, which is less than its length if it is compressed
+    /// or sparse
+    pub fn compressed_size(path: &Path) -> Option<u64> {
+        let wide = to_wide(path);
+        let mut high = 0;
+        // SAFETY: `wide` is NUL terminated and `high` is a valid out pointer
+        let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+        // INVALID_FILE_SIZE is also a valid low word, the last error tells them apart
+        if low == INVALID_FILE_SIZE && unsafe { GetLastError() } != NO_ERROR {
+            return None;
+        }
+        Some(((high as u64) << 32) | low as u64)
+    }
+
+    /// Sum of the sizes of the named streams of `path`. The unnamed `::$DATA` stream holds the
+    /// data of the file, which is already counted.
+    pub fn alternate_streams_size(path: &Path) -> io::Result<u64> {
+        let wide = to_wide(path);
+        let mut data = WIN32_FIND_STREAM_DATA::default();
+        // SAFETY: `wide` is NUL terminated and `data` is the struct FindStreamInfoStandard fills
+        let handle = unsafe {
+            FindFirstStreamW(
+                wide.as_ptr(),
+                FindStreamInfoStandard,
+                (&raw mut data).cast(),
+                0,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = io::Error::last_os_error();
+            // Directories without streams and volumes that don't support them
+            return match err.raw_os_error() {
+                Some(code) if code as u32 == ERROR_HANDLE_EOF => Ok(0),
+                _ => Err(err),
+            };
+        }
+
+        let mut total = 0;
+        loop {
+            let name_len = data
+                .cStreamName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(data.cStreamName.len());
+            if String::from_utf16_lossy(&data.cStreamName[..name_len]) != "::$DATA" {
+                total += data.StreamSize.max(0) as u64;
+            }
+            // SAFETY: `handle` is a valid stream search handle
+            if unsafe { FindNextStreamW(handle, (&raw mut data).cast()) } == 0 {
+                break;
+            }
+        }
+        // SAFETY: `handle` is a valid search handle, closed only once
+        unsafe { FindClose(handle) };
+        Ok(total)
+    }
+
+    /// Cluster size of the volume holding `path`, queried once per volume. Volumes mounted in a
+    /// folder are attributed to the volume of their drive letter or share.
+    pub fn cluster_size(path: &Path) -> Option<u64> {
+        let root = volume_root(path)?;
+        let mut sizes = CLUSTER_SIZES.lock().unwrap();
+        *sizes
+            .entry(root)
+            .or_insert_with_key(|root| query_cluster_size(root))
+    }
+
+    /// `C:\`, `\\?\C:\` or `\\server\share\` for an absolute path
+    fn volume_root(path: &Path) -> Option<PathBuf> {
+        let mut components = path.components();
+        match (components.next(), components.next()) {
+            (Some(prefix @ Component::Prefix(_)), Some(Component::RootDir)) => {
+                Some([prefix, Component::RootDir].iter().collect())
+            }
+            _ => None,
+        }
+    }
+
+    fn query_cluster_size(root: &Path) -> Option<u64> {
+        let wide = to_wide(root);
+        let (mut sectors_per_cluster, mut bytes_per_sector) = (0, 0);
+        let (mut free_clusters, mut total_clusters) = (0, 0);
+        // SAFETY: `wide` is NUL terminated and every out pointer is valid
+        let ok = unsafe {
+            GetDiskFreeSpaceW(
+                wide.as_ptr(),
+                &mut sectors_per_cluster,
+                &mut bytes_per_sector,
+                &mut free_clusters,
+                &mut total_clusters,
+            )
+        };
+        let cluster = sectors_per_cluster as u64 * bytes_per_sector as u64;
+        (ok != 0 && cluster > 0).then_some(cluster)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn alternate_streams_are_summed() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("file.txt");
+            std::fs::write(&path, [1; 500]).unwrap();
+            assert_eq!(alternate_streams_size(&path).unwrap(), 0);
+
+            std::fs::write(dir.path().join("file.txt:stream"), [2; 1000]).unwrap();
+            std::fs::write(dir.path().join("file.txt:Zone.Identifier"), [3; 24]).unwrap();
+            assert_eq!(alternate_streams_size(&path).unwrap(), 1024);
+        }
+    }
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn sizes(file: &File) -> (u64, u64) {
+        let metadata = file.metadata().unwrap();
+        (
+            FilesizeType::DiskUsage.size(Path::new(""), &metadata),
+            FilesizeType::ApparentSize.size(Path::new(""), &metadata),
+        )
+    }
+
+    #[test]
+    fn disk_usage_covers_regular_files() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[1; 10_000]).unwrap();
+        file.sync_all().unwrap();
+
+        let (disk, apparent) = sizes(&file);
+        assert_eq!(apparent, 10_000);
+        assert!(disk >= apparent, "{disk} < {apparent}");
+        assert_eq!(disk % STAT_BLOCK_SIZE, 0);
+    }
+
+    #[test]
+    fn tiny_files_use_whole_blocks() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"x").unwrap();
+        file.sync_all().unwrap();
+
+        let (disk, apparent) = sizes(&file);
+        assert_eq!(apparent, 1);
+        assert_eq!(disk % STAT_BLOCK_SIZE, 0);
+        // 0 when the filesystem stores the data inline in the inode
+        assert!(disk == 0 || disk >= STAT_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn sparse_files_use_less_than_their_length() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(64 * 1024 * 1024).unwrap();
+
+        let (disk, apparent) = sizes(&file);
+        assert_eq!(apparent, 64 * 1024 * 1024);
+        assert!(disk < apparent, "{disk} >= {apparent}");
+    }
+
+    #[test]
+    fn rounds_up_to_whole_units() {
+        assert_eq!(round_up(0, 4096), 0);
+        assert_eq!(round_up(1, 4096), 4096);
+        assert_eq!(round_up(4096, 4096), 4096);
+        assert_eq!(round_up(4097, 4096), 8192);
+        assert_eq!(round_up(u64::MAX, 1 << 20), u64::MAX - ((1 << 20) - 1));
+        assert_eq!(round_up(u64::MAX, 1), u64::MAX);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn xattrs_add_their_values() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        // The system may have tagged the file already, e.g. with com.apple.provenance
+        let before = stream_size(file.path()).unwrap();
+
+        let path = CString::new(file.path().as_os_str().as_bytes()).unwrap();
+        let value = [7u8; 1000];
+        // SAFETY: both strings are NUL terminated and `value` is 1000 bytes long
+        let ret = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                c"com.example.fss".as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0, "{}", io::Error::last_os_error());
+        assert_eq!(stream_size(file.path()).unwrap() - before, 1000);
     }
 }
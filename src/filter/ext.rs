@@ -0,0 +1,77 @@
+use fnv::FnvHashSet;
+
+/// Keeps or drops files by their extension, spelled the way `-g extension` groups them
+#[derive(Debug, Clone, Default)]
+pub struct ExtFilter {
+    include: FnvHashSet<String>,
+    exclude: FnvHashSet<String>,
+}
+
+/// Lowercase an extension and drop its leading dot, so `.JPG` matches the files grouped as `jpg`
+fn normalize(ext: &str) -> String {
+    ext.strip_prefix('.').unwrap_or(ext).to_lowercase()
+}
+
+impl ExtFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        ExtFilter {
+            include: include.iter().map(|ext| normalize(ext)).collect(),
+            exclude: exclude.iter().map(|ext| normalize(ext)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether a file with the extension `ext` is counted. The included extensions are applied
+    /// first, then the excluded ones: an extension given to both is dropped.
+    pub fn keeps(&self, ext: &str) -> bool {
+        (self.include.is_empty() || self.include.contains(ext)) && !self.exclude.contains(ext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> ExtFilter {
+        let strings = |exts: &[&str]| exts.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        ExtFilter::new(&strings(include), &strings(exclude))
+    }
+
+    #[test]
+    fn no_extension_given_keeps_everything() {
+        let filter = filter(&[], &[]);
+        assert!(filter.is_empty());
+        assert!(filter.keeps("jpg"));
+        assert!(filter.keeps(""));
+    }
+
+    #[test]
+    fn included_extensions_are_the_only_ones_kept() {
+        let filter = filter(&["jpg", ".PNG"], &[]);
+        assert!(filter.keeps("jpg"));
+        assert!(filter.keeps("png"));
+        assert!(!filter.keeps("txt"));
+        assert!(!filter.keeps(""));
+    }
+
+    #[test]
+    fn excluded_extensions_are_dropped() {
+        let filter = filter(&[], &["log", "TMP"]);
+        assert!(!filter.keeps("log"));
+        assert!(!filter.keeps("tmp"));
+        assert!(filter.keeps("txt"));
+        assert!(filter.keeps(""));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = filter(&["jpg", "png"], &["png", "txt"]);
+        assert!(filter.keeps("jpg"));
+        assert!(!filter.keeps("png"));
+        assert!(!filter.keeps("txt"));
+        assert!(!filter.keeps("gif"));
+    }
+}
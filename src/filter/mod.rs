@@ -1,3 +1,7 @@
-pub use self::size::SizeFilter;
+pub use self::ext::ExtFilter;
+pub use self::size::{SizeFilter, parse_size};
+pub use self::system::SystemFilter;
 
+mod ext;
 mod size;
+pub mod system;
@@ -27,6 +27,31 @@ const MEBI: u64 = KIBI * 1024;
 const GIBI: u64 = MEBI * 1024;
 const TEBI: u64 = GIBI * 1024;
 
+/// Parse a size made of a number and a unit, e.g. `500k` or `1MiB`. Units are not
+/// case-sensitive. Sizes that don't fit in a u64 are rejected.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let pattern = SIZE_CAPTURES.get_or_init(|| Regex::new(r"(?i)^(\d+)(b|[kmgt]i?b?)$").unwrap());
+    let captures = pattern.captures(s)?;
+    let quantity = captures
+        .get(1)
+        .and_then(|v| v.as_str().parse::<u64>().ok())?;
+
+    let multiplier = match &captures.get(2).map_or("b", |m| m.as_str()).to_lowercase()[..] {
+        v if v.starts_with("ki") => KIBI,
+        v if v.starts_with('k') => KILO,
+        v if v.starts_with("mi") => MEBI,
+        v if v.starts_with('m') => MEGA,
+        v if v.starts_with("gi") => GIBI,
+        v if v.starts_with('g') => GIGA,
+        v if v.starts_with("ti") => TEBI,
+        v if v.starts_with('t') => TERA,
+        "b" => 1,
+        _ => return None,
+    };
+
+    quantity.checked_mul(multiplier)
+}
+
 impl SizeFilter {
     pub fn from_string(s: &str) -> anyhow::Result<Self> {
         SizeFilter::parse_opt(s)
@@ -34,37 +59,12 @@ impl SizeFilter {
     }
 
     fn parse_opt(s: &str) -> Option<Self> {
-        let pattern =
-            SIZE_CAPTURES.get_or_init(|| Regex::new(r"(?i)^([+-]?)(\d+)(b|[kmgt]i?b?)$").unwrap());
-        if !pattern.is_match(s) {
-            return None;
-        }
-
-        let captures = pattern.captures(s)?;
-        let limit_kind = captures.get(1).map_or("+", |m| m.as_str());
-        let quantity = captures
-            .get(2)
-            .and_then(|v| v.as_str().parse::<u64>().ok())?;
-
-        let multiplier = match &captures.get(3).map_or("b", |m| m.as_str()).to_lowercase()[..] {
-            v if v.starts_with("ki") => KIBI,
-            v if v.starts_with('k') => KILO,
-            v if v.starts_with("mi") => MEBI,
-            v if v.starts_with('m') => MEGA,
-            v if v.starts_with("gi") => GIBI,
-            v if v.starts_with('g') => GIGA,
-            v if v.starts_with("ti") => TEBI,
-            v if v.starts_with('t') => TERA,
-            "b" => 1,
-            _ => return None,
-        };
-
-        let size = quantity * multiplier;
-        match limit_kind {
-            "+" => Some(SizeFilter::Min(size)),
-            "-" => Some(SizeFilter::Max(size)),
-            "" => Some(SizeFilter::Equals(size)),
-            _ => None,
+        if let Some(size) = s.strip_prefix('+') {
+            Some(SizeFilter::Min(parse_size(size)?))
+        } else if let Some(size) = s.strip_prefix('-') {
+            Some(SizeFilter::Max(parse_size(size)?))
+        } else {
+            Some(SizeFilter::Equals(parse_size(s)?))
         }
     }
 
@@ -194,6 +194,8 @@ mod tests {
         ensure_invalid_unit_returns_none_3: "+1Mv",
         ensure_bib_format_returns_none: "+1bib",
         ensure_bb_format_returns_none: "+1bb",
+        ensure_double_sign_returns_none: "+-1k",
+        ensure_overflow_returns_none: "+99999999999t",
     }
 
     #[test]
@@ -0,0 +1,137 @@
+use std::ffi::OsStr;
+
+use fnv::FnvHashSet;
+
+/// Files and directories macOS leaves behind: Finder settings, Spotlight indexes, the trash of
+/// external volumes, and the `._` files holding metadata on filesystems without xattrs
+pub const MACOS_NAMES: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    ".Spotlight-V100",
+    ".Trashes",
+    ".fseventsd",
+    ".TemporaryItems",
+    ".DocumentRevisions-V100",
+    ".VolumeIcon.icns",
+    ".apdisk",
+];
+
+/// Files and directories Windows manages: thumbnail caches, folder settings, restore points, the
+/// recycle bin and the paging files
+pub const WINDOWS_NAMES: &[&str] = &[
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "System Volume Information",
+    "$RECYCLE.BIN",
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+];
+
+/// The system files of this platform. Elsewhere, the ones of both, which end up on shared
+/// drives.
+pub fn default_names() -> Vec<&'static str> {
+    if cfg!(target_os = "macos") {
+        MACOS_NAMES.to_vec()
+    } else if cfg!(windows) {
+        WINDOWS_NAMES.to_vec()
+    } else {
+        [MACOS_NAMES, WINDOWS_NAMES].concat()
+    }
+}
+
+/// Skips the files and directories managed by the OS, by name. Names are matched ignoring case,
+/// as macOS and Windows do, and a name ending with '*' matches every name starting with it.
+#[derive(Debug, Clone, Default)]
+pub struct SystemFilter {
+    names: FnvHashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl SystemFilter {
+    pub fn new<S: AsRef<str>>(names: &[S]) -> Self {
+        let mut filter = SystemFilter::default();
+        for name in names {
+            let name = name.as_ref().to_lowercase();
+            match name.strip_suffix('*') {
+                Some(prefix) => filter.prefixes.push(prefix.to_string()),
+                None => {
+                    filter.names.insert(name);
+                }
+            }
+        }
+        filter
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.prefixes.is_empty()
+    }
+
+    /// Whether the entry called `name` is skipped, with everything below it
+    pub fn skips(&self, name: &OsStr) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let Some(name) = name.to_str() else {
+            return false;
+        };
+        let name = name.to_lowercase();
+        self.names.contains(&name) || self.prefixes.iter().any(|p| name.starts_with(p.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skips(filter: &SystemFilter, name: &str) -> bool {
+        filter.skips(OsStr::new(name))
+    }
+
+    #[test]
+    fn default_names_are_skipped() {
+        let filter = SystemFilter::new(&[MACOS_NAMES, WINDOWS_NAMES].concat());
+        for name in [
+            ".DS_Store",
+            "._photo.jpg",
+            ".Spotlight-V100",
+            "Thumbs.db",
+            "thumbs.db",
+            "desktop.ini",
+            "System Volume Information",
+            "$Recycle.Bin",
+        ] {
+            assert!(skips(&filter, name), "{name}");
+        }
+        for name in [
+            "photo.jpg",
+            ".bashrc",
+            "_photo.jpg",
+            "Thumbs.db.bak",
+            ".DS_Store2",
+        ] {
+            assert!(!skips(&filter, name), "{name}");
+        }
+    }
+
+    #[test]
+    fn the_platform_decides_the_defaults() {
+        let names = default_names();
+        assert_eq!(names.contains(&".DS_Store"), !cfg!(windows), "{names:?}");
+        assert_eq!(
+            names.contains(&"Thumbs.db"),
+            !cfg!(target_os = "macos"),
+            "{names:?}"
+        );
+    }
+
+    #[test]
+    fn names_can_be_replaced() {
+        let filter = SystemFilter::new(&["node_modules", "~$*"]);
+        assert!(skips(&filter, "node_modules"));
+        assert!(skips(&filter, "~$report.docx"));
+        assert!(!skips(&filter, ".DS_Store"));
+        assert!(!SystemFilter::default().skips(OsStr::new(".DS_Store")));
+    }
+}
@@ -0,0 +1,107 @@
+//! The files tracked by git, for `--git-tracked`
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, bail};
+
+use crate::input_list::bytes_to_path;
+
+/// The files tracked by git under `root`, spelled starting with `root`. Files of submodules are
+/// included. Fails if `root` is not inside a git work tree.
+pub fn tracked_files(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    // git needs a directory to run in, a file is looked up from its parent
+    let (dir, pathspec) = if root.is_dir() {
+        (root, Path::new("."))
+    } else {
+        let parent = root.parent().filter(|p| !p.as_os_str().is_empty());
+        (
+            parent.unwrap_or(Path::new(".")),
+            Path::new(root.file_name().unwrap_or_default()),
+        )
+    };
+    let output = Command::new("git")
+        .args(["ls-files", "-z", "--recurse-submodules", "--"])
+        .arg(pathspec)
+        .current_dir(dir)
+        .output()
+        .context("could not run git, is it installed?")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("not a git repository") {
+            bail!("'{}' is not inside a git repository", root.display());
+        }
+        bail!(
+            "git ls-files failed in '{}': {}",
+            dir.display(),
+            stderr.trim()
+        );
+    }
+
+    Ok(output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| dir.join(bytes_to_path(path)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=fss", "-c", "user.email=fss@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?}");
+    }
+
+    #[test]
+    fn only_tracked_files_are_listed() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        fs::create_dir_all(repo.join("src")).unwrap();
+        fs::write(repo.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(repo.join("README"), "hi").unwrap();
+        fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["add", "."]);
+        // Ignored, and untracked but not ignored
+        fs::create_dir(repo.join("target")).unwrap();
+        fs::write(repo.join("target/app"), "binary").unwrap();
+        fs::write(repo.join("notes.txt"), "draft").unwrap();
+
+        let mut files = tracked_files(&repo).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                repo.join(".gitignore"),
+                repo.join("README"),
+                repo.join("src/main.rs")
+            ]
+        );
+        assert_eq!(
+            tracked_files(&repo.join("src")).unwrap(),
+            [repo.join("src/main.rs")]
+        );
+        assert_eq!(
+            tracked_files(&repo.join("README")).unwrap(),
+            [repo.join("README")]
+        );
+
+        let err = tracked_files(dir.path()).unwrap_err();
+        assert!(
+            err.to_string().contains("not inside a git repository"),
+            "{err}"
+        );
+    }
+}
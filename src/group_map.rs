@@ -0,0 +1,200 @@
+//! The map from group names to sizes. Grouping by file name or directory can make millions of
+//! groups, and a `String` per key costs its header and a heap allocation on top of its text. Here
+//! all the keys share a single buffer and each group only costs its text, an offset and an index
+//! slot.
+use std::fmt;
+use std::hash::Hasher;
+use std::ops::Index;
+
+use fnv::FnvHasher;
+use hashbrown::HashTable;
+
+#[derive(Clone)]
+pub struct GroupMap<V> {
+    /// The keys one after the other
+    names: String,
+    /// Where each key ends in `names`, the key `i` starts where the key `i - 1` ends
+    ends: Vec<usize>,
+    values: Vec<V>,
+    /// Positions of the keys in `ends` and `values`, hashed by key
+    index: HashTable<usize>,
+}
+
+#[inline(always)]
+fn hash(key: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(key.as_bytes());
+    hasher.finish()
+}
+
+#[inline(always)]
+fn key_at<'a>(names: &'a str, ends: &[usize], i: usize) -> &'a str {
+    let start = if i == 0 { 0 } else { ends[i - 1] };
+    &names[start..ends[i]]
+}
+
+impl<V> GroupMap<V> {
+    pub fn new() -> Self {
+        GroupMap {
+            names: String::new(),
+            ends: Vec::new(),
+            values: Vec::new(),
+            index: HashTable::new(),
+        }
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.ends.reserve(additional);
+        self.values.reserve(additional);
+        let (names, ends) = (&self.names, &self.ends);
+        self.index
+            .reserve(additional, |&i| hash(key_at(names, ends, i)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline(always)]
+    fn position(&self, key: &str) -> Option<usize> {
+        self.index
+            .find(hash(key), |&i| key_at(&self.names, &self.ends, i) == key)
+            .copied()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.position(key).map(|i| &self.values[i])
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.position(key).map(|i| &mut self.values[i])
+    }
+
+    /// Set the value of `key`, returning its previous value if it already had one
+    pub fn insert(&mut self, key: &str, value: V) -> Option<V> {
+        if let Some(i) = self.position(key) {
+            return Some(std::mem::replace(&mut self.values[i], value));
+        }
+
+        let i = self.values.len();
+        self.names.push_str(key);
+        self.ends.push(self.names.len());
+        self.values.push(value);
+        let (names, ends) = (&self.names, &self.ends);
+        self.index
+            .insert_unique(hash(key), i, |&i| hash(key_at(names, ends, i)));
+        None
+    }
+
+    /// The groups in the order they were inserted
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        (0..self.ends.len())
+            .map(|i| key_at(&self.names, &self.ends, i))
+            .zip(&self.values)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(key, _value)| key)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.values.iter_mut()
+    }
+}
+
+impl<V> Default for GroupMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Index<&str> for GroupMap<V> {
+    type Output = V;
+
+    fn index(&self, key: &str) -> &V {
+        self.get(key).expect("no group with this name")
+    }
+}
+
+/// Equal if they hold the same groups, whatever their order
+impl<V: PartialEq> PartialEq for GroupMap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for GroupMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V> IntoIterator for GroupMap<V> {
+    type Item = (String, V);
+    type IntoIter = std::vec::IntoIter<(String, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let keys: Vec<String> = self.keys().map(str::to_string).collect();
+        keys.into_iter()
+            .zip(self.values)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_found_by_their_text() {
+        let mut map = GroupMap::new();
+        assert_eq!(map.insert("txt", 1), None);
+        assert_eq!(map.insert("", 2), None);
+        assert_eq!(map.insert("t", 3), None);
+        assert_eq!(map.insert("txt", 4), Some(1));
+        *map.get_mut("t").unwrap() += 10;
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["txt"], 4);
+        assert_eq!(map[""], 2);
+        assert_eq!(map["t"], 13);
+        assert!(!map.contains_key("tx"));
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            [("txt", &4), ("", &2), ("t", &13)]
+        );
+    }
+
+    #[test]
+    fn many_keys_survive_growing() {
+        let mut map = GroupMap::new();
+        for i in 0..100_000u64 {
+            map.insert(&format!("file{i}.txt"), i);
+        }
+        assert_eq!(map.len(), 100_000);
+        for i in (0..100_000u64).step_by(997) {
+            assert_eq!(map[format!("file{i}.txt").as_str()], i);
+        }
+    }
+
+    #[test]
+    fn order_does_not_matter_for_equality() {
+        let mut a = GroupMap::new();
+        a.insert("png", 1);
+        a.insert("jpg", 2);
+        let mut b = GroupMap::new();
+        b.insert("jpg", 2);
+        b.insert("png", 1);
+        assert_eq!(a, b);
+        b.insert("gif", 3);
+        assert_ne!(a, b);
+    }
+}
@@ -1,4 +1,8 @@
-use std::{fmt::Display, path::Path};
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use clap::ValueEnum;
 use fnv::FnvHashMap;
@@ -42,6 +46,7 @@ lazy_static! {
         hm.insert("jpegxl", FileType::Image);
         hm.insert("png", FileType::Image);
         hm.insert("tiff", FileType::Image);
+        hm.insert("tif", FileType::Image);
         hm.insert("raw", FileType::Image);
         hm.insert("nef", FileType::Image);
         hm.insert("webp", FileType::Image);
@@ -110,6 +115,7 @@ lazy_static! {
         hm.insert("jsx", FileType::Code);
         hm.insert("json", FileType::Code);
         hm.insert("yaml", FileType::Code);
+        hm.insert("yml", FileType::Code);
         hm.insert("toml", FileType::Code);
         hm.insert("ts", FileType::Code);
         hm.insert("c", FileType::Code);
@@ -218,6 +224,257 @@ lazy_static! {
     };
 }
 
+/// A finer classification than [`FileType`], splitting documents and code into subtypes
+#[derive(Hash, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FineType {
+    Image,
+    Video,
+    /// Word processors, spreadsheets, presentations and PDFs
+    Office,
+    /// Plain text, markup and tabular text
+    Text,
+    Ebook,
+    Executable,
+    Archive,
+    Audio,
+    /// Markup, styles and scripts for the web
+    Web,
+    /// Interpreted languages and shell scripts
+    Script,
+    /// Compiled languages and configuration
+    Source,
+    GenomicData,
+    Other,
+}
+
+lazy_static! {
+    /// Subtypes of the extensions mapped to [`FileType::Document`] and [`FileType::Code`].
+    /// Everything else keeps the coarse type.
+    static ref FINETYPE_MAP: FnvHashMap<&'static str, FineType> = {
+        let mut hm = FnvHashMap::default();
+
+        for ext in [
+            "pdf", "docx", "doc", "xlsx", "xls", "odt", "fodt", "pages", "rtf", "ppt", "pptx",
+            "otp", "odp", "pot", "pps",
+        ] {
+            hm.insert(ext, FineType::Office);
+        }
+        for ext in ["txt", "csv", "tsv", "md", "tex", "latex", "bib", "log", "tmp", "temp"] {
+            hm.insert(ext, FineType::Text);
+        }
+        for ext in ["epub", "kpub"] {
+            hm.insert(ext, FineType::Ebook);
+        }
+
+        for ext in [
+            "xml", "html", "htm", "htmx", "xhtml", "xht", "css", "js", "jsx", "json", "ts", "php",
+            "php3", "php4", "php5", "phps",
+        ] {
+            hm.insert(ext, FineType::Web);
+        }
+        for ext in [
+            "py", "pyc", "pyo", "r", "awk", "ipynb", "lua", "pl", "pm", "ps1", "sh", "fish",
+            "vim",
+        ] {
+            hm.insert(ext, FineType::Script);
+        }
+        for ext in [
+            "c", "cpp", "h", "rs", "go", "zig", "cs", "csproj", "ici", "kt", "asm", "d", "java",
+            "lisp", "vb", "sql", "yaml", "yml", "toml",
+        ] {
+            hm.insert(ext, FineType::Source);
+        }
+
+        hm
+    };
+}
+
+lazy_static! {
+    /// Other spellings of an extension, mapped to the most common one. Used by
+    /// `--normalize-ext` to merge the groups of a same format.
+    static ref EXTENSION_ALIASES: FnvHashMap<&'static str, &'static str> = {
+        let mut hm = FnvHashMap::default();
+
+        for (canonical, aliases) in [
+            ("jpg", &["jpeg"][..]),
+            ("tiff", &["tif"]),
+            ("html", &["htm"]),
+            ("yaml", &["yml"]),
+            ("mpg", &["mpeg"]),
+            ("aiff", &["aif"]),
+            ("fasta", &["fa"]),
+            ("fastq", &["fq"]),
+        ] {
+            for alias in aliases {
+                hm.insert(*alias, canonical);
+            }
+        }
+
+        hm
+    };
+}
+
+/// The usual spelling of the extension `ext`, which must be lowercase, if `ext` is an alias
+#[inline(always)]
+pub fn canonical_ext(ext: &str) -> Option<&'static str> {
+    EXTENSION_ALIASES.get(ext).copied()
+}
+
+impl Display for FineType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FineType {
+    pub const ALL: [FineType; 13] = [
+        FineType::Image,
+        FineType::Video,
+        FineType::Office,
+        FineType::Text,
+        FineType::Ebook,
+        FineType::Executable,
+        FineType::Archive,
+        FineType::Audio,
+        FineType::Web,
+        FineType::Script,
+        FineType::Source,
+        FineType::GenomicData,
+        FineType::Other,
+    ];
+
+    /// The coarse type this is a subtype of
+    pub fn file_type(self) -> FileType {
+        match self {
+            FineType::Image => FileType::Image,
+            FineType::Video => FileType::Video,
+            FineType::Office | FineType::Text | FineType::Ebook => FileType::Document,
+            FineType::Executable => FileType::Executable,
+            FineType::Archive => FileType::Archive,
+            FineType::Audio => FileType::Audio,
+            FineType::Web | FineType::Script | FineType::Source => FileType::Code,
+            FineType::GenomicData => FileType::GenomicData,
+            FineType::Other => FileType::Other,
+        }
+    }
+
+    /// The subtype printed as `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ft| ft.to_string() == name)
+    }
+
+    #[inline(always)]
+    pub fn get_finetype<S: AsRef<str>>(ext: &S) -> Self {
+        if let Some(ft) = FINETYPE_MAP.get(ext.as_ref()) {
+            return *ft;
+        }
+        match FileType::get_filetype(ext) {
+            FileType::Image => FineType::Image,
+            FileType::Video => FineType::Video,
+            FileType::Document => FineType::Text,
+            FileType::Executable => FineType::Executable,
+            FileType::Archive => FineType::Archive,
+            FileType::Audio => FineType::Audio,
+            FileType::Code => FineType::Source,
+            FileType::GenomicData => FineType::GenomicData,
+            FileType::Other => FineType::Other,
+        }
+    }
+}
+
+/// How long ago a file was last modified, relative to the start of the scan
+#[derive(Hash, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AgeBucket {
+    LastWeek,
+    LastMonth,
+    LastYear,
+    Older,
+    /// The filesystem does not record modification times
+    Unknown,
+}
+
+pub const DAY: u64 = 24 * 60 * 60;
+
+impl AgeBucket {
+    /// Every bucket, from the newest files to the oldest
+    pub const ALL: [AgeBucket; 5] = [
+        AgeBucket::LastWeek,
+        AgeBucket::LastMonth,
+        AgeBucket::LastYear,
+        AgeBucket::Older,
+        AgeBucket::Unknown,
+    ];
+
+    /// Bucket of a file modified `age` ago. Files modified in the future (clock skew, bogus
+    /// timestamps) are as new as they get.
+    pub fn from_age(age: Option<Duration>) -> Self {
+        let Some(age) = age else {
+            return AgeBucket::Unknown;
+        };
+        match age.as_secs() {
+            s if s < 7 * DAY => AgeBucket::LastWeek,
+            s if s < 30 * DAY => AgeBucket::LastMonth,
+            s if s < 365 * DAY => AgeBucket::LastYear,
+            _ => AgeBucket::Older,
+        }
+    }
+
+    /// Position of the bucket labelled `label` in [`AgeBucket::ALL`], used to print the buckets
+    /// in chronological order instead of by size
+    pub fn rank(label: &str) -> usize {
+        AgeBucket::ALL
+            .iter()
+            .position(|b| b.label() == label)
+            .unwrap_or(AgeBucket::ALL.len())
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            AgeBucket::LastWeek => "Last 7 days",
+            AgeBucket::LastMonth => "Last 30 days",
+            AgeBucket::LastYear => "Last year",
+            AgeBucket::Older => "Older",
+            AgeBucket::Unknown => "Unknown",
+        }
+    }
+}
+
+/// The year and the month (1 to 12) of `time`, in UTC
+pub fn year_month(time: SystemTime) -> (i64, u32) {
+    let (year, month, _day) = civil_date(time);
+    (year, month)
+}
+
+/// Seconds from the Unix epoch to `time`, negative before it
+pub fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// The year, the month (1 to 12) and the day of the month of `time`, in UTC
+pub fn civil_date(time: SystemTime) -> (i64, u32, u32) {
+    // Howard Hinnant's civil_from_days, with years starting in March so that leap days are last
+    let days = unix_secs(time).div_euclid(DAY as i64) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+impl Display for AgeBucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
 impl Display for FileType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -225,6 +482,30 @@ impl Display for FileType {
 }
 
 impl FileType {
+    pub const ALL: [FileType; 9] = [
+        FileType::Image,
+        FileType::Video,
+        FileType::Document,
+        FileType::Executable,
+        FileType::Archive,
+        FileType::Audio,
+        FileType::Code,
+        FileType::GenomicData,
+        FileType::Other,
+    ];
+
+    /// The type printed as `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ft| ft.to_string() == name)
+    }
+
+    /// Every extension with a type other than [`FileType::Other`], sorted
+    pub fn known_extensions() -> Vec<&'static str> {
+        let mut extensions: Vec<&'static str> = FILETYPE_MAP.keys().copied().collect();
+        extensions.sort_unstable();
+        extensions
+    }
+
     #[allow(dead_code)]
     #[inline(always)]
     pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
@@ -254,6 +535,7 @@ impl FileType {
     //         "jpegxl" => Self::Image,
     //         "png" => Self::Image,
     //         "tiff" => Self::Image,
+    //         "tif" => Self::Image,
     //         "raw" => Self::Image,
     //         "nef" => Self::Image,
     //         "webp" => Self::Image,
@@ -322,6 +604,7 @@ impl FileType {
     //         "jsx" => Self::Code,
     //         "json" => Self::Code,
     //         "yaml" => Self::Code,
+    //         "yml" => Self::Code,
     //         "toml" => Self::Code,
     //         "ts" => Self::Code,
     //         "c" => Self::Code,
@@ -430,3 +713,89 @@ impl FileType {
     //     }
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fine_types_split_documents_and_code() {
+        assert_eq!(FineType::get_finetype(&"docx"), FineType::Office);
+        assert_eq!(FineType::get_finetype(&"pdf"), FineType::Office);
+        assert_eq!(FineType::get_finetype(&"md"), FineType::Text);
+        assert_eq!(FineType::get_finetype(&"epub"), FineType::Ebook);
+        assert_eq!(FineType::get_finetype(&"html"), FineType::Web);
+        assert_eq!(FineType::get_finetype(&"sh"), FineType::Script);
+        assert_eq!(FineType::get_finetype(&"rs"), FineType::Source);
+        assert_eq!(FineType::get_finetype(&"png"), FineType::Image);
+        assert_eq!(FineType::get_finetype(&"unknown"), FineType::Other);
+    }
+
+    #[test]
+    fn aliases_merge_into_one_extension() {
+        assert_eq!(canonical_ext("jpeg"), Some("jpg"));
+        assert_eq!(canonical_ext("htm"), Some("html"));
+        assert_eq!(canonical_ext("yml"), Some("yaml"));
+        assert_eq!(canonical_ext("tif"), Some("tiff"));
+        // Already canonical, or a different format
+        assert_eq!(canonical_ext("jpg"), None);
+        assert_eq!(canonical_ext("png"), None);
+        assert_eq!(canonical_ext("h"), None);
+        assert_eq!(canonical_ext(""), None);
+    }
+
+    #[test]
+    fn aliases_keep_their_type() {
+        for (alias, canonical) in EXTENSION_ALIASES.iter() {
+            assert_eq!(
+                FileType::get_filetype(alias),
+                FileType::get_filetype(canonical),
+                "{alias} and {canonical}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_document_and_code_extension_has_a_subtype() {
+        for (ext, ft) in FILETYPE_MAP.iter() {
+            if matches!(ft, FileType::Document | FileType::Code) {
+                assert!(FINETYPE_MAP.contains_key(ext), "{ext} has no subtype");
+            }
+        }
+    }
+
+    #[test]
+    fn age_buckets_are_relative() {
+        let days = |d: u64| Some(Duration::from_secs(d * DAY));
+        assert_eq!(AgeBucket::from_age(days(0)), AgeBucket::LastWeek);
+        assert_eq!(AgeBucket::from_age(days(6)), AgeBucket::LastWeek);
+        assert_eq!(AgeBucket::from_age(days(7)), AgeBucket::LastMonth);
+        assert_eq!(AgeBucket::from_age(days(29)), AgeBucket::LastMonth);
+        assert_eq!(AgeBucket::from_age(days(200)), AgeBucket::LastYear);
+        assert_eq!(AgeBucket::from_age(days(365)), AgeBucket::Older);
+        assert_eq!(AgeBucket::from_age(None), AgeBucket::Unknown);
+
+        let mut labels: Vec<_> = AgeBucket::ALL.iter().rev().map(|b| b.to_string()).collect();
+        labels.sort_by_key(|l| AgeBucket::rank(l));
+        assert_eq!(labels[0], "Last 7 days");
+        assert_eq!(labels[4], "Unknown");
+    }
+
+    #[test]
+    fn times_fall_in_their_month() {
+        let at = |secs: i64| match secs {
+            0.. => UNIX_EPOCH + Duration::from_secs(secs as u64),
+            _ => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+        };
+        assert_eq!(year_month(at(0)), (1970, 1));
+        assert_eq!(year_month(at(-1)), (1969, 12));
+        // 2024-02-29T23:59:59Z and the second after
+        assert_eq!(year_month(at(1_709_251_199)), (2024, 2));
+        assert_eq!(year_month(at(1_709_251_200)), (2024, 3));
+        // 2000-12-31T12:00:00Z
+        assert_eq!(year_month(at(978_264_000)), (2000, 12));
+        assert_eq!(civil_date(at(978_264_000)), (2000, 12, 31));
+        assert_eq!(civil_date(at(1_709_251_199)), (2024, 2, 29));
+        assert_eq!(civil_date(at(-1)), (1969, 12, 31));
+    }
+}
@@ -0,0 +1,76 @@
+//! Reading the inputs from a list of paths, for `--files-from` and `--files0-from`
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+
+/// Read the paths of the list at `path`, '-' being stdin, separated by `delimiter`. Empty
+/// entries are skipped, and a list without any path is an error.
+pub fn read_input_list(path: &Path, delimiter: u8) -> anyhow::Result<Vec<PathBuf>> {
+    let (reader, name): (Box<dyn Read>, _) = if path == Path::new("-") {
+        (Box::new(io::stdin().lock()), "stdin".to_string())
+    } else {
+        let file = File::open(path)
+            .with_context(|| format!("could not open input list '{}'", path.display()))?;
+        (Box::new(file), format!("'{}'", path.display()))
+    };
+    let inputs = split_paths(BufReader::new(reader), delimiter)
+        .with_context(|| format!("could not read the input list from {name}"))?;
+    if inputs.is_empty() {
+        bail!("the input list from {name} has no paths");
+    }
+    Ok(inputs)
+}
+
+fn split_paths<R: BufRead>(reader: R, delimiter: u8) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in reader.split(delimiter) {
+        let mut entry = entry?;
+        // Lists written on Windows end their lines with "\r\n"
+        if delimiter == b'\n' && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            paths.push(bytes_to_path(&entry));
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(unix)]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// Lists are written as UTF-8 on Windows
+#[cfg(not(unix))]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_split_on_the_delimiter() {
+        let list = b"photos/a.jpg\r\n\nwith space.txt\nlast";
+        assert_eq!(
+            split_paths(&list[..], b'\n').unwrap(),
+            [
+                PathBuf::from("photos/a.jpg"),
+                PathBuf::from("with space.txt"),
+                PathBuf::from("last")
+            ]
+        );
+        let list = b"new\nline.txt\0\0b.txt\0";
+        assert_eq!(
+            split_paths(&list[..], b'\0').unwrap(),
+            [PathBuf::from("new\nline.txt"), PathBuf::from("b.txt")]
+        );
+    }
+}
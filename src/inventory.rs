@@ -0,0 +1,195 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, anyhow};
+
+use crate::aggregate::{Aggregator, FileEntry};
+use crate::input_list::bytes_to_path;
+
+/// Feed a per-file inventory into `aggregator`, bypassing the filesystem.
+///
+/// An inventory has one file per line, as its size in bytes and its path separated by a tab:
+/// `<SIZE>\t<PATH>`. Empty lines are ignored, and the paths are taken byte for byte like those of
+/// `--files-from`. Since there are no inodes to compare, files are never deduplicated. Reading
+/// stops once `cancel` is set, keeping the files read until then.
+pub fn read_inventory<R: BufRead>(
+    mut reader: R,
+    aggregator: &mut Aggregator,
+    cancel: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut line = Vec::new();
+    for number in 1.. {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .with_context(|| format!("line {number}: could not read the inventory"))?;
+        if read == 0 {
+            break;
+        }
+        // Inventories written on Windows end their lines with "\r\n"
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (size, path) = parse_line(&line).ok_or_else(|| {
+            anyhow!(
+                "line {number}: expected '<SIZE>\\t<PATH>', got '{}'",
+                String::from_utf8_lossy(&line)
+            )
+        })?;
+        aggregator.add_file(FileEntry::new(bytes_to_path(path), size));
+    }
+    Ok(())
+}
+
+fn parse_line(line: &[u8]) -> Option<(u64, &[u8])> {
+    let tab = line.iter().position(|&b| b == b'\t')?;
+    let (size, path) = (&line[..tab], &line[tab + 1..]);
+    if path.is_empty() {
+        return None;
+    }
+    Some((std::str::from_utf8(size).ok()?.trim().parse().ok()?, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::FileList;
+    use crate::cli::{Cli, GroupBy};
+    use crate::output::print_result;
+    use clap::Parser;
+
+    const INVENTORY: &str = "\
+100\tphotos/a.jpg
+250\tphotos/b.JPG
+40\tnotes/todo.txt
+
+5\tsrc/main.rs
+";
+
+    fn aggregate(group_by: GroupBy) -> Vec<(String, u64)> {
+        let mut aggregator = Aggregator::new(group_by);
+        read_inventory(
+            INVENTORY.as_bytes(),
+            &mut aggregator,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let mut sizes: Vec<_> = aggregator.finish().sizes.into_iter().collect();
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn groups_an_inventory() {
+        let own = |v: &[(&str, u64)]| -> Vec<(String, u64)> {
+            v.iter().map(|(k, s)| (k.to_string(), *s)).collect()
+        };
+        assert_eq!(
+            aggregate(GroupBy::Extension),
+            own(&[("jpg", 350), ("rs", 5), ("txt", 40)])
+        );
+        assert_eq!(
+            aggregate(GroupBy::Type),
+            own(&[("Code", 5), ("Document", 40), ("Image", 350)])
+        );
+        assert_eq!(
+            aggregate(GroupBy::Directory),
+            own(&[("notes", 40), ("photos", 350), ("src", 5)])
+        );
+    }
+
+    #[test]
+    fn prints_an_inventory() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes", "-g", "type"]);
+        let mut aggregator = Aggregator::new(cli.group_by[0]);
+        read_inventory(
+            INVENTORY.as_bytes(),
+            &mut aggregator,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        print_result(&mut out, &aggregator.finish(), &cli).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("  5\tCode\n 40\tDocument\n350\tImage\n"));
+        assert!(out.ends_with("\n395\n"));
+    }
+
+    #[test]
+    fn stops_once_cancelled() {
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        read_inventory(
+            INVENTORY.as_bytes(),
+            &mut aggregator,
+            &AtomicBool::new(true),
+        )
+        .unwrap();
+        assert_eq!(aggregator.finish().file_count, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for line in ["abc\tfoo.txt", "100 foo.txt", "100\t", "-5\tfoo.txt"] {
+            let mut aggregator = Aggregator::new(GroupBy::Extension);
+            let err = read_inventory(line.as_bytes(), &mut aggregator, &AtomicBool::new(false))
+                .unwrap_err();
+            assert!(err.to_string().starts_with("line 1:"), "{err}");
+        }
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        let err = read_inventory(
+            &b"1\ta.txt\n\n\xff\tb.txt\n"[..],
+            &mut aggregator,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("line 3:"), "{err}");
+    }
+
+    #[test]
+    fn reads_windows_line_ends() {
+        let mut aggregator = Aggregator::new(GroupBy::FileName);
+        read_inventory(
+            "100\tphotos\\a.jpg\r\n\r\n5\tmain.rs\r\n".as_bytes(),
+            &mut aggregator,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let result = aggregator.finish();
+        assert_eq!(result.file_count, 2);
+        assert_eq!(result.sizes["main.rs"], 5);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_paths_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut aggregator =
+            Aggregator::new(GroupBy::Extension).list_files(Some(FileList::default()));
+        read_inventory(
+            &b"100\tcaf\xe9.txt\n5\tplain.txt\n"[..],
+            &mut aggregator,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let result = aggregator.finish();
+        assert_eq!(result.sizes["txt"], 105);
+        let paths: Vec<&OsStr> = result.listed.iter().map(|f| f.path.as_os_str()).collect();
+        assert!(
+            paths.contains(&OsStr::from_bytes(b"caf\xe9.txt")),
+            "{paths:?}"
+        );
+    }
+}
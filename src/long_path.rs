@@ -0,0 +1,89 @@
+//! Windows refuses paths longer than 260 characters (MAX_PATH) unless they are written in the
+//! extended-length form `\\?\C:\...` (or `\\?\UNC\server\share\...`). Deep trees such as
+//! `node_modules` hit this limit all the time, so the walk starts from extended-length roots and
+//! every path built from them inherits the prefix. The prefix is stripped again for display.
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+const VERBATIM_PREFIX: &str = r"\\?\";
+const VERBATIM_UNC_PREFIX: &str = r"\\?\UNC\";
+
+/// Turn `path` into its extended-length form so that it and its children can be longer than
+/// MAX_PATH. Does nothing outside of Windows.
+#[cfg(windows)]
+pub fn to_extended(path: &Path) -> PathBuf {
+    // Verbatim paths are not normalized by Windows, so `.`, `..` and `/` must be resolved
+    // before adding the prefix, which `absolute` does
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    match absolute.to_str() {
+        Some(s) => PathBuf::from(extend_absolute(s)),
+        None => absolute,
+    }
+}
+
+#[cfg(not(windows))]
+pub fn to_extended(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// `path` as a NUL terminated UTF-16 string for the Windows API
+#[cfg(windows)]
+pub fn to_wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
+
+/// Add the verbatim prefix to an absolute, normalized Windows path
+#[cfg_attr(not(windows), allow(dead_code))]
+fn extend_absolute(path: &str) -> String {
+    if path.starts_with(VERBATIM_PREFIX) {
+        path.to_string()
+    } else if let Some(unc) = path.strip_prefix(r"\\") {
+        format!("{VERBATIM_UNC_PREFIX}{unc}")
+    } else {
+        format!("{VERBATIM_PREFIX}{path}")
+    }
+}
+
+/// The friendly form of a path, without the extended-length prefix
+pub fn strip_extended(path: &Path) -> Cow<'_, Path> {
+    let Some(s) = path.to_str() else {
+        return Cow::Borrowed(path);
+    };
+    if let Some(unc) = s.strip_prefix(VERBATIM_UNC_PREFIX) {
+        Cow::Owned(PathBuf::from(format!(r"\\{unc}")))
+    } else if let Some(rest) = s.strip_prefix(VERBATIM_PREFIX) {
+        Cow::Borrowed(Path::new(rest))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extends_drive_and_unc_paths() {
+        assert_eq!(extend_absolute(r"C:\Users\me"), r"\\?\C:\Users\me");
+        assert_eq!(
+            extend_absolute(r"\\server\share\dir"),
+            r"\\?\UNC\server\share\dir"
+        );
+        assert_eq!(extend_absolute(r"\\?\C:\already"), r"\\?\C:\already");
+    }
+
+    #[test]
+    fn strips_the_prefix_for_display() {
+        let strip = |s: &str| strip_extended(Path::new(s)).into_owned();
+        assert_eq!(strip(r"\\?\C:\Users\me"), PathBuf::from(r"C:\Users\me"));
+        assert_eq!(
+            strip(r"\\?\UNC\server\share"),
+            PathBuf::from(r"\\server\share")
+        );
+        assert_eq!(strip("relative/path"), PathBuf::from("relative/path"));
+    }
+}
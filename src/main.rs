@@ -1,84 +1,524 @@
+mod access;
+mod aggregate;
 pub mod cli;
+mod compare;
+mod config;
+mod duplicates;
+mod error_log;
+mod extents;
 mod filesize;
 mod filter;
+mod git;
+mod group_map;
 mod groups;
+mod input_list;
+mod inventory;
+mod long_path;
+mod output;
+mod palette;
+mod progress;
+mod reparse;
+mod sample;
+mod snapshot;
+mod stream;
+mod threads;
 mod unique_id;
 mod walk;
+use aggregate::{Aggregator, FileList, ScanResult};
+use anyhow::Context;
 use clap::Parser;
-use colored::Colorize;
-use filter::SizeFilter;
+use error_log::ErrorLog;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime};
 use walk::Walk;
 
-use cli::FormatOption;
+use cli::{ColorWhen, Command, ErrorFormat, OutputFormat, TopKey};
 use filesize::FilesizeType;
-use std::collections::HashMap;
-
-fn print_result(
-    total: u64,
-    sizes: HashMap<String, u64>,
-    errors: &[walk::Error],
-    size_format: FormatOption,
-    size_filter: Vec<SizeFilter>,
-    verbose: bool,
-) {
-    if verbose {
-        for err in errors {
-            match err {
-                walk::Error::NoMetadataForPath(path) => {
-                    eprintln!(
-                        "fss: could not retrieve metadata for path '{}'",
-                        path.to_string_lossy()
-                    );
-                }
-                walk::Error::CouldNotReadDir(path) => {
-                    eprintln!(
-                        "fss: could not read contents of directory '{}'",
-                        path.to_string_lossy()
-                    );
-                }
-            }
+use filter::{ExtFilter, SystemFilter, system};
+use group_map::GroupMap;
+use output::{
+    Timings, print_errors, print_errors_json, print_extent_fallback, print_partial, print_result,
+    print_result_to, print_saturation, print_special_files, print_timings, print_types,
+};
+use sample::Sampler;
+use snapshot::{ScanSettings, Snapshot};
+use stream::StreamWriter;
+
+/// Exit codes, documented in the long help
+mod exit_code {
+    /// Invalid arguments or nothing could be read. Also used for errors returned from `main`.
+    pub const FAILURE: u8 = 1;
+    /// Some entries could not be read and `--strict` was given
+    pub const PARTIAL_READ: u8 = 2;
+    /// The scan was stopped early by `--max-files` or `--timeout`
+    pub const PARTIAL_SCAN: u8 = 3;
+    /// The total is 0 and `--fail-on-empty` was given
+    pub const EMPTY: u8 = 4;
+    /// Filters were given and no file matched them, like grep finding nothing. Not 1 as in grep,
+    /// which was already taken by hard failures.
+    pub const NO_MATCH: u8 = 5;
+    /// The scan was stopped by Ctrl-C, like shells report a SIGINT
+    pub const INTERRUPTED: u8 = 130;
+}
+
+/// Split the inputs into the ones that exist and the ones that don't. A dangling symlink exists.
+fn partition_missing(inputs: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    inputs
+        .iter()
+        .cloned()
+        .partition(|input| input.symlink_metadata().is_ok())
+}
+
+/// The system files to skip, none unless asked for
+fn system_filter(cli: &cli::Cli) -> SystemFilter {
+    let skip = cli.skip_system || cli.system_names.is_some();
+    if !skip || cli.count_system {
+        return SystemFilter::default();
+    }
+    match &cli.system_names {
+        Some(names) => SystemFilter::new(names),
+        None => SystemFilter::new(&system::default_names()),
+    }
+}
+
+/// The groups of a scan saved with `--save-groups` or `--save-snapshot`, and the rest of the
+/// snapshot for the latter
+fn read_saved_scan(path: &Path) -> anyhow::Result<(GroupMap<u64>, Option<Snapshot>)> {
+    let file = File::open(path)
+        .with_context(|| format!("could not open saved scan '{}'", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let start = reader
+        .fill_buf()
+        .with_context(|| format!("could not read saved scan '{}'", path.display()))?;
+    if start.starts_with(snapshot::MAGIC.as_bytes()) {
+        let mut snapshot = snapshot::read_snapshot(reader)
+            .with_context(|| format!("could not read snapshot '{}'", path.display()))?;
+        Ok((std::mem::take(&mut snapshot.sizes), Some(snapshot)))
+    } else {
+        Ok((compare::read_groups(reader)?, None))
+    }
+}
+
+/// A seed for `--sample` when none is given, different for every run
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, RandomState};
+
+    RandomState::new().hash_one(std::time::SystemTime::now())
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    // 'fss scan ...' is the same as 'fss ...'
+    let scan_command = args.get(1).is_some_and(|arg| arg == "scan");
+    if scan_command {
+        args.remove(1);
+    }
+    let args = config::with_defaults(args)?;
+    let cli = match cli::Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(err) => {
+            // clap exits with 2 on usage errors, which we reserve for --strict
+            err.print()?;
+            let code = if err.use_stderr() {
+                exit_code::FAILURE
+            } else {
+                0
+            };
+            return Ok(ExitCode::from(code));
         }
-    } else if !errors.is_empty() {
+    };
+
+    // With 'auto', colored reads NO_COLOR and CLICOLOR_FORCE and checks that stdout is a terminal
+    match cli.color {
+        ColorWhen::Auto => {}
+        ColorWhen::Always => colored::control::set_override(true),
+        ColorWhen::Never => colored::control::set_override(false),
+    }
+
+    // A directory called like a command is easily taken for an input
+    let command_word = match &cli.command {
+        Some(Command::Types) => Some("types"),
+        Some(Command::Diff { .. }) => Some("diff"),
+        None => scan_command.then_some("scan"),
+    };
+    if let Some(word) = command_word
+        && !cli.quiet
+        && Path::new(word).exists()
+    {
         eprintln!(
-            "[fss warning] the results may be tainted. Re-run with -v/--verbose to print all errors."
+            "[fss warning] '{word}' is read as the {word} command, not as the path '{word}', \
+             write './{word}' to scan it"
         );
     }
 
-    let mut sorted_sizes: Vec<(String, u64)> = sizes.into_iter().collect();
-    sorted_sizes.sort_unstable_by_key(|(_k, v)| *v);
-    for (group, size) in sorted_sizes {
-        if size_filter.iter().any(|f| !f.is_within(size)) {
-            continue;
+    match &cli.command {
+        Some(Command::Types) => {
+            let mut stdout = std::io::stdout().lock();
+            print_types(&mut stdout)?;
+            stdout.flush()?;
+            return Ok(ExitCode::SUCCESS);
         }
+        Some(Command::Diff { old, new }) => {
+            let (old_groups, old_snapshot) = read_saved_scan(old)?;
+            let (new_groups, new_snapshot) = read_saved_scan(new)?;
+            // Compared with each other, not with the flags, which are for the output here
+            if let (Some(old_snapshot), Some(new_snapshot)) = (&old_snapshot, &new_snapshot) {
+                old_snapshot.check_same(new_snapshot).with_context(|| {
+                    format!(
+                        "could not compare '{}' with '{}'",
+                        old.display(),
+                        new.display()
+                    )
+                })?;
+            }
+            let result = ScanResult {
+                total: new_groups
+                    .iter()
+                    .fold(0, |sum: u64, (_group, size)| sum.saturating_add(*size)),
+                sizes: new_groups,
+                baseline: Some(old_groups),
+                ..Default::default()
+            };
+            let mut stdout = std::io::stdout().lock();
+            print_result(&mut stdout, &result, &cli)?;
+            stdout.flush()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    let filesize_type = if cli.apparent_size || cli.du_compat {
+        FilesizeType::ApparentSize
+    } else {
+        FilesizeType::DiskUsage
+    };
 
-        println!("{: >10}\t{}", size_format.format(size), group)
+    let inputs = match (&cli.files_from, &cli.files0_from) {
+        (Some(list), _) => input_list::read_input_list(list, b'\n')?,
+        (None, Some(list)) => input_list::read_input_list(list, b'\0')?,
+        (None, None) => cli.inputs.clone(),
+    };
+    let (inputs, missing) = partition_missing(&inputs);
+    if !missing.is_empty() && !cli.ignore_missing && !cli.reads_inventory() {
+        for input in &missing {
+            eprintln!("fss: input '{}' does not exist", input.to_string_lossy());
+        }
+        return Ok(ExitCode::from(exit_code::FAILURE));
     }
 
-    println!(
-        "\n{}\n{: >10}",
-        "Total: ".bold().cyan(),
-        size_format.format(total)
-    );
-}
+    #[cfg(unix)]
+    threads::raise_fd_limit();
 
-fn main() -> anyhow::Result<()> {
-    let cli = cli::Cli::parse();
+    let num_threads = match cli.threads {
+        0 => threads::auto_threads(&inputs),
+        n => n,
+    };
 
-    let filesize_type = if cli.apparent_size {
-        FilesizeType::ApparentSize
+    // Before the walk, which would otherwise run for nothing
+    let error_log = match &cli.error_log {
+        Some(path) => Some(
+            ErrorLog::open(path)
+                .with_context(|| format!("could not open error log '{}'", path.display()))?,
+        ),
+        None => None,
+    };
+
+    // These print or save the groups of a single grouping
+    let single_grouping = [
+        ("--compact", cli.compact),
+        ("--list-files", cli.list_files),
+        ("--explain", cli.explain),
+        ("--largest", cli.largest.is_some()),
+        ("--duplicates", cli.duplicates),
+        ("--compare", cli.compare.is_some()),
+        ("--save-groups", cli.save_groups.is_some()),
+        ("--save-snapshot", cli.save_snapshot.is_some()),
+    ];
+    if cli.group_by.len() > 1
+        && let Some((flag, _)) = single_grouping.iter().find(|(_flag, given)| *given)
+    {
+        eprintln!("fss: --group-by can't be given several times with {flag}");
+        return Ok(ExitCode::from(exit_code::FAILURE));
+    }
+
+    // These need the size of each file
+    let sized_files = [
+        ("--du-compat", cli.du_compat),
+        ("--block-size", cli.block_size.is_some()),
+        ("--both-sizes", cli.both_sizes),
+        ("--dedupe-extents", cli.dedupe_extents),
+        ("--list-files", cli.list_files),
+        ("--explain", cli.explain),
+        ("--largest", cli.largest.is_some()),
+        ("--duplicates", cli.duplicates),
+        ("--split-outliers", cli.split_outliers.is_some()),
+    ];
+    if cli.counts_entries()
+        && let Some((flag, _)) = sized_files.iter().find(|(_flag, given)| *given)
+    {
+        let counting = if cli.inodes {
+            "--inodes"
+        } else {
+            "--count-mode files"
+        };
+        eprintln!("fss: {counting} can't be combined with {flag}");
+        return Ok(ExitCode::from(exit_code::FAILURE));
+    }
+
+    // These print something else than the groups, and only the groups are laid out as a table
+    let not_tabular = [
+        ("--compact", cli.compact),
+        ("--list-files", cli.list_files),
+        ("--explain", cli.explain),
+        ("--duplicates", cli.duplicates),
+        ("--chart", cli.chart.is_some()),
+    ];
+    if cli.output == OutputFormat::Table
+        && let Some((flag, _)) = not_tabular.iter().find(|(_flag, given)| *given)
+    {
+        eprintln!("fss: -o table can't be combined with {flag}");
+        return Ok(ExitCode::from(exit_code::FAILURE));
+    }
+
+    let aggregator_for = |group_by| {
+        Aggregator::new(group_by)
+            .dedup(!cli.no_dedup)
+            .dotfile_as_ext(cli.dotfile_as_ext)
+            .normalize_ext(cli.normalize_ext)
+            .escape_nonutf8(cli.escape_nonutf8)
+            .block_size(cli.block_size)
+            .dedupe_extents(cli.dedupe_extents)
+            .max_groups(cli.max_groups)
+            .flat_depth(cli.flat_depth)
+            .absolute(cli.absolute)
+            .split_outliers(cli.split_outliers)
+            .list_files(if cli.list_files || cli.explain {
+                Some(FileList {
+                    top: cli.top,
+                    size: cli.size.clone(),
+                    limit: cli.limit,
+                })
+            } else {
+                cli.largest.map(|n| FileList {
+                    top: Some(n),
+                    ..Default::default()
+                })
+            })
+            .find_duplicates(cli.duplicates)
+            .count_files(
+                cli.top_by.is_some_and(|top_by| top_by.key == TopKey::Count)
+                    || cli.save_snapshot.is_some(),
+            )
+            .sizes_as_counts(cli.counts_entries())
+            .group_dirs(cli.inodes)
+    };
+    let aggregator = aggregator_for(cli.group_by[0])
+        .sections(
+            cli.group_by[1..]
+                .iter()
+                .copied()
+                .map(aggregator_for)
+                .collect(),
+        )
+        .per_root(cli.per_root)
+        .error_log(error_log);
+
+    // Read first, so that a bad file fails before the scan
+    let baseline = (cli.compare.as_deref())
+        .map(|path| -> anyhow::Result<GroupMap<u64>> {
+            let (groups, snapshot) = read_saved_scan(path)?;
+            if let Some(snapshot) = snapshot {
+                snapshot
+                    .check(&ScanSettings::new(&cli))
+                    .with_context(|| format!("could not compare with '{}'", path.display()))?;
+            }
+            Ok(groups)
+        })
+        .transpose()?;
+
+    // The first Ctrl-C stops the walk, the reading of the sizes or the hashing, and the results
+    // so far are printed. The second one exits.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || {
+        if handler_flag.swap(true, Ordering::Relaxed) {
+            std::process::exit(exit_code::INTERRUPTED.into());
+        }
+    })
+    .context("could not install the Ctrl-C handler")?;
+
+    // The status line would end up in the middle of redirected errors
+    let show_progress = atty::is(atty::Stream::Stderr) && !cli.quiet;
+    let walk_start = Instant::now();
+    let mut result = if let Some(path) = &cli.from_inventory {
+        let file = File::open(path)
+            .with_context(|| format!("could not open inventory '{}'", path.display()))?;
+        let mut aggregator = aggregator;
+        inventory::read_inventory(BufReader::new(file), &mut aggregator, &interrupted)?;
+        aggregator.finish()
+    } else if cli.stdin_sizes {
+        let mut aggregator = aggregator;
+        inventory::read_inventory(std::io::stdin().lock(), &mut aggregator, &interrupted)
+            .context("could not read the sizes from stdin")?;
+        aggregator.finish()
     } else {
-        FilesizeType::DiskUsage
+        let tracked = if cli.git_tracked {
+            let mut files = Vec::new();
+            for input in &inputs {
+                files.extend(git::tracked_files(input)?);
+            }
+            Some(files)
+        } else {
+            None
+        };
+        let walk = Walk::new(&inputs, num_threads, filesize_type, aggregator)
+            .exclude(cli.exclude_mount.clone())
+            .follow_links(cli.follow_links)
+            .force_dedup(cli.force_dedup)
+            // The length of the contents tells which files may be duplicates
+            .measure_both(cli.stats || cli.both_sizes || cli.duplicates)
+            .count_dirs(cli.du_compat || cli.inodes)
+            .regular_only(cli.regular_only)
+            .files(tracked)
+            .max_files(cli.max_files)
+            .timeout(cli.timeout)
+            .ext_filter(ExtFilter::new(&cli.ext, &cli.exclude_ext))
+            .system_filter(system_filter(&cli))
+            .sample(cli.sample.map(|rate| Sampler {
+                rate,
+                seed: cli.seed.unwrap_or_else(random_seed),
+            }))
+            .progress(cli.progress && show_progress)
+            .accurate_progress(cli.accurate_progress && show_progress);
+        #[cfg(target_os = "macos")]
+        let walk = walk.include_streams(cli.include_xattrs);
+        #[cfg(windows)]
+        let walk = walk.include_streams(cli.include_ads);
+        walk.run_with_cancel(&interrupted)?
     };
+    let mut timings = Timings {
+        walk: walk_start.elapsed(),
+        ..Default::default()
+    };
+    if cli.bench_mode {
+        // Nothing else is printed, so that only the walk is measured
+        println!(
+            "{} files\t{} bytes\t{} errors\t{:.3}s",
+            result.file_count,
+            result.total,
+            result.error_count(),
+            timings.walk.as_secs_f64()
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    result.baseline = baseline;
+    if cli.duplicates {
+        let hashing_start = Instant::now();
+        let candidates = std::mem::take(&mut result.candidates);
+        let duplicates = duplicates::find_duplicates(candidates, num_threads, &interrupted)?;
+        result.duplicates = Some(duplicates);
+        timings.hashing = Some(hashing_start.elapsed());
+    }
+    // Only the walk sets it itself. Piped into --stdin-sizes, the producer dies of the same
+    // Ctrl-C, and its output ending early looks like any other end of input.
+    if interrupted.load(Ordering::Relaxed) {
+        result.interrupted = true;
+        result.partial = true;
+    }
+    if !cli.quiet {
+        match cli.errors {
+            ErrorFormat::Human => {
+                print_errors(&mut std::io::stderr().lock(), &result, cli.verbose)?
+            }
+            ErrorFormat::Json => print_errors_json(&mut std::io::stderr().lock(), &result)?,
+        }
+        if let Some(err) = &result.error_log_failure {
+            eprintln!("[fss warning] could not write the error log: {err}");
+        }
+        print_extent_fallback(&mut std::io::stderr().lock(), &result)?;
+        print_saturation(&mut std::io::stderr().lock(), &result)?;
+        print_partial(&mut std::io::stderr().lock(), &result)?;
+        print_special_files(&mut std::io::stderr().lock(), &result, &cli)?;
+    }
 
-    let walk = Walk::new(&cli.inputs, cli.threads, filesize_type, cli.group_by);
-    let (total, sizes, errors) = walk.run()?;
-    print_result(
-        total,
-        sizes,
-        &errors,
-        cli.size_format,
-        cli.size,
-        cli.verbose,
-    );
-    Ok(())
+    if !cli.reads_inventory() && !inputs.is_empty() && result.unreadable_roots == inputs.len() {
+        return Ok(ExitCode::from(exit_code::FAILURE));
+    }
+
+    let output_start = Instant::now();
+    // A terminal shows every line as it's written, a pipe gets them in chunks
+    let mut stdout: Box<dyn Write> = if atty::is(atty::Stream::Stdout) {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(StreamWriter::new(std::io::stdout().lock()))
+    };
+    if cli.summary_to_stderr {
+        print_result_to(&mut stdout, Some(&mut std::io::stderr()), &result, &cli)?;
+    } else {
+        print_result(&mut stdout, &result, &cli)?;
+    }
+    stdout.flush()?;
+    if let Some(path) = &cli.save_groups {
+        let file =
+            File::create(path).with_context(|| format!("could not create '{}'", path.display()))?;
+        let mut file = BufWriter::new(file);
+        compare::write_groups(&mut file, &result)?;
+        file.flush()?;
+    }
+    if let Some(path) = &cli.save_snapshot {
+        let file =
+            File::create(path).with_context(|| format!("could not create '{}'", path.display()))?;
+        let mut file = BufWriter::new(file);
+        let settings = ScanSettings::new(&cli);
+        snapshot::write_snapshot(&mut file, &result, &inputs, &settings, SystemTime::now())?;
+        file.flush()?;
+    }
+    if cli.timings {
+        timings.output = output_start.elapsed();
+        print_timings(&mut std::io::stderr().lock(), &result, &timings, &cli)?;
+    }
+
+    if result.interrupted {
+        return Ok(ExitCode::from(exit_code::INTERRUPTED));
+    }
+    if result.partial {
+        return Ok(ExitCode::from(exit_code::PARTIAL_SCAN));
+    }
+    if cli.fail_on_empty && result.total == 0 {
+        return Ok(ExitCode::from(exit_code::EMPTY));
+    }
+    if cli.strict && result.error_count() > 0 {
+        return Ok(ExitCode::from(exit_code::PARTIAL_READ));
+    }
+    if matched_nothing(&result, &cli) {
+        if !cli.quiet {
+            eprintln!("fss: no files matched the given filters");
+        }
+        return Ok(ExitCode::from(exit_code::NO_MATCH));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Whether `--ext`, `--exclude-ext` or `--size` were given and left nothing to show. Without
+/// filters, an empty scan is a success.
+fn matched_nothing(result: &ScanResult, cli: &cli::Cli) -> bool {
+    let filters_files = !cli.ext.is_empty() || !cli.exclude_ext.is_empty();
+    if !filters_files && cli.size.is_empty() {
+        return false;
+    }
+    if cli.list_files {
+        // --size already selected the files
+        return result.listed.is_empty();
+    }
+    result.file_count == 0
+        || !result
+            .sizes
+            .iter()
+            .any(|(_group, &size)| cli.size.iter().all(|filter| filter.is_within(size)))
 }
@@ -0,0 +1,1943 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::prelude::*;
+use colored::{Color, ColoredString, Colorize};
+use num_format::{Locale, ToFormattedString};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::aggregate::{ListedFile, ScanResult, classify, path_str};
+use crate::cli::{Cli, GroupBy, LabelCase, OutputFormat, SizeFormat, TopBy, TopKey};
+use crate::compare::Trend;
+use crate::duplicates::Duplicates;
+use crate::filesize::Usage;
+use crate::groups::{AgeBucket, FileType, FineType};
+use crate::long_path;
+use crate::palette::Palette;
+
+/// Escape control characters (newlines, tabs, escape sequences...) in a group name, so a
+/// hostile or broken file name can't mess up the terminal or break the one-group-per-line output
+fn escape_control(s: &str) -> Cow<'_, str> {
+    if !s.chars().any(char::is_control) {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::with_capacity(s.len() + 4);
+    for c in s.chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn fmt_count(n: u64) -> String {
+    n.to_formatted_string(&Locale::en)
+}
+
+/// Disk usage as a percentage of the apparent size, below 100% for sparse or compressed files
+fn fmt_ratio(usage: Usage) -> String {
+    if usage.apparent == 0 {
+        return "-".to_string();
+    }
+    format!(
+        "{}%",
+        usage.allocated as u128 * 100 / usage.apparent as u128
+    )
+}
+
+/// Report the errors of a scan. Without `-v` this is a one-line warning, `-v` prints one line
+/// per kind of error and `-vv` prints every single error.
+pub fn print_errors<W: Write>(err: &mut W, result: &ScanResult, verbose: u8) -> io::Result<()> {
+    let errors = &result.errors;
+    match verbose {
+        0 => {
+            if result.error_count() > 0 {
+                writeln!(
+                    err,
+                    "[fss warning] the results may be tainted by {} errors. Re-run with -v/--verbose to see them.",
+                    fmt_count(result.error_count())
+                )?;
+            }
+            return Ok(());
+        }
+        1 => {
+            for summary in &result.error_summary {
+                let single = errors.iter().find(|e| e.kind() == summary.kind);
+                if let (1, Some(error)) = (summary.count, single) {
+                    writeln!(err, "fss: {}", error)?;
+                } else {
+                    writeln!(
+                        err,
+                        "fss: {}: {} paths (first: {})",
+                        summary.kind,
+                        fmt_count(summary.count),
+                        summary.first.to_string_lossy()
+                    )?;
+                }
+            }
+        }
+        _ => {
+            for error in errors {
+                writeln!(err, "fss: {}", error)?;
+            }
+            if errors.len() as u64 != result.error_count() {
+                writeln!(err, "fss: the errors were written to the error log")?;
+            }
+        }
+    }
+
+    if result.vanished > 0 {
+        writeln!(
+            err,
+            "fss: {} files disappeared during the scan",
+            fmt_count(result.vanished)
+        )?;
+    }
+    Ok(())
+}
+
+/// Report every error of a scan as a JSON object per line, for `--errors json`. The ones that
+/// went to `--error-log` are not kept to be printed.
+pub fn print_errors_json<W: Write>(err: &mut W, result: &ScanResult) -> io::Result<()> {
+    for error in &result.errors {
+        let path = long_path::strip_extended(error.path());
+        let lossy = path.to_string_lossy();
+        write!(
+            err,
+            "{{\"kind\":\"{}\",\"path\":{}",
+            error.kind().id(),
+            json_string(&lossy)
+        )?;
+        if let Cow::Owned(_) = lossy {
+            let bytes = BASE64_STANDARD.encode(path.as_os_str().as_encoded_bytes());
+            write!(err, ",\"path_bytes\":\"{bytes}\"")?;
+        }
+        writeln!(err, ",\"message\":{}}}", json_string(&error.to_string()))?;
+    }
+    Ok(())
+}
+
+/// `s` as a JSON string, quoted and escaped
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Print the extensions with a type, for `fss types`, as the extension, its type and its finer
+/// type, sorted by type
+pub fn print_types<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut extensions: Vec<(FileType, FineType, &str)> = FileType::known_extensions()
+        .into_iter()
+        .map(|ext| {
+            (
+                FileType::get_filetype(&ext),
+                FineType::get_finetype(&ext),
+                ext,
+            )
+        })
+        .collect();
+    extensions.sort_unstable();
+    for (file_type, fine_type, ext) in extensions {
+        writeln!(out, "{ext}\t{file_type}\t{fine_type}")?;
+    }
+    Ok(())
+}
+
+/// Tell that some files were counted as exclusive because their extents could not be read
+pub fn print_extent_fallback<W: Write>(err: &mut W, result: &ScanResult) -> io::Result<()> {
+    if result.unknown_extents > 0 {
+        writeln!(
+            err,
+            "fss: the extents of {} files could not be read (their filesystem may not support \
+             FIEMAP), their data is counted as exclusive",
+            fmt_count(result.unknown_extents)
+        )?;
+    }
+    Ok(())
+}
+
+/// What `--include-xattrs` or `--include-ads` add to the sizes
+#[cfg(windows)]
+const STREAMS: &str = "alternate data streams";
+#[cfg(not(windows))]
+const STREAMS: &str = "extended attributes";
+
+/// Tell that the sizes hit the largest number they can hold
+pub fn print_saturation<W: Write>(err: &mut W, result: &ScanResult) -> io::Result<()> {
+    if result.saturated {
+        writeln!(
+            err,
+            "[fss warning] some sizes exceed {} bytes and were capped, they are lower bounds",
+            fmt_count(u64::MAX)
+        )?;
+    }
+    Ok(())
+}
+
+/// Tell how many special files were left out by `--regular-only`
+pub fn print_special_files<W: Write>(
+    err: &mut W,
+    result: &ScanResult,
+    cli: &Cli,
+) -> io::Result<()> {
+    if cli.regular_only && result.special_files > 0 {
+        writeln!(
+            err,
+            "fss: {} special files (FIFOs, sockets, devices) were not counted",
+            fmt_count(result.special_files)
+        )?;
+    }
+    Ok(())
+}
+
+/// Tell that the scan was stopped before reaching every file
+pub fn print_partial<W: Write>(err: &mut W, result: &ScanResult) -> io::Result<()> {
+    if result.interrupted {
+        writeln!(err, "[fss warning] interrupted, the results are partial")?;
+    } else if result.partial {
+        writeln!(
+            err,
+            "[fss warning] the scan was stopped by --max-files, --limit or --timeout, the results are partial"
+        )?;
+    }
+    Ok(())
+}
+
+pub fn print_result<W: Write>(out: &mut W, result: &ScanResult, cli: &Cli) -> io::Result<()> {
+    print_result_to(out, None, result, cli)
+}
+
+/// Like [`print_result`], but the total and the notes below it go to `footer` when given, so
+/// the groups can be piped while the summary stays on the terminal
+pub fn print_result_to<W: Write>(
+    out: &mut W,
+    footer: Option<&mut dyn Write>,
+    result: &ScanResult,
+    cli: &Cli,
+) -> io::Result<()> {
+    let size_format = SizeFormat::new(cli);
+
+    if cli.compact {
+        // Keep this format stable, scripts and status bars parse it.
+        let size_format = SizeFormat {
+            show_bytes: false,
+            ..size_format
+        };
+        return writeln!(
+            out,
+            "{} ({} files)",
+            size_format.format(result.total),
+            result.file_count
+        );
+    }
+
+    let mut footer = footer;
+    for (i, section) in std::iter::once(result).chain(&result.sections).enumerate() {
+        if !result.sections.is_empty() {
+            let title = format!("By {}:", section_title(section.group_by));
+            let blank = if i > 0 { "\n" } else { "" };
+            writeln!(out, "{blank}{}", title.bold().cyan())?;
+        }
+        let footer = footer
+            .as_mut()
+            .map(|footer| &mut **footer as &mut dyn Write);
+        print_groups(out, footer, section, cli)?;
+    }
+
+    let out: &mut dyn Write = match footer {
+        Some(footer) => footer,
+        None => out,
+    };
+    print_notes(out, result, cli)
+}
+
+/// How the sections of several `--group-by` are titled, "By <title>:"
+fn section_title(group_by: GroupBy) -> &'static str {
+    match group_by {
+        GroupBy::Extension => "extension",
+        GroupBy::Type => "type",
+        GroupBy::FineType => "finer type",
+        GroupBy::FileName => "file name",
+        GroupBy::Directory => "directory",
+        GroupBy::AgeBucket => "age",
+        GroupBy::Path => "path",
+        GroupBy::CreatedYear => "year of creation",
+        GroupBy::CreatedMonth => "month of creation",
+        GroupBy::Access => "access",
+    }
+}
+
+/// Print the groups of `result` and their total, the total to `footer` when given
+fn print_groups<W: Write>(
+    out: &mut W,
+    footer: Option<&mut dyn Write>,
+    result: &ScanResult,
+    cli: &Cli,
+) -> io::Result<()> {
+    let size_format = SizeFormat::new(cli);
+    let mut sorted_sizes: Vec<(&str, &u64)> = result.sizes.iter().collect();
+    let count = |group: &str| result.file_counts.get(group).copied().unwrap_or_default();
+    // Ties are broken by name so that the order doesn't depend on how the groups are stored
+    match (cli.top_by.map(|top_by| top_by.key), result.group_by) {
+        (Some(TopKey::Count), _) => sorted_sizes
+            .sort_unstable_by(|(k1, _), (k2, _)| count(k1).cmp(&count(k2)).then(k1.cmp(k2))),
+        // Ages read best in chronological order, newest first
+        (None, GroupBy::AgeBucket) => {
+            sorted_sizes.sort_unstable_by_key(|(k, _v)| AgeBucket::rank(k))
+        }
+        // Dates too, oldest first and the unknown last
+        (None, GroupBy::CreatedYear | GroupBy::CreatedMonth) => {
+            let unknown = AgeBucket::Unknown.to_string();
+            sorted_sizes.sort_unstable_by_key(|(k, _v)| (*k == unknown, *k))
+        }
+        _ => {
+            sorted_sizes.sort_unstable_by(|(k1, v1), (k2, v2)| v1.cmp(v2).then_with(|| k1.cmp(k2)))
+        }
+    }
+    let depth = |group: &str| result.depths.get(group).copied();
+    if cli.by_depth {
+        // Stable, the groups of a depth stay sorted by size
+        sorted_sizes.sort_by_key(|(group, _size)| depth(group));
+    }
+    sorted_sizes.retain(|(_group, size)| cli.size.iter().all(|f| f.is_within(**size)));
+    if let Some(TopBy { n, .. }) = cli.top_by {
+        // The largest are last
+        sorted_sizes.drain(..sorted_sizes.len().saturating_sub(n));
+    }
+    if let Some(baseline) = &result.baseline {
+        // Listed first, with nothing left
+        let removed: Vec<(&str, &u64)> = baseline
+            .keys()
+            .filter(|group| !result.sizes.contains_key(group))
+            .map(|group| (group, &0))
+            .collect();
+        sorted_sizes.splice(0..0, removed);
+    }
+    let shared = |group: &str| result.shared_sizes.get(group).copied().unwrap_or_default();
+    let usage = |group: &str| result.usage_sizes.get(group).copied().unwrap_or_default();
+    let (total_label, total, shared_total, usage_total) = if cli.filtered_total {
+        // Named differently so it's not mistaken for the grand total
+        (
+            "Shown total",
+            sorted_sizes
+                .iter()
+                .fold(0, |sum: u64, (_group, size)| sum.saturating_add(**size)),
+            sorted_sizes.iter().fold(0, |sum: u64, (group, _size)| {
+                sum.saturating_add(shared(group))
+            }),
+            sorted_sizes
+                .iter()
+                .fold(Usage::default(), |sum, (group, _size)| {
+                    let usage = usage(group);
+                    Usage {
+                        apparent: sum.apparent.saturating_add(usage.apparent),
+                        allocated: sum.allocated.saturating_add(usage.allocated),
+                    }
+                }),
+        )
+    } else {
+        (
+            "Total",
+            result.total,
+            result.shared_total,
+            Usage {
+                apparent: result.apparent_total,
+                allocated: result.allocated_total,
+            },
+        )
+    };
+    // The size columns of a group: its size, its own and its shared data, or both of its sizes
+    let columns = |size: u64, shared: u64, usage: Usage| -> Vec<String> {
+        if cli.dedupe_extents {
+            vec![
+                size_format.format(size.saturating_sub(shared)),
+                size_format.format(shared),
+            ]
+        } else if cli.both_sizes {
+            vec![
+                size_format.format(usage.apparent),
+                size_format.format(usage.allocated),
+                fmt_ratio(usage),
+            ]
+        } else {
+            vec![size_format.format(size)]
+        }
+    };
+    let mut headers: Vec<&str> = if cli.dedupe_extents {
+        vec!["Exclusive", "Shared"]
+    } else if cli.both_sizes {
+        vec!["Apparent", "On disk", "Ratio"]
+    } else {
+        vec!["Size"]
+    };
+    let show_depth = result.group_by == GroupBy::Path;
+    if show_depth {
+        headers.push("Depth");
+    }
+    if result.baseline.is_some() {
+        headers.push("Trend");
+    }
+    let palette = Palette::from_env();
+    let paint = |group: &str, size: u64| Paint {
+        color: palette.color(result.group_by, group),
+        alert: Alert::new(size, cli),
+    };
+    let cells = |group: &str, size: u64| {
+        let mut cells = columns(size, shared(group), usage(group));
+        if show_depth {
+            // '(other groups)' and removed groups have none
+            cells.push(
+                depth(group)
+                    .map(|depth| depth.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if let Some(baseline) = &result.baseline {
+            let trend = Trend::new(
+                baseline.get(group).copied(),
+                result.sizes.get(group).copied(),
+            );
+            cells.push(trend.to_string());
+        }
+        cells
+    };
+    let label = |group| cli.label_case.apply(escape_control(group));
+    let row = |(group, &size)| (cells(group, size), label(group), paint(group, size));
+    let mut total_columns = columns(total, shared_total, usage_total);
+    if show_depth {
+        total_columns.push(String::new());
+    }
+    if let Some(baseline) = &result.baseline {
+        let saved_total = baseline
+            .iter()
+            .fold(0, |sum: u64, (_group, size)| sum.saturating_add(*size));
+        total_columns.push(Trend::new(Some(saved_total), Some(total)).to_string());
+    }
+    let total_label = match (result.sampler, result.partial) {
+        (Some(_), true) => Cow::Owned(format!(
+            "Estimated {} (partial)",
+            total_label.to_lowercase()
+        )),
+        (Some(_), false) => Cow::Owned(format!("Estimated {}", total_label.to_lowercase())),
+        (None, true) => Cow::Owned(format!("{total_label} (partial)")),
+        (None, false) => Cow::Borrowed(total_label),
+    };
+
+    let terminal_width = || {
+        atty::is(atty::Stream::Stdout)
+            .then(terminal_size::terminal_size)
+            .flatten()
+            .map(|(width, _height)| width.0 as usize)
+    };
+    let table_width = match cli.output {
+        OutputFormat::Table => terminal_width(),
+        _ => None,
+    };
+    let separator = cli.separator.as_deref().unwrap_or("\t");
+    let base = cli
+        .relative_to
+        .as_deref()
+        .map(base_spellings)
+        .unwrap_or_default();
+    // The table ends with its own total
+    let total_lines = if let Some(duplicates) = &result.duplicates {
+        let wasted = size_format.format(duplicates.wasted);
+        let label_case = cli.label_case;
+        print_duplicates(out, duplicates, &size_format, separator, &base, label_case)?;
+        Some(format!("\n{}\n{wasted}", "Total wasted: ".bold().cyan()))
+    } else if cli.list_files || cli.explain {
+        let total = size_format.format(total);
+        let width = result
+            .listed
+            .iter()
+            .map(|file| size_format.format(file.size).width())
+            .fold(total.width(), usize::max);
+        let columns = if cli.explain {
+            FileColumns::Explained(cli.label_case)
+        } else {
+            FileColumns::Group(cli.label_case)
+        };
+        let files = &result.listed;
+        print_files(out, files, &size_format, separator, width, columns, &base)?;
+        Some(format!(
+            "\n{}\n{total: >width$}",
+            format!("{total_label}: ").bold().cyan(),
+        ))
+    } else if let Some(n) = cli.chart {
+        let bars: Vec<Bar> = sorted_sizes[sorted_sizes.len().saturating_sub(n as usize)..]
+            .iter()
+            .map(|&(group, &size)| Bar {
+                label: label(group),
+                size,
+                paint: paint(group, size),
+            })
+            .collect();
+        let width = terminal_width().unwrap_or(CHART_WIDTH);
+        print_chart(out, &bars, total, &size_format, width)?;
+        Some(format!(
+            "\n{} {}",
+            format!("{total_label}:").bold().cyan(),
+            size_format.format(total)
+        ))
+    } else if let Some(width) = table_width {
+        let rows: Vec<Row> = sorted_sizes.into_iter().map(row).collect();
+        print_table(out, &headers, &rows, (&total_label, &total_columns), width)?;
+        None
+    } else {
+        // Each column is right-aligned to its widest cell. There can be millions of groups, so
+        // the cells are formatted twice rather than kept around until the widths are known.
+        let mut widths: Vec<usize> = total_columns.iter().map(|cell| cell.width()).collect();
+        for &(group, &size) in &sorted_sizes {
+            for (width, cell) in widths.iter_mut().zip(cells(group, size)) {
+                *width = (*width).max(cell.width());
+            }
+        }
+        for (columns, group, paint) in sorted_sizes.into_iter().map(row) {
+            // Coloring allocates, skipped for the usual uncolored groups
+            if paint.alert.is_none() {
+                for (column, width) in columns.iter().zip(&widths) {
+                    write!(out, "{column: >width$}{separator}")?;
+                }
+            } else {
+                for (column, width) in columns.iter().zip(&widths) {
+                    write!(
+                        out,
+                        "{}{separator}",
+                        paint.cell(&format!("{column: >width$}"))
+                    )?;
+                }
+            }
+            if paint.color.is_none() && paint.alert.is_none() {
+                writeln!(out, "{}", group)?;
+            } else {
+                writeln!(out, "{}", paint.name(&group))?;
+            }
+        }
+
+        Some(format!(
+            "\n{}\n{}",
+            format!("{total_label}: ").bold().cyan(),
+            total_columns
+                .iter()
+                .zip(&widths)
+                .map(|(column, width)| format!("{column: >width$}"))
+                .collect::<Vec<_>>()
+                .join(separator)
+                // The depth column is blank
+                .trim_end()
+        ))
+    };
+
+    let out: &mut dyn Write = match footer {
+        Some(footer) => footer,
+        None => out,
+    };
+    if let Some(total_lines) = total_lines {
+        writeln!(out, "{total_lines}")?;
+    }
+    if !result.root_sizes.is_empty() {
+        writeln!(out, "\n{}", "Per input:".bold().cyan())?;
+        let sizes: Vec<String> = (result.root_sizes.iter())
+            .map(|(_root, size)| size_format.format(*size))
+            .collect();
+        let width = sizes
+            .iter()
+            .map(|size| size.width())
+            .max()
+            .unwrap_or_default();
+        for ((root, _size), size) in result.root_sizes.iter().zip(sizes) {
+            let root = path_str(root);
+            writeln!(out, "{size: >width$}{separator}{}", escape_control(&root))?;
+        }
+    }
+    if cli.largest.is_some() && !result.listed.is_empty() {
+        writeln!(out, "\n{}", "Largest files:".bold().cyan())?;
+        let width = result
+            .listed
+            .iter()
+            .map(|file| size_format.format(file.size).width())
+            .max()
+            .unwrap_or_default();
+        let largest_first = result.listed.iter().rev();
+        print_files(
+            out,
+            largest_first,
+            &size_format,
+            separator,
+            width,
+            FileColumns::Path,
+            &base,
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the notes below the total: what it includes and how it was counted
+fn print_notes(out: &mut dyn Write, result: &ScanResult, cli: &Cli) -> io::Result<()> {
+    let size_format = SizeFormat::new(cli);
+    if let Some(duplicates) = result.duplicates.as_ref().filter(|d| d.unreadable > 0) {
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "({} files of the same length as another could not be read to compare them)",
+                fmt_count(duplicates.unreadable)
+            )
+            .dimmed()
+        )?;
+    }
+    if result.stream_total > 0 {
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "(including {} of {STREAMS})",
+                size_format.format(result.stream_total)
+            )
+            .dimmed()
+        )?;
+    }
+
+    if let Some(sampler) = result.sampler {
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "(estimated from {} files read with --sample {} --seed {})",
+                fmt_count(result.file_count),
+                sampler.rate,
+                sampler.seed
+            )
+            .dimmed()
+        )?;
+    }
+
+    if result.deduplicated > 0 {
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "({} hardlinked duplicates were deduplicated, use --no-dedup to count them)",
+                result.deduplicated
+            )
+            .dimmed()
+        )?;
+    }
+
+    if cli.stats {
+        print_stats(out, result)?;
+    }
+    Ok(())
+}
+
+/// Narrowest the group column gets when the terminal is too small for the table
+const MIN_GROUP_WIDTH: usize = 8;
+
+/// Cut `s` to at most `width` columns, marking the cut with an ellipsis
+fn truncate_to_width(s: &str, width: usize) -> Cow<'_, str> {
+    if s.width() <= width {
+        return Cow::Borrowed(s);
+    }
+    let mut truncated = String::with_capacity(width + 2);
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        used += w;
+    }
+    truncated.push('…');
+    Cow::Owned(truncated)
+}
+
+/// Width of the chart when the output is not a terminal
+const CHART_WIDTH: usize = 80;
+
+/// Narrowest the bars of the chart get
+const MIN_BAR_WIDTH: usize = 10;
+
+/// A group at or above `--warn` or `--highlight`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alert {
+    Warn,
+    Highlight,
+}
+
+impl Alert {
+    fn new(size: u64, cli: &Cli) -> Option<Self> {
+        if cli.highlight.is_some_and(|threshold| size >= threshold) {
+            Some(Alert::Highlight)
+        } else if cli.warn.is_some_and(|threshold| size >= threshold) {
+            Some(Alert::Warn)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a group is colored: its name by its type, and all of it past a size threshold
+#[derive(Debug, Default, Clone, Copy)]
+struct Paint {
+    color: Option<Color>,
+    alert: Option<Alert>,
+}
+
+impl Paint {
+    fn name(&self, name: &str) -> ColoredString {
+        match (self.alert, self.color) {
+            (None, Some(color)) => name.color(color),
+            _ => self.cell(name),
+        }
+    }
+
+    fn cell(&self, cell: &str) -> ColoredString {
+        match self.alert {
+            Some(Alert::Highlight) => cell.red().bold(),
+            Some(Alert::Warn) => cell.yellow(),
+            None => cell.normal(),
+        }
+    }
+}
+
+/// A group of the chart
+struct Bar<'a> {
+    label: Cow<'a, str>,
+    size: u64,
+    paint: Paint,
+}
+
+/// What is printed about each file between its size and its path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileColumns {
+    Path,
+    /// The group it was added to, in this case
+    Group(LabelCase),
+    /// The group, the extension and the types it maps to
+    Explained(LabelCase),
+}
+
+/// Print the files kept by `--list-files`, `--explain` or `--largest` as their size,
+/// right-aligned to `width`, the `columns` and their path
+fn print_files<'a, W: Write + ?Sized>(
+    out: &mut W,
+    files: impl IntoIterator<Item = &'a ListedFile>,
+    size_format: &SizeFormat,
+    separator: &str,
+    width: usize,
+    columns: FileColumns,
+    base: &[PathBuf],
+) -> io::Result<()> {
+    for file in files {
+        write!(out, "{: >width$}{separator}", size_format.format(file.size))?;
+        if let FileColumns::Group(label_case) | FileColumns::Explained(label_case) = columns {
+            let group = label_case.apply(escape_control(&file.group));
+            write!(out, "{group}{separator}")?;
+        }
+        if let FileColumns::Explained(_) = columns {
+            let (ext, file_type, fine_type) = classify(&file.path);
+            let ext = if ext.is_empty() { "(none)" } else { &ext };
+            write!(
+                out,
+                "{}{separator}{file_type}{separator}{fine_type}{separator}",
+                escape_control(ext)
+            )?;
+        }
+        let path = display_path(&file.path, base);
+        writeln!(out, "{}", escape_control(&path_str(&path)))?;
+    }
+    Ok(())
+}
+
+/// The spellings of the `--relative-to` base that paths are stripped of: as given, and with
+/// the links resolved, as the inputs may be spelled either way
+fn base_spellings(base: &Path) -> Vec<PathBuf> {
+    let mut spellings: Vec<PathBuf> = std::path::absolute(base).into_iter().collect();
+    if let Ok(canonical) = base.canonicalize()
+        && !spellings.contains(&canonical)
+    {
+        spellings.push(canonical);
+    }
+    spellings
+}
+
+/// `path` as printed: relative to the `--relative-to` base when below one of its spellings,
+/// absolute when outside of it, as found without one
+fn display_path<'a>(path: &'a Path, base: &[PathBuf]) -> Cow<'a, Path> {
+    let path = long_path::strip_extended(path);
+    if base.is_empty() {
+        return path;
+    }
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    match base
+        .iter()
+        .find_map(|base| absolute.strip_prefix(base).ok())
+    {
+        Some(relative) if relative.as_os_str().is_empty() => Cow::Borrowed(Path::new(".")),
+        Some(relative) => Cow::Owned(relative.to_path_buf()),
+        None => Cow::Owned(absolute),
+    }
+}
+
+/// Print the sets of identical files as the space their extra copies take, their number and
+/// size, then their paths indented. The wasted space of each group follows, aligned like the
+/// groups.
+fn print_duplicates<W: Write>(
+    out: &mut W,
+    duplicates: &Duplicates,
+    size_format: &SizeFormat,
+    separator: &str,
+    base: &[PathBuf],
+    label_case: LabelCase,
+) -> io::Result<()> {
+    let width = duplicates
+        .sets
+        .iter()
+        .map(|set| size_format.format(set.wasted()).width())
+        .max()
+        .unwrap_or_default();
+    for set in &duplicates.sets {
+        writeln!(
+            out,
+            "{: >width$}{separator}{} copies of {}",
+            size_format.format(set.wasted()),
+            set.paths.len(),
+            size_format.format(set.size)
+        )?;
+        for path in &set.paths {
+            let path = display_path(path, base);
+            writeln!(
+                out,
+                "{: >width$}{separator}{}",
+                "",
+                escape_control(&path_str(&path))
+            )?;
+        }
+    }
+
+    let mut by_group: Vec<(&str, &u64)> = duplicates.wasted_by_group.iter().collect();
+    if by_group.is_empty() {
+        return Ok(());
+    }
+    by_group.sort_unstable_by(|(k1, v1), (k2, v2)| v1.cmp(v2).then_with(|| k1.cmp(k2)));
+    writeln!(out, "\n{}", "Wasted by group:".bold().cyan())?;
+    let width = by_group
+        .iter()
+        .map(|(_group, wasted)| size_format.format(**wasted).width())
+        .max()
+        .unwrap_or_default();
+    for (group, wasted) in by_group {
+        writeln!(
+            out,
+            "{: >width$}{separator}{}",
+            size_format.format(*wasted),
+            label_case.apply(escape_control(group))
+        )?;
+    }
+    Ok(())
+}
+
+/// Print each group as its name, a bar and its share of `total`, at most `width` columns wide.
+/// A full bar is the whole total. The names are truncated when there is not enough room.
+fn print_chart<W: Write>(
+    out: &mut W,
+    bars: &[Bar],
+    total: u64,
+    size_format: &SizeFormat,
+    width: usize,
+) -> io::Result<()> {
+    // Eighths of a block, for bars finer than one column
+    const PARTIAL_BLOCKS: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+
+    let share = |size: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            size as f64 / total as f64
+        }
+    };
+    let tails: Vec<String> = bars
+        .iter()
+        .map(|bar| {
+            format!(
+                " {: >5.1}% ({})",
+                share(bar.size) * 100.0,
+                size_format.format(bar.size)
+            )
+        })
+        .collect();
+    let tail_width = tails.iter().map(|tail| tail.width()).max().unwrap_or(0);
+    // The label, " │ ", the bar and the tail
+    let max_label_width = (width / 3).max(MIN_GROUP_WIDTH);
+    let label_width = bars
+        .iter()
+        .map(|bar| bar.label.width())
+        .max()
+        .unwrap_or(0)
+        .min(max_label_width);
+    let bar_width = width
+        .saturating_sub(label_width + 3 + tail_width)
+        .max(MIN_BAR_WIDTH);
+
+    for (bar, tail) in bars.iter().zip(&tails) {
+        let label = truncate_to_width(&bar.label, label_width);
+        let label_padding = " ".repeat(label_width - label.width());
+        let eighths = (share(bar.size) * (bar_width * 8) as f64).round() as usize;
+        let mut blocks = "█".repeat(eighths / 8);
+        blocks.push_str(PARTIAL_BLOCKS[eighths % 8]);
+        let bar_padding = " ".repeat(bar_width.saturating_sub(blocks.chars().count()));
+        writeln!(
+            out,
+            "{}{label_padding} │ {}{bar_padding}{}",
+            bar.paint.name(&label),
+            bar.paint.name(&blocks),
+            bar.paint.cell(tail)
+        )?;
+    }
+    Ok(())
+}
+
+/// The size columns of a group, its name and how to color them
+type Row<'a> = (Vec<String>, Cow<'a, str>, Paint);
+
+/// Print the groups and the total in a bordered table at most `width` columns wide. Each row
+/// has one cell per header, followed by the group, and the total is a label and its cells.
+/// Group names are truncated when they don't fit.
+fn print_table<W: Write>(
+    out: &mut W,
+    headers: &[&str],
+    rows: &[Row],
+    (total_label, total): (&str, &[String]),
+    width: usize,
+) -> io::Result<()> {
+    const GROUP_HEADER: &str = "Group";
+
+    let column_widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|(cells, _, _)| cells[i].width())
+                .chain([header.width(), total[i].width()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    // Borders and padding: "│ " + cell + " │ " ... + group + " │"
+    let used: usize = column_widths.iter().map(|w| w + 3).sum::<usize>() + 4;
+    let max_group_width = width.saturating_sub(used).max(MIN_GROUP_WIDTH);
+    let group_width = rows
+        .iter()
+        .map(|(_, group, _)| group.width())
+        .chain([GROUP_HEADER.width(), total_label.width()])
+        .max()
+        .unwrap_or_default()
+        .min(max_group_width);
+
+    let rule = |left: &str, mid: &str, right: &str| {
+        let mut line = left.to_string();
+        for width in &column_widths {
+            line.push_str(&"─".repeat(width + 2));
+            line.push_str(mid);
+        }
+        line.push_str(&"─".repeat(group_width + 2));
+        line.push_str(right);
+        line
+    };
+    // Padded outside of the colors, the escape codes have no width
+    let row = |cells: &[String], group: &str, paint: Paint| {
+        let mut line = String::from("│");
+        for (cell, width) in cells.iter().zip(&column_widths) {
+            // Pad by display width, `format!` would count chars
+            let padding = " ".repeat(width - cell.width());
+            line.push_str(&format!(" {padding}{} │", paint.cell(cell)));
+        }
+        let group = truncate_to_width(group, group_width);
+        let padding = " ".repeat(group_width - group.width());
+        line.push_str(&format!(" {}{padding} │", paint.name(&group)));
+        line
+    };
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+
+    writeln!(out, "{}", rule("┌", "┬", "┐"))?;
+    writeln!(out, "{}", row(&headers, GROUP_HEADER, Paint::default()))?;
+    writeln!(out, "{}", rule("├", "┼", "┤"))?;
+    for (columns, group, paint) in rows {
+        writeln!(out, "{}", row(columns, group, *paint))?;
+    }
+    writeln!(out, "{}", rule("├", "┼", "┤"))?;
+    writeln!(out, "{}", row(total, total_label, Paint::default()))?;
+    writeln!(out, "{}", rule("└", "┴", "┘"))
+}
+
+fn print_stats<W: Write + ?Sized>(out: &mut W, result: &ScanResult) -> io::Result<()> {
+    writeln!(out, "\n{}", "Stats: ".bold().cyan())?;
+    writeln!(out, "{: >10}\tfiles", result.file_count)?;
+    writeln!(out, "{: >10}\terrors", result.error_count())?;
+    writeln!(out, "{: >10}\tvanished", result.vanished)?;
+    writeln!(out, "{: >10}\tthreads", result.threads)?;
+    if result.special_files > 0 {
+        writeln!(
+            out,
+            "{: >10}\tspecial files (FIFOs, sockets, devices)",
+            result.special_files
+        )?;
+    }
+    if result.stream_total > 0 {
+        writeln!(out, "{: >10}\tbytes of {STREAMS}", result.stream_total)?;
+    }
+    if result.apparent_total > 0 || result.allocated_total > 0 {
+        writeln!(out, "{: >10}\tbytes apparent size", result.apparent_total)?;
+        writeln!(out, "{: >10}\tbytes allocated", result.allocated_total)?;
+        writeln!(
+            out,
+            "{: >10}\tsparse or compressed files",
+            result.sparse_files
+        )?;
+    }
+    Ok(())
+}
+
+/// How long the phases of a run took, for `--timings`
+#[derive(Debug, Default)]
+pub struct Timings {
+    /// Reading the files and grouping them
+    pub walk: Duration,
+    /// Hashing the files of `--duplicates`
+    pub hashing: Option<Duration>,
+    /// Printing the results and saving the groups
+    pub output: Duration,
+}
+
+/// Print the time of each phase, and the files and bytes read per second during the walk
+pub fn print_timings<W: Write>(
+    err: &mut W,
+    result: &ScanResult,
+    timings: &Timings,
+    cli: &Cli,
+) -> io::Result<()> {
+    let size_format = SizeFormat::new(cli);
+    let seconds = |duration: Duration| format!("{:.3}s", duration.as_secs_f64());
+    // A walk too short to measure is reported as if it took a second
+    let per_second = |n: u64| match timings.walk.as_secs_f64() {
+        secs if secs > 0.0 => (n as f64 / secs) as u64,
+        _ => n,
+    };
+    writeln!(err, "\n{}", "Timings: ".bold().cyan())?;
+    writeln!(err, "{: >10}\twalk", seconds(timings.walk))?;
+    if let Some(hashing) = timings.hashing {
+        writeln!(err, "{: >10}\thashing duplicates", seconds(hashing))?;
+    }
+    writeln!(err, "{: >10}\toutput", seconds(timings.output))?;
+    writeln!(
+        err,
+        "{: >10}\tfiles per second",
+        fmt_count(per_second(result.file_count))
+    )?;
+    // With --count-mode files the total is the number of files again
+    if !cli.counts_entries() {
+        writeln!(
+            err,
+            "{: >10}\tper second",
+            size_format.format(per_second(result.total))
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicates::DuplicateSet;
+    use crate::group_map::GroupMap;
+    use crate::sample::Sampler;
+    use clap::Parser;
+
+    fn scan_result() -> ScanResult {
+        let mut result = ScanResult {
+            total: 1_500,
+            file_count: 3,
+            ..Default::default()
+        };
+        result.sizes.insert("png", 1_000);
+        result.sizes.insert("txt", 500);
+        result
+    }
+
+    fn render(args: &[&str]) -> String {
+        let cli = Cli::parse_from(std::iter::once("fss").chain(args.iter().copied()));
+        let mut out = Vec::new();
+        print_result(&mut out, &scan_result(), &cli).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn timings_are_given_per_phase() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes"]);
+        let mut result = scan_result();
+        result.file_count = 3_000;
+        let timings = Timings {
+            walk: Duration::from_millis(1_500),
+            hashing: None,
+            output: Duration::from_millis(20),
+        };
+        let mut err = Vec::new();
+        print_timings(&mut err, &result, &timings, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "\nTimings: \n    1.500s\twalk\n    0.020s\toutput\n     2,000\tfiles per second\n\
+             \x20     1000\tper second\n"
+        );
+
+        let timings = Timings {
+            hashing: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let mut err = Vec::new();
+        print_timings(&mut err, &result, &timings, &cli).unwrap();
+        let err = String::from_utf8(err).unwrap();
+        assert!(err.contains("    2.000s\thashing duplicates\n"), "{err}");
+        assert!(err.contains("     3,000\tfiles per second"), "{err}");
+    }
+
+    #[test]
+    fn stats_are_printed_after_the_total() {
+        let out = render(&["--stats", "-s", "bytes"]);
+        assert!(out.ends_with(
+            "         3\tfiles\n         0\terrors\n         0\tvanished\n         0\tthreads\n"
+        ));
+    }
+
+    #[test]
+    fn trends_are_shown_against_the_saved_scan() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes"]);
+        let mut result = scan_result();
+        result.sizes.insert("log", 300);
+        let mut baseline = GroupMap::new();
+        baseline.insert("png", 800);
+        baseline.insert("txt", 520);
+        baseline.insert("gz", 90);
+        result.baseline = Some(baseline);
+
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "   0\tremoved\tgz\n\
+             \x20300\t    new\tlog\n\
+             \x20500\t  ↓ -4%\ttxt\n\
+             1000\t ↑ +25%\tpng\n\
+             \n{}\n1500\t  ↑ +6%\n",
+                "Total: ".bold().cyan()
+            )
+        );
+    }
+
+    #[test]
+    fn top_by_is_parsed() {
+        let top_by = |spec: &str| Cli::try_parse_from(["fss", "--top-by", spec]).map(|c| c.top_by);
+        assert_eq!(
+            top_by("size:10").unwrap(),
+            Some(TopBy {
+                key: TopKey::Size,
+                n: 10
+            })
+        );
+        assert_eq!(
+            top_by("count:5").unwrap(),
+            Some(TopBy {
+                key: TopKey::Count,
+                n: 5
+            })
+        );
+        let error = top_by("name:3").unwrap_err().to_string();
+        assert!(error.contains("unknown key 'name'"), "{error}");
+        for spec in ["size", "size:0", "size:-1", "count:many"] {
+            assert!(top_by(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn top_by_keeps_the_largest_groups() {
+        let mut result = scan_result();
+        result.sizes.insert("log", 700);
+        result.file_counts.insert("png", 1);
+        result.file_counts.insert("txt", 5);
+        result.file_counts.insert("log", 2);
+        let render = |top_by: &str| {
+            let cli = Cli::parse_from(["fss", "-s", "bytes", "--top-by", top_by]);
+            let mut out = Vec::new();
+            print_result(&mut out, &result, &cli).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = render("size:2");
+        assert!(out.starts_with(" 700\tlog\n1000\tpng\n\n"), "{out}");
+        let out = render("count:2");
+        assert!(out.starts_with(" 700\tlog\n 500\ttxt\n\n"), "{out}");
+        // The total is still the one of every group
+        assert!(out.contains("1500"), "{out}");
+        let out = render("count:10");
+        assert!(
+            out.starts_with("1000\tpng\n 700\tlog\n 500\ttxt\n\n"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn fixed_units_apply_to_the_groups_and_the_total() {
+        let out = render(&["--unit", "kib"]);
+        assert!(out.starts_with("0.49\ttxt\n0.98\tpng\n"), "{out}");
+        assert!(out.ends_with("\n1.46\n"), "{out}");
+
+        let out = render(&["--unit", "kb", "--unit-suffix", "--precision", "1"]);
+        assert!(out.starts_with("0.5 kB\ttxt\n1.0 kB\tpng\n"), "{out}");
+        assert!(out.ends_with("\n1.5 kB\n"), "{out}");
+
+        assert!(Cli::try_parse_from(["fss", "--unit-suffix"]).is_err());
+    }
+
+    #[test]
+    fn digits_are_grouped_on_request() {
+        let mut result = scan_result();
+        result.total = 139_482_003_948;
+        let render = |args: &[&str]| {
+            let cli = Cli::parse_from(std::iter::once("fss").chain(args.iter().copied()));
+            let mut out = Vec::new();
+            print_result(&mut out, &result, &cli).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = render(&["-s", "bytes", "--group-digits"]);
+        assert!(
+            out.contains("            500\ttxt\n          1,000\tpng\n"),
+            "{out}"
+        );
+        assert!(out.ends_with("139,482,003,948\n"), "{out}");
+        let out = render(&["-s", "bytes", "--group-digits=_"]);
+        assert!(out.ends_with("139_482_003_948\n"), "{out}");
+        let out = render(&["--unit", "kb", "--group-digits"]);
+        assert!(out.ends_with("139,482,003.95\n"), "{out}");
+        let out = render(&["-s", "bytes"]);
+        assert!(out.ends_with("139482003948\n"), "{out}");
+
+        assert!(Cli::try_parse_from(["fss", "--group-digits=x"]).is_err());
+    }
+
+    #[test]
+    fn sizes_are_aligned_to_the_widest() {
+        let mut result = scan_result();
+        result.sizes.insert("iso", 12_345_678_901);
+        result.total = 12_345_680_401;
+        let render = |format: &str| {
+            let cli = Cli::parse_from(["fss", "-s", format]);
+            let mut out = Vec::new();
+            print_result(&mut out, &result, &cli).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        for (format, expected) in [
+            (
+                "decimal",
+                "   500 B\ttxt\n    1 kB\tpng\n12.35 GB\tiso\n\nTotal: \n12.35 GB\n",
+            ),
+            (
+                "binary",
+                "    500 B\ttxt\n   1000 B\tpng\n11.50 GiB\tiso\n\nTotal: \n11.50 GiB\n",
+            ),
+            (
+                "bytes",
+                "        500\ttxt\n       1000\tpng\n12345678901\tiso\n\nTotal: \n12345680401\n",
+            ),
+            (
+                "blocks",
+                "       1\ttxt\n       1\tpng\n12056328\tiso\n\nTotal: \n12056329\n",
+            ),
+        ] {
+            assert_eq!(render(format), expected, "{format}");
+        }
+    }
+
+    #[test]
+    fn exact_bytes_follow_human_sizes() {
+        assert_eq!(
+            render(&["--show-bytes"]),
+            "   500 B (500)\ttxt\n   1 kB (1000)\tpng\n\nTotal: \n1.50 kB (1500)\n"
+        );
+        let out = render(&["--show-bytes", "--group-digits", "-s", "binary"]);
+        assert!(out.ends_with("\n1.46 KiB (1,500)\n"), "{out}");
+        assert_eq!(
+            render(&["--show-bytes", "-s", "bytes"]),
+            render(&["-s", "bytes"])
+        );
+        assert_eq!(render(&["--show-bytes", "-c"]), "1.50 kB (3 files)\n");
+        assert_eq!(render(&["--both-units"]), render(&["--show-bytes"]));
+    }
+
+    #[test]
+    fn columns_can_be_separated_by_any_string() {
+        assert_eq!(
+            render(&["--separator", ",", "-s", "bytes"]),
+            " 500,txt\n1000,png\n\nTotal: \n1500\n"
+        );
+        let out = render(&["--separator", " | ", "--dedupe-extents"]);
+        assert!(
+            out.starts_with("  500 B | 0 B | txt\n   1 kB | 0 B | png\n"),
+            "{out}"
+        );
+        assert!(out.ends_with("\n1.50 kB | 0 B\n"), "{out}");
+    }
+
+    #[test]
+    fn files_are_listed_with_their_group() {
+        let mut result = scan_result();
+        for (size, path, group) in [(5, "notes", ""), (1_000, "img/a.png", "png")] {
+            result.listed.push(ListedFile {
+                size,
+                path: path.into(),
+                group: group.to_string(),
+            });
+        }
+        let cli = Cli::parse_from(["fss", "--list-files", "-s", "bytes"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "   5\t\tnotes\n1000\tpng\timg/a.png\n\nTotal: \n1500\n"
+        );
+    }
+
+    #[test]
+    fn labels_can_change_case() {
+        let mut result = scan_result();
+        result.sizes.insert("Other", 20);
+        result.listed.push(ListedFile {
+            size: 5,
+            path: "a.Png".into(),
+            group: "Png".to_string(),
+        });
+        let render = |args: &[&str]| {
+            let args = ["fss", "-s", "bytes"].iter().chain(args);
+            let mut out = Vec::new();
+            print_result(&mut out, &result, &Cli::parse_from(args)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = render(&[]);
+        assert!(
+            out.starts_with("  20\tOther\n 500\ttxt\n1000\tpng\n"),
+            "{out}"
+        );
+        let out = render(&["--label-case", "as-is"]);
+        assert!(
+            out.starts_with("  20\tOther\n 500\ttxt\n1000\tpng\n"),
+            "{out}"
+        );
+        let out = render(&["--label-case", "upper"]);
+        assert!(
+            out.starts_with("  20\tOTHER\n 500\tTXT\n1000\tPNG\n"),
+            "{out}"
+        );
+        let out = render(&["--label-case", "lower"]);
+        assert!(
+            out.starts_with("  20\tother\n 500\ttxt\n1000\tpng\n"),
+            "{out}"
+        );
+        // The group of listed files, but not their path
+        let out = render(&["--label-case", "lower", "--list-files"]);
+        assert!(out.starts_with("   5\tpng\ta.Png\n"), "{out}");
+        let out = render(&["--label-case", "upper", "--chart=1"]);
+        assert!(out.starts_with("PNG │"), "{out}");
+    }
+
+    #[test]
+    fn inputs_follow_the_total() {
+        let mut result = scan_result();
+        result.root_sizes = vec![("photos".into(), 1_000), ("notes".into(), 500)];
+        let cli = Cli::parse_from(["fss", "--per-root", "-s", "bytes"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " 500\ttxt\n1000\tpng\n\nTotal: \n1500\n\nPer input:\n1000\tphotos\n 500\tnotes\n"
+        );
+    }
+
+    #[test]
+    fn paths_can_be_relative_to_a_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().parent().unwrap().join("elsewhere/b.txt");
+        let mut result = scan_result();
+        for (size, path) in [(5, dir.path().join("img/a.png")), (1_000, outside.clone())] {
+            result.listed.push(ListedFile {
+                size,
+                path,
+                group: String::new(),
+            });
+        }
+        let base = dir.path().display().to_string();
+        let cli = Cli::parse_from([
+            "fss",
+            "--largest",
+            "2",
+            "-s",
+            "bytes",
+            "--relative-to",
+            &base,
+        ]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let expected = format!(
+            "Largest files:\n1000\t{}\n   5\t{}\n",
+            outside.display(),
+            Path::new("img").join("a.png").display()
+        );
+        assert!(out.ends_with(&expected), "{out}");
+
+        // Relative paths are made absolute, whether inside the base or not
+        let cwd = std::env::current_dir().unwrap();
+        let base = base_spellings(Path::new("src"));
+        assert_eq!(
+            display_path(Path::new("src/cli.rs"), &base),
+            Path::new("cli.rs")
+        );
+        assert_eq!(display_path(Path::new("src"), &base), Path::new("."));
+        assert_eq!(
+            display_path(Path::new("tests/cli.rs"), &base),
+            cwd.join("tests/cli.rs")
+        );
+        assert_eq!(
+            display_path(Path::new("src/cli.rs"), &[]),
+            Path::new("src/cli.rs")
+        );
+    }
+
+    #[test]
+    fn files_are_explained_with_their_types() {
+        let mut result = scan_result();
+        for (size, path, group) in [(5, "Makefile", "Other"), (1_000, "img/a.PNG", "Image")] {
+            result.listed.push(ListedFile {
+                size,
+                path: path.into(),
+                group: group.to_string(),
+            });
+        }
+        let cli = Cli::parse_from(["fss", "--explain", "-g", "t", "-s", "bytes"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        let png = FileType::get_filetype(&"png");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "   5\tOther\t(none)\tOther\tOther\tMakefile\n\
+                 1000\tImage\tpng\t{png}\t{png}\timg/a.PNG\n\nTotal: \n1500\n"
+            )
+        );
+    }
+
+    #[test]
+    fn the_largest_files_follow_the_total() {
+        let mut result = scan_result();
+        for (size, path) in [(5, "notes"), (1_000, "img/a.png")] {
+            result.listed.push(ListedFile {
+                size,
+                path: path.into(),
+                group: String::new(),
+            });
+        }
+        let cli = Cli::parse_from(["fss", "--largest", "2", "-s", "bytes"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with("\n1500\n\nLargest files:\n1000\timg/a.png\n   5\tnotes\n"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn duplicates_replace_the_groups() {
+        let mut result = scan_result();
+        let mut wasted_by_group = GroupMap::default();
+        wasted_by_group.insert("txt", 500);
+        wasted_by_group.insert("png", 2_000);
+        result.duplicates = Some(Duplicates {
+            sets: vec![
+                DuplicateSet {
+                    size: 500,
+                    paths: vec!["a.txt".into(), "b.txt".into()],
+                },
+                DuplicateSet {
+                    size: 1_000,
+                    paths: vec!["a.png".into(), "b.png".into(), "c.png".into()],
+                },
+            ],
+            wasted: 2_500,
+            wasted_by_group,
+            unreadable: 1,
+        });
+        let cli = Cli::parse_from(["fss", "--duplicates", "-s", "bytes"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " 500\t2 copies of 500\n    \ta.txt\n    \tb.txt\n\
+             2000\t3 copies of 1000\n    \ta.png\n    \tb.png\n    \tc.png\n\
+             \nWasted by group:\n 500\ttxt\n2000\tpng\n\
+             \nTotal wasted: \n2500\n\
+             (1 files of the same length as another could not be read to compare them)\n"
+        );
+    }
+
+    #[test]
+    fn blocks_are_rounded_up() {
+        let out = render(&["-s", "blocks"]);
+        assert!(out.starts_with("1\ttxt\n1\tpng\n"), "{out}");
+        assert!(out.ends_with("\n2\n"), "{out}");
+
+        let out = render(&["-s", "blocks", "--output-block-size", "100b"]);
+        assert!(out.starts_with(" 5\ttxt\n10\tpng\n"), "{out}");
+        assert!(out.ends_with("\n15\n"), "{out}");
+    }
+
+    #[test]
+    fn sizes_are_rounded_for_display() {
+        // 500 is halfway and rounded up, 1000 is exact, and the total of 1500 is halfway and
+        // rounded up too
+        let out = render(&["-s", "bytes", "--round-to", "1000b"]);
+        assert!(out.starts_with("1000\ttxt\n1000\tpng\n"), "{out}");
+        assert!(out.ends_with("\n2000\n"), "{out}");
+
+        let out = render(&["-s", "bytes", "--round-to", "1001b"]);
+        assert!(out.starts_with("   0\ttxt\n1001\tpng\n"), "{out}");
+        assert!(out.ends_with("\n1001\n"), "{out}");
+
+        let out = render(&["-s", "bytes", "--round-to", "999b"]);
+        assert!(out.starts_with(" 999\ttxt\n 999\tpng\n"), "{out}");
+        assert!(out.ends_with("\n1998\n"), "{out}");
+
+        // The exact sizes are still the ones filtered and added in parentheses
+        let out = render(&["--round-to", "1k", "--size", "-600b", "--show-bytes"]);
+        assert!(out.contains("1 kB (500)\ttxt\n"), "{out}");
+        assert!(!out.contains("png"), "{out}");
+
+        let format = SizeFormat {
+            round_to: Some(10),
+            ..SizeFormat::new(&Cli::parse_from(["fss", "-s", "bytes"]))
+        };
+        assert_eq!(format.format(u64::MAX), u64::MAX.to_string());
+        assert_eq!(format.format(4), "0");
+        assert!(Cli::try_parse_from(["fss", "--round-to", "0"]).is_err());
+    }
+
+    #[test]
+    fn precision_applies_to_the_groups_and_the_total() {
+        let out = render(&["--precision", "3"]);
+        assert!(out.starts_with("   500 B\ttxt\n    1 kB\tpng\n"), "{out}");
+        assert!(out.ends_with("\n1.500 kB\n"), "{out}");
+
+        let out = render(&["--precision", "0", "-s", "binary"]);
+        assert!(out.contains("1000 B\tpng\n"), "{out}");
+        assert!(out.contains("1 KiB"), "{out}");
+
+        assert!(Cli::try_parse_from(["fss", "--precision", "7"]).is_err());
+    }
+
+    #[test]
+    fn both_sizes_are_compared_in_the_stats() {
+        let cli = Cli::parse_from(["fss", "--stats"]);
+        let mut result = scan_result();
+        result.apparent_total = 10_000;
+        result.allocated_total = 4_096;
+        result.sparse_files = 1;
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with(
+            "     10000\tbytes apparent size\n      4096\tbytes allocated\n         1\tsparse or compressed files\n"
+        ));
+    }
+
+    #[test]
+    fn samples_are_shown_as_estimates() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes", "--sample", "0.1"]);
+        let mut result = scan_result();
+        result.sampler = Some(Sampler {
+            rate: 0.1,
+            seed: 42,
+        });
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Estimated total: \n1500\n"), "{out}");
+        assert!(
+            out.contains("(estimated from 3 files read with --sample 0.1 --seed 42)"),
+            "{out:?}"
+        );
+    }
+
+    #[test]
+    fn deduplicated_hardlinks_are_noted() {
+        let cli = Cli::parse_from(["fss"]);
+        let mut result = scan_result();
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("deduplicated"));
+
+        result.deduplicated = 4;
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("4 hardlinked duplicates were deduplicated")
+        );
+    }
+
+    #[test]
+    fn streams_are_noted() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes"]);
+        let mut result = scan_result();
+        result.stream_total = 300;
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains(&format!("(including 300 of {STREAMS})"))
+        );
+
+        let cli = Cli::parse_from(["fss", "--stats"]);
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .ends_with(&format!("       300\tbytes of {STREAMS}\n"))
+        );
+    }
+
+    #[test]
+    fn errors_are_summarized_by_kind() {
+        use crate::aggregate::Aggregator;
+        use crate::walk::Error;
+        use std::path::PathBuf;
+
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        for i in 0..1200 {
+            let path = PathBuf::from(format!("/var/lib/{i}"));
+            aggregator.add_error(Error::CouldNotReadDir(path, denied()));
+        }
+        aggregator.add_error(Error::NoMetadataForPath(
+            PathBuf::from("/dev/weird"),
+            io::Error::other("boom"),
+        ));
+        let result = aggregator.finish();
+
+        let render = |verbose| {
+            let mut err = Vec::new();
+            print_errors(&mut err, &result, verbose).unwrap();
+            String::from_utf8(err).unwrap()
+        };
+        assert_eq!(
+            render(0),
+            "[fss warning] the results may be tainted by 1,201 errors. \
+             Re-run with -v/--verbose to see them.\n"
+        );
+        assert_eq!(
+            render(1),
+            "fss: permission denied: 1,200 paths (first: /var/lib/0)\n\
+             fss: could not retrieve metadata for path '/dev/weird': boom\n"
+        );
+        assert_eq!(render(2).lines().count(), 1201);
+    }
+
+    #[test]
+    fn errors_can_be_printed_as_json() {
+        use crate::aggregate::Aggregator;
+        use crate::walk::Error;
+        use std::path::PathBuf;
+
+        let mut aggregator = Aggregator::new(GroupBy::Extension);
+        aggregator.add_error(Error::CouldNotReadDir(
+            PathBuf::from("/var/\"quoted\"\tdir"),
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        ));
+        aggregator.add_error(Error::ChangedDuringScan(PathBuf::from("/tmp/moved")));
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let path = std::ffi::OsStr::from_bytes(b"/data/caf\xe9");
+            aggregator.add_error(Error::NoMetadataForPath(
+                path.into(),
+                io::Error::other("boom"),
+            ));
+        }
+        let result = aggregator.finish();
+
+        let mut err = Vec::new();
+        print_errors_json(&mut err, &result).unwrap();
+        let err = String::from_utf8(err).unwrap();
+        let mut lines = err.lines();
+        assert!(
+            lines.next().unwrap().starts_with(
+                r#"{"kind":"permission_denied","path":"/var/\"quoted\"\tdir","message":"could not read"#
+            ),
+            "{err}"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            r#"{"kind":"changed","path":"/tmp/moved","message":"directory '/tmp/moved' changed while it was being read"}"#
+        );
+        #[cfg(unix)]
+        assert!(
+            lines.next().unwrap().starts_with(
+                r#"{"kind":"other","path":"/data/caf�","path_bytes":"L2RhdGEvY2Fm6Q==","message":"#
+            ),
+            "{err}"
+        );
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert_eq!(escape_control("plain name"), "plain name");
+        assert_eq!(escape_control("two\nlines"), "two\\nlines");
+        assert_eq!(escape_control("\x1b[31mred"), "\\u{1b}[31mred");
+    }
+
+    #[test]
+    fn compact_prints_a_single_line() {
+        assert_eq!(render(&["-c", "-s", "bytes"]), "1500 (3 files)\n");
+        assert_eq!(render(&["--compact"]), "1.50 kB (3 files)\n");
+    }
+
+    #[test]
+    fn age_buckets_are_printed_newest_first() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes", "-g", "age"]);
+        let mut result = ScanResult::default();
+        for (bucket, size) in [("Older", 900), ("Last 7 days", 5), ("Last year", 40)] {
+            result.sizes.insert(bucket, size);
+        }
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .starts_with("  5\tLast 7 days\n 40\tLast year\n900\tOlder\n")
+        );
+    }
+
+    #[test]
+    fn the_highest_threshold_reached_wins() {
+        let cli = Cli::parse_from(["fss", "--highlight", "1k", "--warn", "100b"]);
+        assert_eq!(Alert::new(99, &cli), None);
+        assert_eq!(Alert::new(100, &cli), Some(Alert::Warn));
+        assert_eq!(Alert::new(1_000, &cli), Some(Alert::Highlight));
+
+        let cli = Cli::parse_from(["fss", "--warn", "100b"]);
+        assert_eq!(Alert::new(5_000, &cli), Some(Alert::Warn));
+    }
+
+    #[test]
+    fn chart_snapshot() {
+        let bar = |label, size| Bar {
+            label: Cow::Borrowed(label),
+            size,
+            paint: Paint::default(),
+        };
+        let bars = [
+            bar("a_name_too_long_for_the_chart", 3),
+            bar("txt", 500),
+            bar("png", 1_000),
+            bar("照片", 2_000),
+        ];
+        let size_format = SizeFormat::new(&Cli::parse_from(["fss"]));
+        let mut out = Vec::new();
+        print_chart(&mut out, &bars, 4_000, &size_format, 50).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+a_name_too_long… │                    0.1% (3 B)
+txt              │ ██                12.5% (500 B)
+png              │ ████              25.0% (1 kB)
+照片             │ ████████          50.0% (2 kB)
+"
+        );
+
+        // Only the largest groups
+        assert!(render(&["--chart=1", "-s", "bytes"]).starts_with("png │ "));
+    }
+
+    #[test]
+    fn table_snapshot() {
+        let rows = [
+            (
+                vec!["500 B".to_string()],
+                Cow::Borrowed("txt"),
+                Paint::default(),
+            ),
+            (
+                vec!["1.00 kB".to_string()],
+                Cow::Borrowed("png"),
+                Paint::default(),
+            ),
+            (
+                vec!["2.00 kB".to_string()],
+                Cow::Borrowed("照片"),
+                Paint::default(),
+            ),
+        ];
+        let mut out = Vec::new();
+        let total = ["3.50 kB".to_string()];
+        print_table(&mut out, &["Size"], &rows, ("Total", &total), 80).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+┌─────────┬───────┐
+│    Size │ Group │
+├─────────┼───────┤
+│   500 B │ txt   │
+│ 1.00 kB │ png   │
+│ 2.00 kB │ 照片  │
+├─────────┼───────┤
+│ 3.50 kB │ Total │
+└─────────┴───────┘
+"
+        );
+    }
+
+    #[test]
+    fn table_fits_the_terminal() {
+        let long = "a_very_long_directory_name_that_does_not_fit";
+        let rows = [(
+            vec!["1.00 kB".to_string()],
+            Cow::Borrowed(long),
+            Paint::default(),
+        )];
+        let mut out = Vec::new();
+        let total = ["1.00 kB".to_string()];
+        print_table(&mut out, &["Size"], &rows, ("Total", &total), 30).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().all(|line| line.width() == 30), "{out}");
+        assert!(out.contains("│ a_very_long_dir… │"), "{out}");
+
+        assert_eq!(truncate_to_width("照片照片", 5), "照片…");
+    }
+
+    #[test]
+    fn table_falls_back_to_plain_when_piped() {
+        assert_eq!(render(&["-o", "table"]), render(&[]));
+    }
+
+    #[test]
+    fn filtered_total_sums_the_shown_groups() {
+        let grand = render(&["-s", "bytes", "-S", "-600b"]);
+        assert_eq!(grand, " 500\ttxt\n\nTotal: \n1500\n");
+
+        let filtered = render(&["-s", "bytes", "-S", "-600b", "--filtered-total"]);
+        assert_eq!(filtered, "500\ttxt\n\nShown total: \n500\n");
+
+        // Without filters both totals agree
+        assert!(render(&["-s", "bytes", "--filtered-total"]).ends_with("Shown total: \n1500\n"));
+    }
+
+    #[test]
+    fn both_sizes_are_shown_side_by_side() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes", "--both-sizes"]);
+        let mut result = scan_result();
+        let usage = |apparent, allocated| Usage {
+            apparent,
+            allocated,
+        };
+        result.usage_sizes.insert("png", usage(4_000, 1_000));
+        result.usage_sizes.insert("txt", usage(0, 500));
+        result.apparent_total = 4_000;
+        result.allocated_total = 1_500;
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "   0\t 500\t  -\ttxt\n4000\t1000\t25%\tpng\n\nTotal: \n4000\t1500\t37%\n"
+        );
+    }
+
+    #[test]
+    fn compact_has_room_for_one_size() {
+        assert!(Cli::try_parse_from(["fss", "--both-sizes", "--compact"]).is_err());
+    }
+
+    #[test]
+    fn shared_extents_get_their_own_column() {
+        let cli = Cli::parse_from(["fss", "-s", "bytes", "--dedupe-extents"]);
+        let mut result = scan_result();
+        result.shared_sizes.insert("png", 600);
+        result.shared_total = 600;
+        let mut out = Vec::new();
+        print_result(&mut out, &result, &cli).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "500\t  0\ttxt\n400\t600\tpng\n\nTotal: \n900\t600\n"
+        );
+
+        let rows = [(
+            vec!["400".to_string(), "600".to_string()],
+            Cow::Borrowed("png"),
+            Paint::default(),
+        )];
+        let mut out = Vec::new();
+        let total = ["400".to_string(), "600".to_string()];
+        print_table(
+            &mut out,
+            &["Exclusive", "Shared"],
+            &rows,
+            ("Total", &total),
+            80,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+┌───────────┬────────┬───────┐
+│ Exclusive │ Shared │ Group │
+├───────────┼────────┼───────┤
+│       400 │    600 │ png   │
+├───────────┼────────┼───────┤
+│       400 │    600 │ Total │
+└───────────┴────────┴───────┘
+"
+        );
+
+        let mut err = Vec::new();
+        result.unknown_extents = 2;
+        print_extent_fallback(&mut err, &result).unwrap();
+        assert!(
+            String::from_utf8(err)
+                .unwrap()
+                .contains("extents of 2 files")
+        );
+    }
+}
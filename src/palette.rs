@@ -0,0 +1,131 @@
+//! Colors of the group names by file type, like `ls` and `eza` color file names.
+//!
+//! The palette can be changed with the `FSS_COLORS` environment variable, a list of
+//! `<TYPE>=<COLOR>` separated by ':', e.g. `FSS_COLORS='image=bright green:code=yellow'`. The
+//! types are the ones of `--group-by type`, the colors are the 16 terminal colors (`red`,
+//! `bright blue`...), or `none` to leave a type uncolored.
+use colored::Color;
+use fnv::FnvHashMap;
+
+use crate::cli::GroupBy;
+use crate::groups::{FileType, FineType};
+
+pub const ENV_VAR: &str = "FSS_COLORS";
+
+pub struct Palette {
+    colors: FnvHashMap<FileType, Color>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let colors = [
+            (FileType::Image, Color::Green),
+            (FileType::Video, Color::Magenta),
+            (FileType::Audio, Color::Cyan),
+            (FileType::Archive, Color::Yellow),
+            (FileType::Document, Color::Blue),
+            (FileType::Executable, Color::Red),
+            (FileType::Code, Color::BrightCyan),
+            (FileType::GenomicData, Color::BrightMagenta),
+        ];
+        Palette {
+            colors: colors.into_iter().collect(),
+        }
+    }
+}
+
+impl Palette {
+    /// The default palette, changed by `FSS_COLORS` if it's set
+    pub fn from_env() -> Self {
+        let mut palette = Palette::default();
+        if let Ok(spec) = std::env::var(ENV_VAR) {
+            palette.apply(&spec);
+        }
+        palette
+    }
+
+    /// Apply the `<TYPE>=<COLOR>` entries of `spec`, skipping the ones that make no sense: a
+    /// typo in the environment is no reason to fail a scan
+    fn apply(&mut self, spec: &str) {
+        for entry in spec.split(':') {
+            let Some((name, color)) = entry.split_once('=') else {
+                continue;
+            };
+            let Some(file_type) = FileType::ALL
+                .into_iter()
+                .find(|ft| ft.to_string().eq_ignore_ascii_case(name.trim()))
+            else {
+                continue;
+            };
+            match color.trim() {
+                "none" => {
+                    self.colors.remove(&file_type);
+                }
+                color => {
+                    if let Ok(color) = color.parse() {
+                        self.colors.insert(file_type, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The color of `group`, when the groups are made by type or extension
+    pub fn color(&self, group_by: GroupBy, group: &str) -> Option<Color> {
+        let file_type = match group_by {
+            GroupBy::Type => FileType::from_name(group)?,
+            GroupBy::FineType => FineType::from_name(group)?.file_type(),
+            GroupBy::Extension => FileType::get_filetype(&group),
+            GroupBy::FileName
+            | GroupBy::Directory
+            | GroupBy::AgeBucket
+            | GroupBy::Path
+            | GroupBy::CreatedYear
+            | GroupBy::CreatedMonth
+            | GroupBy::Access => {
+                return None;
+            }
+        };
+        self.colors.get(&file_type).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_are_colored_by_type() {
+        let palette = Palette::default();
+        assert_eq!(palette.color(GroupBy::Type, "Image"), Some(Color::Green));
+        assert_eq!(
+            palette.color(GroupBy::Extension, "mkv"),
+            Some(Color::Magenta)
+        );
+        assert_eq!(
+            palette.color(GroupBy::FineType, "Script"),
+            Some(Color::BrightCyan)
+        );
+        assert_eq!(palette.color(GroupBy::Type, "Other"), None);
+        assert_eq!(palette.color(GroupBy::Extension, "(other groups)"), None);
+        assert_eq!(palette.color(GroupBy::FileName, "photo.jpg"), None);
+    }
+
+    #[test]
+    fn the_palette_can_be_changed() {
+        let mut palette = Palette::default();
+        palette.apply(
+            "image=bright green: Code = Bright Yellow:video=none:bogus=red:audio=plaid:junk",
+        );
+        assert_eq!(
+            palette.color(GroupBy::Type, "Image"),
+            Some(Color::BrightGreen)
+        );
+        assert_eq!(
+            palette.color(GroupBy::Type, "Code"),
+            Some(Color::BrightYellow)
+        );
+        assert_eq!(palette.color(GroupBy::Type, "Video"), None);
+        assert_eq!(palette.color(GroupBy::Type, "Audio"), Some(Color::Cyan));
+    }
+}
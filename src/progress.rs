@@ -0,0 +1,158 @@
+//! A status line on stderr telling how far a scan got. By default it can only count the
+//! entries scanned so far. With `--accurate-progress` a first pass counts the entries of the
+//! inputs, which gives a percentage and an estimated time left, at the cost of reading every
+//! directory twice.
+use std::io::Write;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{self, RecvTimeoutError};
+use num_format::{Locale, ToFormattedString};
+
+/// How often the status line is redrawn
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+/// Number of characters of the bar
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Entries found by the counting pass
+    counted: AtomicU64,
+    /// Entries scanned
+    done: AtomicU64,
+    /// Result of the counting pass, and when the scan started
+    total: OnceLock<(u64, Instant)>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_counted(&self, n: u64) {
+        self.counted.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// End the counting pass, the percentage is computed from now on
+    pub fn set_total(&self, total: u64) {
+        let _ = self.total.set((total, Instant::now()));
+    }
+
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The status line as of `now`
+    fn line(&self, now: Instant) -> String {
+        let done = self.done.load(Ordering::Relaxed);
+        let Some(&(total, start)) = self.total.get() else {
+            let counted = self.counted.load(Ordering::Relaxed);
+            return if counted > 0 {
+                format!(
+                    "Counting entries: {}",
+                    counted.to_formatted_string(&Locale::en)
+                )
+            } else {
+                format!("Scanned {} entries", done.to_formatted_string(&Locale::en))
+            };
+        };
+
+        // The count is only an estimate (links, files created since...), never claim to be done
+        let fraction = if total == 0 {
+            0.0
+        } else {
+            (done as f64 / total as f64).min(0.99)
+        };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let mut line = format!(
+            "[{}{}] {:>2}% ({} / {} entries)",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u64,
+            done.to_formatted_string(&Locale::en),
+            total.to_formatted_string(&Locale::en),
+        );
+        if fraction > 0.0 {
+            let elapsed = now.saturating_duration_since(start).as_secs_f64();
+            let left = (elapsed * (1.0 - fraction) / fraction) as u64;
+            line.push_str(&format!(" ETA {}:{:02}", left / 60, left % 60));
+        }
+        line
+    }
+
+    /// Run `f` while redrawing the status line on stderr, and erase it once `f` returns
+    pub fn show_while<T>(&self, f: impl FnOnce() -> T) -> T {
+        let (stop_tx, stop_rx) = channel::bounded::<()>(0);
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut stderr = std::io::stderr();
+                while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(REFRESH_INTERVAL) {
+                    let _ = write!(stderr, "\r\x1b[K{}", self.line(Instant::now()));
+                    let _ = stderr.flush();
+                }
+                let _ = write!(stderr, "\r\x1b[K");
+            });
+            let result = f();
+            drop(stop_tx);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_counted_without_a_total() {
+        let progress = Progress::new();
+        for _ in 0..1234 {
+            progress.inc();
+        }
+        assert_eq!(progress.line(Instant::now()), "Scanned 1,234 entries");
+    }
+
+    #[test]
+    fn counting_pass_is_shown() {
+        let progress = Progress::new();
+        progress.add_counted(5000);
+        assert_eq!(progress.line(Instant::now()), "Counting entries: 5,000");
+    }
+
+    #[test]
+    fn percentage_and_eta_follow_the_total() {
+        let progress = Progress::new();
+        progress.set_total(400);
+        let start = progress.total.get().unwrap().1;
+        assert_eq!(
+            progress.line(start),
+            format!("[{}]  0% (0 / 400 entries)", " ".repeat(BAR_WIDTH))
+        );
+
+        for _ in 0..100 {
+            progress.inc();
+        }
+        // A quarter in 10 seconds, 30 seconds left
+        assert_eq!(
+            progress.line(start + Duration::from_secs(10)),
+            format!(
+                "[{}{}] 25% (100 / 400 entries) ETA 0:30",
+                "#".repeat(7),
+                " ".repeat(BAR_WIDTH - 7)
+            )
+        );
+    }
+
+    #[test]
+    fn never_reaches_a_hundred_percent() {
+        let progress = Progress::new();
+        progress.set_total(10);
+        for _ in 0..20 {
+            progress.inc();
+        }
+        let line = progress.line(Instant::now());
+        assert!(line.contains("99% (20 / 10 entries)"), "{line}");
+    }
+}
@@ -0,0 +1,146 @@
+//! Windows marks special entries as reparse points and tells them apart by a reparse tag.
+//! Symbolic links and junctions point somewhere else and must not be descended into unless
+//! links are followed, while cloud placeholders (OneDrive, Dropbox...) are regular files and
+//! directories whose data may only exist on a server. The classification is plain bit
+//! twiddling so it is tested everywhere, only reading the tag is Windows specific.
+use std::fs;
+use std::path::Path;
+
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x0000_0400;
+const FILE_ATTRIBUTE_OFFLINE: u32 = 0x0000_1000;
+/// Set on placeholder directories whose contents have not been fetched yet
+const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x0004_0000;
+const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x0040_0000;
+
+/// Set in the tag of reparse points that stand for another entry, like symlinks and junctions
+const NAME_SURROGATE_BIT: u32 = 0x2000_0000;
+/// `IO_REPARSE_TAG_CLOUD`, the sync providers use variants with the bits of
+/// `IO_REPARSE_TAG_CLOUD_MASK` set
+const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001A;
+const IO_REPARSE_TAG_CLOUD_MASK: u32 = 0x0000_F000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reparse {
+    /// Not a reparse point
+    None,
+    /// A symbolic link or a junction
+    Link,
+    /// A file or directory managed by a cloud sync provider. A dehydrated placeholder keeps its
+    /// logical size but has no data on disk.
+    CloudPlaceholder { dehydrated: bool },
+    /// Any other reparse point (deduplicated files, WIM images...), read like a regular entry
+    Other,
+}
+
+/// Classify an entry from its file attributes and reparse tag
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn classify(attributes: u32, tag: u32) -> Reparse {
+    if attributes & FILE_ATTRIBUTE_REPARSE_POINT == 0 {
+        Reparse::None
+    } else if tag & NAME_SURROGATE_BIT != 0 {
+        Reparse::Link
+    } else if tag & !IO_REPARSE_TAG_CLOUD_MASK == IO_REPARSE_TAG_CLOUD {
+        Reparse::CloudPlaceholder {
+            dehydrated: attributes
+                & (FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS
+                    | FILE_ATTRIBUTE_RECALL_ON_OPEN
+                    | FILE_ATTRIBUTE_OFFLINE)
+                != 0,
+        }
+    } else {
+        Reparse::Other
+    }
+}
+
+/// Classify the entry at `path`, whose metadata was read without following links
+#[cfg(windows)]
+pub fn reparse_kind(path: &Path, metadata: &fs::Metadata) -> Reparse {
+    use std::os::windows::fs::MetadataExt;
+
+    let attributes = metadata.file_attributes();
+    if attributes & FILE_ATTRIBUTE_REPARSE_POINT == 0 {
+        return Reparse::None;
+    }
+    classify(attributes, reparse_tag(path).unwrap_or(0))
+}
+
+#[cfg(not(windows))]
+pub fn reparse_kind(_path: &Path, _metadata: &fs::Metadata) -> Reparse {
+    Reparse::None
+}
+
+/// The reparse tag of `path`, which FindFirstFileW returns without opening the entry (opening
+/// a placeholder could start downloading it)
+#[cfg(windows)]
+fn reparse_tag(path: &Path) -> Option<u32> {
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::{FindClose, FindFirstFileW, WIN32_FIND_DATAW};
+
+    let wide = crate::long_path::to_wide(path);
+    let mut data = WIN32_FIND_DATAW::default();
+    // SAFETY: `wide` is NUL terminated and `data` outlives the call
+    let handle = unsafe { FindFirstFileW(wide.as_ptr(), &mut data) };
+    if handle == INVALID_HANDLE_VALUE {
+        return None;
+    }
+    // SAFETY: `handle` is a valid search handle, closed only once
+    unsafe { FindClose(handle) };
+    Some(data.dwReserved0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+    const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+    const IO_REPARSE_TAG_DEDUP: u32 = 0x8000_0013;
+    const ONEDRIVE_TAG: u32 = 0x9000_601A;
+
+    #[test]
+    fn regular_entries_are_not_reparse_points() {
+        // The tag is meaningless without the attribute
+        assert_eq!(classify(0x20, IO_REPARSE_TAG_SYMLINK), Reparse::None);
+        assert_eq!(classify(0x10, 0), Reparse::None);
+    }
+
+    #[test]
+    fn symlinks_and_junctions_are_links() {
+        let attrs = FILE_ATTRIBUTE_REPARSE_POINT | 0x10;
+        assert_eq!(classify(attrs, IO_REPARSE_TAG_MOUNT_POINT), Reparse::Link);
+        assert_eq!(classify(attrs, IO_REPARSE_TAG_SYMLINK), Reparse::Link);
+    }
+
+    #[test]
+    fn cloud_placeholders_are_recognized() {
+        let attrs = FILE_ATTRIBUTE_REPARSE_POINT;
+        for tag in [IO_REPARSE_TAG_CLOUD, ONEDRIVE_TAG] {
+            assert_eq!(
+                classify(attrs, tag),
+                Reparse::CloudPlaceholder { dehydrated: false }
+            );
+            assert_eq!(
+                classify(attrs | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS, tag),
+                Reparse::CloudPlaceholder { dehydrated: true }
+            );
+            assert_eq!(
+                classify(attrs | 0x10 | FILE_ATTRIBUTE_RECALL_ON_OPEN, tag),
+                Reparse::CloudPlaceholder { dehydrated: true }
+            );
+            assert_eq!(
+                classify(attrs | FILE_ATTRIBUTE_OFFLINE, tag),
+                Reparse::CloudPlaceholder { dehydrated: true }
+            );
+        }
+    }
+
+    #[test]
+    fn other_reparse_points_are_regular_entries() {
+        assert_eq!(
+            classify(FILE_ATTRIBUTE_REPARSE_POINT, IO_REPARSE_TAG_DEDUP),
+            Reparse::Other
+        );
+        // Tag could not be read
+        assert_eq!(classify(FILE_ATTRIBUTE_REPARSE_POINT, 0), Reparse::Other);
+    }
+}
@@ -0,0 +1,75 @@
+//! Picks the files to read when only a fraction of them is sampled. The choice is a hash of the
+//! path and a seed rather than a random number generator shared by the threads, so that a seed
+//! gives the same files whatever order the threads reach them in.
+use std::hash::Hasher;
+use std::path::Path;
+
+use fnv::FnvHasher;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampler {
+    /// Fraction of the files read, in (0, 1]
+    pub rate: f64,
+    pub seed: u64,
+}
+
+/// Mix the bits of `x` so that close inputs give unrelated outputs (the splitmix64 finalizer)
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl Sampler {
+    /// Whether the file at `path` is read
+    pub fn keeps(&self, path: &Path) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        let mut hasher = FnvHasher::with_key(mix(self.seed));
+        hasher.write(path.as_os_str().as_encoded_bytes());
+        // The top 53 bits make a uniform number in [0, 1)
+        let x = (mix(hasher.finish()) >> 11) as f64 / (1u64 << 53) as f64;
+        x < self.rate
+    }
+
+    /// Scale a sum over the sampled files up to an estimate for all the files
+    pub fn scale(&self, n: u64) -> u64 {
+        // Casting saturates at u64::MAX
+        (n as f64 / self.rate).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths() -> impl Iterator<Item = std::path::PathBuf> {
+        (0..10_000).map(|i| Path::new("dir").join(format!("file{i}.txt")))
+    }
+
+    #[test]
+    fn a_rate_of_one_keeps_everything() {
+        let sampler = Sampler { rate: 1.0, seed: 3 };
+        assert!(paths().all(|path| sampler.keeps(&path)));
+        assert_eq!(sampler.scale(12345), 12345);
+    }
+
+    #[test]
+    fn about_the_rate_is_kept() {
+        let sampler = Sampler { rate: 0.1, seed: 3 };
+        let kept = paths().filter(|path| sampler.keeps(path)).count();
+        assert!((800..1200).contains(&kept), "{kept}");
+        assert_eq!(sampler.scale(100), 1000);
+    }
+
+    #[test]
+    fn the_seed_picks_the_files() {
+        let kept = |seed| -> Vec<_> {
+            let sampler = Sampler { rate: 0.5, seed };
+            paths().filter(|path| sampler.keeps(path)).collect()
+        };
+        assert_eq!(kept(1), kept(1));
+        assert_ne!(kept(1), kept(2));
+    }
+}
@@ -0,0 +1,594 @@
+//! Snapshots of a whole scan, for `--save-snapshot`.
+//!
+//! A snapshot is a text file of one record per line, its fields separated by tabs, the first
+//! field naming the record:
+//!
+//! ```text
+//! fss-snapshot    2
+//! fss             0.1.0
+//! time            2024-02-29T23:59:59Z
+//! group_by        extension
+//! count           bytes
+//! size_type       disk
+//! flat_depth      none
+//! absolute        false
+//! dotfile_as_ext  false
+//! normalize_ext   false
+//! escape_nonutf8  false
+//! block_size      none
+//! du_compat       false
+//! dedup           true
+//! split_outliers  none
+//! max_groups      none
+//! sample          none
+//! root            /home/me
+//! total           1500
+//! files           3
+//! partial         false
+//! error           permission_denied   2   /home/me/private
+//! group           1000    1   png
+//! ```
+//!
+//! The first line gives the version of the format, bumped whenever a record changes meaning.
+//! Records this version doesn't know are skipped, so that adding one needs no new version. The
+//! last field of a record may contain tabs. In the names of the groups and in the paths, line
+//! breaks are written as `\n` and `\r` and backslashes are doubled, which format 1 didn't do.
+//!
+//! The records from `group_by` to `sample` tell how the sizes were counted and the groups
+//! named. A snapshot is only compared with a scan or another snapshot made with the same
+//! settings, as far as both record them: a setting missing from an older snapshot is not
+//! checked. Neither are the filters, like `--exclude` or `--skip-system`, which leave files out
+//! without changing how the others are counted.
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::{Context, anyhow, bail};
+use clap::ValueEnum;
+
+use crate::aggregate::{ScanResult, path_str};
+use crate::cli::{Cli, CountMode, GroupBy};
+use crate::error_log::timestamp;
+use crate::group_map::GroupMap;
+use crate::long_path;
+
+/// The first field of a snapshot, which tells it apart from the groups of `--save-groups`
+pub const MAGIC: &str = "fss-snapshot";
+
+/// The version of the format written
+const FORMAT_VERSION: u32 = 2;
+
+/// The oldest version of the format read, whose names and paths are not escaped
+const OLDEST_FORMAT_VERSION: u32 = 1;
+
+/// How a scan counted its sizes and named its groups
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanSettings {
+    pub group_by: GroupBy,
+    /// What the sizes add up: "bytes", "files" or "inodes"
+    pub count: &'static str,
+    /// Apparent sizes rather than disk usage
+    pub apparent: bool,
+    pub flat_depth: Option<usize>,
+    pub absolute: bool,
+    pub dotfile_as_ext: bool,
+    pub normalize_ext: bool,
+    pub escape_nonutf8: bool,
+    pub block_size: Option<u64>,
+    pub du_compat: bool,
+    pub dedup: bool,
+    pub split_outliers: Option<u64>,
+    pub max_groups: Option<usize>,
+    pub sample: Option<f64>,
+}
+
+impl ScanSettings {
+    /// The settings of a scan grouping the files by the first `--group-by` of `cli`
+    pub fn new(cli: &Cli) -> Self {
+        ScanSettings {
+            group_by: cli.group_by[0],
+            count: if cli.inodes {
+                "inodes"
+            } else if cli.count_mode == CountMode::Files {
+                "files"
+            } else {
+                "bytes"
+            },
+            apparent: cli.apparent_size || cli.du_compat,
+            flat_depth: cli.flat_depth,
+            absolute: cli.absolute,
+            dotfile_as_ext: cli.dotfile_as_ext,
+            normalize_ext: cli.normalize_ext,
+            escape_nonutf8: cli.escape_nonutf8,
+            block_size: cli.block_size,
+            du_compat: cli.du_compat,
+            dedup: !cli.no_dedup,
+            split_outliers: cli.split_outliers,
+            max_groups: cli.max_groups,
+            sample: cli.sample,
+        }
+    }
+
+    /// The records of the settings after `group_by`, with the flags changing them
+    fn records(&self) -> [(&'static str, String, &'static str); 13] {
+        fn or_none<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "none".to_string(), |value| value.to_string())
+        }
+        let size_type = if self.apparent { "apparent" } else { "disk" };
+        [
+            ("count", self.count.to_string(), "--count-mode or --inodes"),
+            ("size_type", size_type.to_string(), "-b/--apparent-size"),
+            ("flat_depth", or_none(self.flat_depth), "--flat-depth"),
+            ("absolute", self.absolute.to_string(), "--absolute"),
+            (
+                "dotfile_as_ext",
+                self.dotfile_as_ext.to_string(),
+                "--dotfile-as-ext",
+            ),
+            (
+                "normalize_ext",
+                self.normalize_ext.to_string(),
+                "--normalize-ext",
+            ),
+            (
+                "escape_nonutf8",
+                self.escape_nonutf8.to_string(),
+                "--escape-nonutf8",
+            ),
+            ("block_size", or_none(self.block_size), "--block-size"),
+            ("du_compat", self.du_compat.to_string(), "--du-compat"),
+            ("dedup", self.dedup.to_string(), "--no-dedup"),
+            (
+                "split_outliers",
+                or_none(self.split_outliers),
+                "--split-outliers",
+            ),
+            ("max_groups", or_none(self.max_groups), "--max-groups"),
+            ("sample", or_none(self.sample), "--sample"),
+        ]
+    }
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        ScanSettings {
+            group_by: GroupBy::default(),
+            count: "bytes",
+            apparent: false,
+            flat_depth: None,
+            absolute: false,
+            dotfile_as_ext: false,
+            normalize_ext: false,
+            escape_nonutf8: false,
+            block_size: None,
+            du_compat: false,
+            dedup: true,
+            split_outliers: None,
+            max_groups: None,
+            sample: None,
+        }
+    }
+}
+
+/// A scan read back from a snapshot
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// The version of fss that saved it
+    pub fss_version: String,
+    /// When it was saved, as RFC 3339
+    pub time: String,
+    pub group_by: GroupBy,
+    /// The other settings of the scan, as their record and their value. Older snapshots may
+    /// lack some of them.
+    pub settings: Vec<(String, String)>,
+    pub roots: Vec<PathBuf>,
+    pub total: u64,
+    pub file_count: u64,
+    pub partial: bool,
+    /// The errors of each kind, as their kind, their count and the path of the first one
+    pub errors: Vec<(String, u64, PathBuf)>,
+    pub sizes: GroupMap<u64>,
+    /// Number of files of each group
+    pub file_counts: GroupMap<u64>,
+}
+
+impl Snapshot {
+    /// Check that the snapshot was made with `settings`, to compare a scan with it
+    pub fn check(&self, settings: &ScanSettings) -> anyhow::Result<()> {
+        if self.group_by != settings.group_by {
+            bail!(
+                "the snapshot groups the files by {}, not by {}, use the same --group-by",
+                group_by_name(self.group_by),
+                group_by_name(settings.group_by)
+            );
+        }
+        for (record, value, flags) in settings.records() {
+            if let Some(saved) = self.setting(record)
+                && saved != value
+            {
+                bail!("the snapshot has {record} {saved}, not {value}, use the same {flags}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the snapshot and `newer` were made with the same settings, to compare them
+    pub fn check_same(&self, newer: &Snapshot) -> anyhow::Result<()> {
+        if self.group_by != newer.group_by {
+            bail!(
+                "the snapshots group the files by {} and by {}",
+                group_by_name(self.group_by),
+                group_by_name(newer.group_by)
+            );
+        }
+        for (record, value) in &self.settings {
+            if let Some(newer_value) = newer.setting(record)
+                && newer_value != value
+            {
+                bail!("the snapshots have {record} {value} and {newer_value}");
+            }
+        }
+        Ok(())
+    }
+
+    fn setting(&self, record: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|(name, _value)| name == record)
+            .map(|(_name, value)| value.as_str())
+    }
+}
+
+/// Write `result`, a scan of `roots` made with `settings` and finished at `time`. The groups
+/// need their files counted.
+pub fn write_snapshot<W: Write>(
+    out: &mut W,
+    result: &ScanResult,
+    roots: &[PathBuf],
+    settings: &ScanSettings,
+    time: SystemTime,
+) -> io::Result<()> {
+    writeln!(out, "{MAGIC}\t{FORMAT_VERSION}")?;
+    writeln!(out, "fss\t{}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "time\t{}", timestamp(time))?;
+    writeln!(out, "group_by\t{}", group_by_name(settings.group_by))?;
+    for (record, value, _flags) in settings.records() {
+        writeln!(out, "{record}\t{value}")?;
+    }
+    for root in roots {
+        let root = long_path::strip_extended(root);
+        writeln!(out, "root\t{}", escape(&path_str(&root)))?;
+    }
+    writeln!(out, "total\t{}", result.total)?;
+    writeln!(out, "files\t{}", result.file_count)?;
+    writeln!(out, "partial\t{}", result.partial)?;
+    for summary in &result.error_summary {
+        let first = long_path::strip_extended(&summary.first);
+        writeln!(
+            out,
+            "error\t{}\t{}\t{}",
+            summary.kind.id(),
+            summary.count,
+            escape(&path_str(&first))
+        )?;
+    }
+    for (group, size) in result.sizes.iter() {
+        let count = result.file_counts.get(group).copied().unwrap_or_default();
+        writeln!(out, "group\t{size}\t{count}\t{}", escape(group))?;
+    }
+    Ok(())
+}
+
+/// Read a snapshot
+pub fn read_snapshot<R: BufRead>(reader: R) -> anyhow::Result<Snapshot> {
+    let mut lines = reader.lines();
+    let first = lines
+        .next()
+        .transpose()
+        .context("could not read the snapshot")?
+        .unwrap_or_default();
+    let version = match first.split_once('\t') {
+        Some((MAGIC, version)) => match version.parse::<u32>() {
+            Ok(version @ OLDEST_FORMAT_VERSION..=FORMAT_VERSION) => version,
+            _ => bail!(
+                "the snapshot is in format {version}, this version of fss reads formats \
+                 {OLDEST_FORMAT_VERSION} to {FORMAT_VERSION}"
+            ),
+        },
+        _ => bail!("not a snapshot saved by --save-snapshot"),
+    };
+    let unescape = |field: &str| {
+        if version >= 2 {
+            unescape(field).into_owned()
+        } else {
+            field.to_string()
+        }
+    };
+    let setting_records = ScanSettings::default().records().map(|(record, ..)| record);
+
+    let mut snapshot = Snapshot::default();
+    let mut group_by_seen = false;
+    for (idx, line) in lines.enumerate() {
+        let line = line.context("could not read the snapshot")?;
+        // The first line was read already
+        let malformed = || anyhow!("line {}: malformed record '{line}'", idx + 2);
+        let number = |field: &str| field.parse::<u64>().map_err(|_| malformed());
+        let (record, fields) = line.split_once('\t').unwrap_or((&line, ""));
+        match record {
+            "fss" => snapshot.fss_version = fields.to_string(),
+            "time" => snapshot.time = fields.to_string(),
+            "group_by" => {
+                snapshot.group_by = GroupBy::from_str(fields, false).map_err(|_| malformed())?;
+                group_by_seen = true;
+            }
+            "root" => snapshot.roots.push(PathBuf::from(unescape(fields))),
+            "total" => snapshot.total = number(fields)?,
+            "files" => snapshot.file_count = number(fields)?,
+            "partial" => snapshot.partial = fields.parse().map_err(|_| malformed())?,
+            "error" => {
+                let mut fields = fields.splitn(3, '\t');
+                let (Some(kind), Some(count), Some(first)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(malformed());
+                };
+                let count = number(count)?;
+                snapshot
+                    .errors
+                    .push((kind.to_string(), count, PathBuf::from(unescape(first))));
+            }
+            "group" => {
+                let mut fields = fields.splitn(3, '\t');
+                let (Some(size), Some(count), Some(group)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(malformed());
+                };
+                let group = unescape(group);
+                snapshot.sizes.insert(&group, number(size)?);
+                snapshot.file_counts.insert(&group, number(count)?);
+            }
+            record if setting_records.contains(&record) => snapshot
+                .settings
+                .push((record.to_string(), fields.to_string())),
+            _ => {}
+        }
+    }
+    if !group_by_seen {
+        bail!("the snapshot doesn't say how its files were grouped");
+    }
+    Ok(snapshot)
+}
+
+/// Double the backslashes of a name or a path and spell its line breaks `\n` and `\r`, for it
+/// to fit on one line
+fn escape(name: &str) -> Cow<'_, str> {
+    if !name.contains(['\\', '\n', '\r']) {
+        return Cow::Borrowed(name);
+    }
+    let mut escaped = String::with_capacity(name.len() + 2);
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Undo [`escape`]. A backslash before any other character is kept as it is.
+fn unescape(field: &str) -> Cow<'_, str> {
+    if !field.contains('\\') {
+        return Cow::Borrowed(field);
+    }
+    let mut name = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            name.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => name.push('\\'),
+            Some('n') => name.push('\n'),
+            Some('r') => name.push('\r'),
+            Some(other) => {
+                name.push('\\');
+                name.push(other);
+            }
+            None => name.push('\\'),
+        }
+    }
+    Cow::Owned(name)
+}
+
+/// The name of `group_by` as given to `--group-by`
+fn group_by_name(group_by: GroupBy) -> String {
+    group_by
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::ErrorSummary;
+    use crate::walk::ErrorKind;
+
+    fn scan_result() -> ScanResult {
+        let mut result = ScanResult {
+            group_by: GroupBy::Type,
+            total: 1_507,
+            file_count: 4,
+            partial: true,
+            ..Default::default()
+        };
+        result.sizes.insert("Image", 1_000);
+        result.sizes.insert("Text\tnotes", 500);
+        result.sizes.insert("two\nlines\\", 7);
+        result.file_counts.insert("Image", 1);
+        result.file_counts.insert("Text\tnotes", 2);
+        result.file_counts.insert("two\nlines\\", 1);
+        result.error_summary.push(ErrorSummary {
+            kind: ErrorKind::PermissionDenied,
+            count: 2,
+            first: PathBuf::from("/home/me/private\r"),
+        });
+        result
+    }
+
+    fn settings() -> ScanSettings {
+        ScanSettings {
+            group_by: GroupBy::Type,
+            apparent: true,
+            flat_depth: Some(2),
+            ..Default::default()
+        }
+    }
+
+    fn saved(settings: &ScanSettings) -> Vec<u8> {
+        let mut saved = Vec::new();
+        let roots = [PathBuf::from("/home/me"), PathBuf::from("data")];
+        write_snapshot(
+            &mut saved,
+            &scan_result(),
+            &roots,
+            settings,
+            SystemTime::UNIX_EPOCH,
+        )
+        .unwrap();
+        saved
+    }
+
+    #[test]
+    fn snapshots_are_read_back() {
+        let settings = settings();
+        let saved = saved(&settings);
+        let text = String::from_utf8(saved.clone()).unwrap();
+        assert!(text.starts_with("fss-snapshot\t2\nfss\t"), "{text}");
+        assert!(text.contains("\ntime\t1970-01-01T00:00:00Z\n"), "{text}");
+        assert!(
+            text.contains("\ncount\tbytes\nsize_type\tapparent\n"),
+            "{text}"
+        );
+        assert!(text.contains("\nflat_depth\t2\n"), "{text}");
+        assert!(text.contains("\ngroup\t7\t1\ttwo\\nlines\\\\\n"), "{text}");
+
+        let snapshot = read_snapshot(&saved[..]).unwrap();
+        snapshot.check(&settings).unwrap();
+        assert_eq!(snapshot.fss_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(snapshot.group_by, GroupBy::Type);
+        assert_eq!(snapshot.settings.len(), 13);
+        assert_eq!(
+            snapshot.roots,
+            [PathBuf::from("/home/me"), PathBuf::from("data")]
+        );
+        assert_eq!((snapshot.total, snapshot.file_count), (1_507, 4));
+        assert!(snapshot.partial);
+        assert_eq!(
+            snapshot.errors,
+            [(
+                "permission_denied".to_string(),
+                2,
+                PathBuf::from("/home/me/private\r")
+            )]
+        );
+        // Every group is kept, they add up to the total
+        assert_eq!(snapshot.sizes.len(), 3);
+        assert_eq!(snapshot.sizes["Text\tnotes"], 500);
+        assert_eq!(snapshot.sizes["two\nlines\\"], 7);
+        assert_eq!(snapshot.file_counts["Text\tnotes"], 2);
+        assert_eq!(snapshot.file_counts["Image"], 1);
+    }
+
+    #[test]
+    fn names_are_escaped_on_one_line() {
+        for name in ["plain", "a\\b", "\\n", "two\nlines\r\n", "end\\"] {
+            assert!(!escape(name).contains(['\n', '\r']), "{name:?}");
+            assert_eq!(unescape(&escape(name)), name);
+        }
+        // Format 1 wrote the names as they were
+        let old = "fss-snapshot\t1\ngroup_by\ttype\ngroup\t5\t1\tC:\\new\n";
+        let snapshot = read_snapshot(old.as_bytes()).unwrap();
+        assert_eq!(snapshot.sizes["C:\\new"], 5);
+    }
+
+    #[test]
+    fn other_settings_and_formats_are_rejected() {
+        let settings = settings();
+        let snapshot = read_snapshot(&saved(&settings)[..]).unwrap();
+        let check = |other: ScanSettings| snapshot.check(&other).unwrap_err().to_string();
+        assert_eq!(
+            check(ScanSettings {
+                group_by: GroupBy::Extension,
+                ..settings.clone()
+            }),
+            "the snapshot groups the files by type, not by extension, use the same --group-by"
+        );
+        assert_eq!(
+            check(ScanSettings {
+                count: "inodes",
+                ..settings.clone()
+            }),
+            "the snapshot has count bytes, not inodes, use the same --count-mode or --inodes"
+        );
+        assert_eq!(
+            check(ScanSettings {
+                apparent: false,
+                ..settings.clone()
+            }),
+            "the snapshot has size_type apparent, not disk, use the same -b/--apparent-size"
+        );
+        assert_eq!(
+            check(ScanSettings {
+                flat_depth: None,
+                ..settings.clone()
+            }),
+            "the snapshot has flat_depth 2, not none, use the same --flat-depth"
+        );
+        assert_eq!(
+            check(ScanSettings {
+                sample: Some(0.1),
+                ..settings.clone()
+            }),
+            "the snapshot has sample none, not 0.1, use the same --sample"
+        );
+
+        // Two snapshots are compared with each other
+        let sampled = ScanSettings {
+            sample: Some(0.5),
+            ..settings.clone()
+        };
+        let other = read_snapshot(&saved(&sampled)[..]).unwrap();
+        assert_eq!(
+            snapshot.check_same(&other).unwrap_err().to_string(),
+            "the snapshots have sample none and 0.5"
+        );
+        snapshot.check_same(&snapshot).unwrap();
+
+        let err = read_snapshot("fss-snapshot\t3\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("format 3"), "{err}");
+        let err = read_snapshot("100\tpng\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("not a snapshot"), "{err}");
+        let err = read_snapshot("fss-snapshot\t2\ntotal\tmany\n".as_bytes());
+        assert!(err.unwrap_err().to_string().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn settings_and_records_missing_or_added_are_skipped() {
+        // Saved before the settings other than the grouping were recorded
+        let older = "fss-snapshot\t1\ngroup_by\ttype\ngroup\t5\t1\tImage\n";
+        let snapshot = read_snapshot(older.as_bytes()).unwrap();
+        assert!(snapshot.settings.is_empty());
+        snapshot.check(&settings()).unwrap();
+
+        // Saved by a later version
+        let newer = "fss-snapshot\t2\ngroup_by\ttype\nowner\tme\ngroup\t5\t1\tImage\n";
+        let snapshot = read_snapshot(newer.as_bytes()).unwrap();
+        assert_eq!(snapshot.sizes["Image"], 5);
+    }
+}
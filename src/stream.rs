@@ -0,0 +1,72 @@
+//! Buffered output that still reaches a pipe promptly.
+//!
+//! Writing the groups through the line-buffered stdout costs a write call per line, which adds
+//! up with millions of groups. Fully buffered, a consumer like `less` or `head` sees nothing
+//! until the buffer fills. [`StreamWriter`] buffers, and flushes at the end of a line once
+//! [`FLUSH_INTERVAL`] passed since the last flush.
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+/// How long written lines can wait in the buffer
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct StreamWriter<W: Write> {
+    inner: BufWriter<W>,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_interval(inner, FLUSH_INTERVAL)
+    }
+
+    pub fn with_interval(inner: W, interval: Duration) -> Self {
+        StreamWriter {
+            inner: BufWriter::new(inner),
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    #[cfg(test)]
+    fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        // Lines are never split between two flushes, unless they don't fit in the buffer
+        if buf[..written].ends_with(b"\n") && self.last_flush.elapsed() >= self.interval {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_flushed_once_the_interval_passed() {
+        let mut writer = StreamWriter::with_interval(Vec::new(), Duration::ZERO);
+        write!(writer, "500 B\t").unwrap();
+        assert!(writer.get_ref().is_empty());
+        writeln!(writer, "txt").unwrap();
+        assert_eq!(writer.get_ref(), b"500 B\ttxt\n");
+
+        let mut writer = StreamWriter::with_interval(Vec::new(), Duration::from_secs(3600));
+        writeln!(writer, "500 B\ttxt").unwrap();
+        assert!(writer.get_ref().is_empty());
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"500 B\ttxt\n");
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+/// Filesystem types whose latency comes from the network rather than from a local disk
+const NETWORK_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "lustre",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+/// What kind of storage a root lives on, as far as we can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageKind {
+    Network,
+    Rotational,
+    SolidState,
+    Unknown,
+}
+
+impl StorageKind {
+    /// Number of threads that works well for this kind of storage.
+    ///
+    /// Network filesystems and spinning disks don't get faster with more outstanding requests,
+    /// they only pay for the extra context switches. Local solid state storage benefits from some
+    /// oversubscription, but less than the 3x used by default, which is tuned for cold caches.
+    pub fn threads(&self, cores: usize) -> usize {
+        match self {
+            StorageKind::Network | StorageKind::Rotational => cores,
+            StorageKind::SolidState | StorageKind::Unknown => 2 * cores,
+        }
+    }
+}
+
+/// Pick the number of threads for `--threads auto` based on where the inputs are stored.
+/// With several inputs the most conservative choice wins.
+pub fn auto_threads(roots: &[PathBuf]) -> usize {
+    let cores = num_cpus::get();
+    roots
+        .iter()
+        .map(|root| storage_kind(root).threads(cores))
+        .min()
+        .unwrap_or(2 * cores)
+        .max(1)
+}
+
+#[cfg(target_os = "linux")]
+pub fn storage_kind(path: &Path) -> StorageKind {
+    let Ok(path) = path.canonicalize() else {
+        return StorageKind::Unknown;
+    };
+    let Ok(mountinfo) = std::fs::read_to_string("/proc/self/mountinfo") else {
+        return StorageKind::Unknown;
+    };
+    let mounts = parse_mountinfo(&mountinfo);
+    let Some(mount) = find_mount(&mounts, &path) else {
+        return StorageKind::Unknown;
+    };
+
+    if NETWORK_FS_TYPES.contains(&mount.fs_type.as_str()) {
+        return StorageKind::Network;
+    }
+
+    // Partitions don't have a queue of their own, the disk they belong to does
+    let device = Path::new("/sys/dev/block").join(&mount.device);
+    for queue in [device.join("queue"), device.join("../queue")] {
+        if let Ok(rotational) = std::fs::read_to_string(queue.join("rotational")) {
+            return match rotational.trim() {
+                "1" => StorageKind::Rotational,
+                _ => StorageKind::SolidState,
+            };
+        }
+    }
+    StorageKind::Unknown
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn storage_kind(_path: &Path) -> StorageKind {
+    StorageKind::Unknown
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+struct Mount {
+    /// "major:minor" of the mounted device
+    device: String,
+    mount_point: PathBuf,
+    fs_type: String,
+}
+
+/// Parse the contents of /proc/self/mountinfo, see proc(5)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (before, after) = line.split_once(" - ")?;
+            let mut fields = before.split(' ');
+            let device = fields.nth(2)?.to_string();
+            let mount_point = unescape_mount_path(fields.nth(1)?);
+            let fs_type = after.split(' ').next()?.to_string();
+            Some(Mount {
+                device,
+                mount_point: PathBuf::from(mount_point),
+                fs_type,
+            })
+        })
+        .collect()
+}
+
+/// Mount points have spaces, tabs, newlines and backslashes escaped as octal, e.g. "\040"
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape_mount_path(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('\\') {
+        out.push_str(&rest[..idx]);
+        let escaped = rest.get(idx + 1..idx + 4).unwrap_or_default();
+        match u8::from_str_radix(escaped, 8) {
+            Ok(byte) => {
+                out.push(byte as char);
+                rest = &rest[idx + 4..];
+            }
+            Err(_) => {
+                out.push('\\');
+                rest = &rest[idx + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The mount containing `path` is the one with the longest mount point that prefixes it
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn find_mount<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .max_by_key(|m| m.mount_point.components().count())
+}
+
+/// The number of file descriptors the process may open, `None` if unlimited or unknown
+#[cfg(unix)]
+pub fn fd_limit() -> Option<usize> {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `rlimit` is a valid out pointer
+    let ret = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) };
+    (ret == 0 && rlimit.rlim_cur != libc::RLIM_INFINITY).then_some(rlimit.rlim_cur as usize)
+}
+
+/// Raise the soft limit on file descriptors to the hard limit, like fd does. Many threads
+/// listing directories at once easily go past the usual soft limit of 1024.
+#[cfg(unix)]
+pub fn raise_fd_limit() {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `rlimit` is a valid out pointer
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) } != 0
+        || rlimit.rlim_cur >= rlimit.rlim_max
+    {
+        return;
+    }
+    // macOS refuses limits above OPEN_MAX, even when the hard limit is unlimited
+    let candidates = [rlimit.rlim_max, rlimit.rlim_max.min(10240)];
+    for limit in candidates {
+        let raised = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: rlimit.rlim_max,
+        };
+        // SAFETY: `raised` is a valid rlimit
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+40 22 0:45 / /mnt/nas rw,relatime shared:20 - nfs4 nas:/export rw,vers=4.2
+41 22 259:1 / /mnt/my\\040disk rw,relatime shared:21 - xfs /dev/nvme0n1p1 rw
+42 41 0:46 / /mnt/my\\040disk/tmp rw shared:22 - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn parses_mountinfo() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(
+            mounts[2],
+            Mount {
+                device: "259:1".to_string(),
+                mount_point: PathBuf::from("/mnt/my disk"),
+                fs_type: "xfs".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn finds_the_innermost_mount() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+        let mount = |p: &str| find_mount(&mounts, Path::new(p)).unwrap().fs_type.as_str();
+        assert_eq!(mount("/home/user"), "ext4");
+        assert_eq!(mount("/mnt/nas/photos"), "nfs4");
+        assert_eq!(mount("/mnt/my disk/data"), "xfs");
+        assert_eq!(mount("/mnt/my disk/tmp/x"), "tmpfs");
+        // Prefixes are matched per component, not per character
+        assert_eq!(mount("/mnt/nasty"), "ext4");
+    }
+
+    #[test]
+    fn conservative_thread_counts() {
+        assert_eq!(StorageKind::Network.threads(8), 8);
+        assert_eq!(StorageKind::Rotational.threads(8), 8);
+        assert_eq!(StorageKind::SolidState.threads(8), 16);
+    }
+}
@@ -1,16 +1,25 @@
-#[derive(Eq, PartialEq, Hash, Debug)]
+use std::path::Path;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct UniqueID {
     device: u64,
     inode: u64,
 }
 
+/// The ID of the file `path`, if it has several hardlinks or `every_file` is set. Files with a
+/// single link can only be reached twice through bind mounts or overlapping inputs, so they are
+/// only tracked when asked, which keeps the set of IDs small.
 #[cfg(not(windows))]
-pub fn generate_unique_id(metadata: &std::fs::Metadata) -> Option<UniqueID> {
+pub fn generate_unique_id(
+    _path: &Path,
+    metadata: &std::fs::Metadata,
+    every_file: bool,
+) -> Option<UniqueID> {
     use std::os::unix::fs::MetadataExt;
     // If the entry has more than one hard link, generate
     // a unique ID consisting of device and inode in order
     // not to count this entry twice.
-    if metadata.is_file() && metadata.nlink() > 1 {
+    if metadata.is_file() && (every_file || metadata.nlink() > 1) {
         Some(UniqueID {
             device: metadata.dev(),
             inode: metadata.ino(),
@@ -21,12 +30,37 @@ pub fn generate_unique_id(metadata: &std::fs::Metadata) -> Option<UniqueID> {
 }
 
 #[cfg(windows)]
-pub fn generate_unique_id(_metadata: &std::fs::Metadata) -> Option<UniqueID> {
-    // Windows-internal tools such as Powershell, Explorer or `dir` are not respecting hardlinks
-    // or junction points when determining the size of a directory. `diskus` does the same and
-    // counts such entries multiple times (on Unix systems, multiple hardlinks to a single file are
-    // counted just once).
-    //
-    // See: https://github.com/sharkdp/diskus/issues/32
-    None
+pub fn generate_unique_id(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    every_file: bool,
+) -> Option<UniqueID> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT,
+        GetFileInformationByHandle,
+    };
+
+    // The link count and file index are only available from a handle, so unlike on Unix every
+    // file costs an extra open. It is opened without any access right, which is enough to query
+    // its information and doesn't download cloud placeholders.
+    if !metadata.is_file() {
+        return None;
+    }
+    let file = std::fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(path)
+        .ok()?;
+    let mut info = BY_HANDLE_FILE_INFORMATION::default();
+    // SAFETY: the handle is open for the duration of the call and `info` is a valid out pointer
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return None;
+    }
+    // Same as on Unix, files with a single link are only tracked when asked
+    (every_file || info.nNumberOfLinks > 1).then(|| UniqueID {
+        device: info.dwVolumeSerialNumber as u64,
+        inode: ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64,
+    })
 }
@@ -1,106 +1,681 @@
-use crate::cli::GroupBy;
 use rayon::prelude::*;
 
 use crate::{
-    groups::FileType,
-    unique_id::{UniqueID, generate_unique_id},
+    access::{Access, User},
+    aggregate::{Aggregator, FileEntry, ScanResult, push_ext},
+    cli::GroupBy,
+    extents,
+    filesize::{self, Usage},
+    filter::{ExtFilter, SystemFilter},
+    long_path,
+    progress::Progress,
+    reparse::{self, Reparse},
+    sample::Sampler,
+    threads,
+    unique_id::generate_unique_id,
 };
 use crossbeam::channel;
+use fnv::FnvHashSet;
 
 use crate::FilesizeType;
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
+    fmt::Display,
+    fs, io,
     path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+/// The kinds of filesystem errors worth telling apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    PermissionDenied,
+    /// The entry disappeared between listing its directory and reading it
+    NotFound,
+    TooManyOpenFiles,
+    /// The entry was replaced by something else while we were reading it
+    Changed,
+    Other,
+}
+
+impl From<&io::Error> for ErrorKind {
+    fn from(err: &io::Error) -> Self {
+        #[cfg(unix)]
+        if err.raw_os_error() == Some(libc::EMFILE) {
+            return ErrorKind::TooManyOpenFiles;
+        }
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl ErrorKind {
+    /// The name of the kind in machine-readable output
+    pub fn id(&self) -> &'static str {
+        match self {
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::TooManyOpenFiles => "too_many_open_files",
+            ErrorKind::Changed => "changed",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ErrorKind::PermissionDenied => "permission denied",
+            ErrorKind::NotFound => "not found",
+            ErrorKind::TooManyOpenFiles => "too many open files",
+            ErrorKind::Changed => "changed during the scan",
+            ErrorKind::Other => "other error",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
-    NoMetadataForPath(PathBuf),
-    CouldNotReadDir(PathBuf),
+    NoMetadataForPath(PathBuf, io::Error),
+    CouldNotReadDir(PathBuf, io::Error),
+    /// A directory was replaced (e.g. by a file) between reading its metadata and its contents,
+    /// its contents are not counted
+    ChangedDuringScan(PathBuf),
+}
+
+impl Error {
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::NoMetadataForPath(path, _)
+            | Error::CouldNotReadDir(path, _)
+            | Error::ChangedDuringScan(path) => path,
+        }
+    }
+
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::NoMetadataForPath(_, err) | Error::CouldNotReadDir(_, err) => Some(err),
+            Error::ChangedDuringScan(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.io_error() {
+            Some(err) => err.into(),
+            None => ErrorKind::Changed,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = long_path::strip_extended(self.path());
+        let path = path.to_string_lossy();
+        match self {
+            Error::NoMetadataForPath(_, err) => {
+                write!(f, "could not retrieve metadata for path '{path}': {err}")
+            }
+            Error::CouldNotReadDir(_, err) => {
+                write!(f, "could not read contents of directory '{path}': {err}")
+            }
+            Error::ChangedDuringScan(_) => {
+                write!(f, "directory '{path}' changed while it was being read")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.io_error()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
 }
 
 #[derive(Debug)]
 enum Message {
-    SizeEntry(Option<UniqueID>, PathBuf, u64),
+    SizeEntry(FileEntry),
     Error { error: Error },
 }
 
-fn walk(tx: channel::Sender<Message>, entries: &[PathBuf], filesize_type: FilesizeType) {
-    entries.into_par_iter().for_each_with(tx, |tx_ref, entry| {
-        if let Ok(metadata) = entry.symlink_metadata() {
-            let unique_id = generate_unique_id(&metadata);
+/// Longest wait before giving up on a file descriptor, the waits add up to about twice that
+const MAX_FD_BACKOFF: Duration = Duration::from_millis(512);
+
+/// Whether the process or the system ran out of file descriptors
+fn is_out_of_fds(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE));
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// Run `f` again if it was interrupted by a signal, a second interruption is reported as is.
+/// When the process runs out of file descriptors, fewer directories are kept open and `f` is
+/// retried after a growing delay, while the other threads close theirs.
+fn retry<T>(open_dirs: &OpenDirs, f: impl Fn() -> io::Result<T>) -> io::Result<T> {
+    let mut interrupted = false;
+    let mut delay = Duration::from_millis(1);
+    loop {
+        match f() {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted && !interrupted => {
+                interrupted = true;
+            }
+            Err(err) if is_out_of_fds(&err) && delay <= MAX_FD_BACKOFF => {
+                open_dirs.shrink();
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Whether `path` still refers to the same directory whose metadata is `before`
+#[cfg(unix)]
+fn is_same_dir(path: &Path, before: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    path.symlink_metadata()
+        .is_ok_and(|now| now.is_dir() && now.dev() == before.dev() && now.ino() == before.ino())
+}
+
+#[cfg(not(unix))]
+fn is_same_dir(path: &Path, _before: &fs::Metadata) -> bool {
+    path.symlink_metadata().is_ok_and(|now| now.is_dir())
+}
+
+/// Limits after which the walk stops dispatching new work, what was found so far is kept
+#[derive(Debug, Default)]
+struct Budget<'a> {
+    max_files: Option<u64>,
+    deadline: Option<Instant>,
+    /// Set from outside the walk, by Ctrl-C or the embedder
+    cancel: Option<&'a AtomicBool>,
+    /// Set by the aggregator once it has all the files it needs
+    done: Option<&'a AtomicBool>,
+    files: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl Budget<'_> {
+    fn is_exhausted(&self) -> bool {
+        if self.exhausted.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self
+            .cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        {
+            self.exhausted.store(true, Ordering::Relaxed);
+            return true;
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.exhausted.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// Whether the aggregator has all the files it needs. Unlike the other limits, this doesn't
+    /// make the results partial by itself: only skipping an entry that held more does.
+    fn is_done(&self) -> bool {
+        self.done.is_some_and(|done| done.load(Ordering::Relaxed))
+    }
+
+    /// Count a file, false if it is over the limit and must be left out
+    fn take_file(&self) -> bool {
+        let Some(max_files) = self.max_files else {
+            return true;
+        };
+        if self.files.fetch_add(1, Ordering::Relaxed) < max_files {
+            return true;
+        }
+        self.exhausted.store(true, Ordering::Relaxed);
+        false
+    }
+}
+
+/// Settings shared by every step of the recursive walk
+struct WalkContext<'a> {
+    filesize_type: FilesizeType,
+    /// Directories not to descend into, spelled the same way as the paths of the walk
+    excluded: FnvHashSet<PathBuf>,
+    follow_links: bool,
+    /// Whether to read the extents of every file to find the shared ones
+    dedupe_extents: bool,
+    /// Whether files need a unique ID, which costs an extra open per file on Windows
+    dedup: bool,
+    /// Whether files with a single link need one too
+    dedup_every_file: bool,
+    /// Whether to add the extended attributes or alternate data streams of every file to its size
+    include_streams: bool,
+    /// Whether to measure both the apparent size and the disk usage of every file
+    measure_both: bool,
+    /// The user whose access to every file is worked out, for `GroupBy::Access`
+    user: Option<User>,
+    /// Whether to count the size of directories themselves
+    count_dirs: bool,
+    /// Whether to skip FIFOs, sockets and devices
+    regular_only: bool,
+    /// FIFOs, sockets and devices found, counted or not
+    special_files: AtomicU64,
+    progress: Option<Progress>,
+    budget: Budget<'a>,
+    /// Files left out by extension
+    ext_filter: ExtFilter,
+    /// Files and directories managed by the OS, left out by name
+    system_filter: SystemFilter,
+    /// Picks the files read when only a fraction of them is
+    sampler: Option<Sampler>,
+    open_dirs: OpenDirs,
+    /// Canonical inputs and link targets, whatever is below them is already counted
+    seen_targets: Mutex<Vec<PathBuf>>,
+}
+
+/// Spell each excluded directory the way the walk will reach it, i.e. starting with the
+/// (possibly relative, non canonical) root that contains it. Directories that don't exist or
+/// aren't below any root can never be reached and are dropped.
+fn resolve_excluded(roots: &[PathBuf], excluded: &[PathBuf]) -> FnvHashSet<PathBuf> {
+    let excluded: Vec<PathBuf> = excluded
+        .iter()
+        .filter_map(|p| p.canonicalize().ok())
+        .collect();
+
+    let mut resolved = FnvHashSet::default();
+    for root in roots {
+        let Ok(canonical_root) = root.canonicalize() else {
+            continue;
+        };
+        for path in &excluded {
+            match path.strip_prefix(&canonical_root) {
+                // Joining an empty path would add a trailing slash
+                Ok(rel) if rel.as_os_str().is_empty() => resolved.insert(root.clone()),
+                Ok(rel) => resolved.insert(root.join(rel)),
+                Err(_) => false,
+            };
+        }
+    }
+    resolved
+}
+
+/// Metadata of the entry `link` points to, and its canonical path. Targets inside one of the
+/// inputs or already reached through another link are skipped (`None`), they are counted once
+/// that way and a link to one of its own parents can't send the walk in circles.
+fn follow_link(link: &Path, ctx: &WalkContext) -> Option<io::Result<(PathBuf, fs::Metadata)>> {
+    let target = match fs::canonicalize(link) {
+        Ok(target) => target,
+        Err(err) => return Some(Err(err)),
+    };
+    let metadata = match retry(&ctx.open_dirs, || fs::metadata(&target)) {
+        Ok(metadata) => metadata,
+        Err(err) => return Some(Err(err)),
+    };
+
+    let mut seen = ctx.seen_targets.lock().unwrap();
+    if seen.iter().any(|seen| target.starts_with(seen)) {
+        return None;
+    }
+    seen.push(target.clone());
+    Some(Ok((target, metadata)))
+}
+
+/// Number of entries in the inputs, for the progress percentage. This only lists directories,
+/// which tells the type of their children without reading their metadata on most filesystems.
+fn count_entries(roots: &[PathBuf], ctx: &WalkContext, progress: &Progress) -> u64 {
+    progress.add_counted(roots.len() as u64);
+    let below: u64 = roots
+        .par_iter()
+        .filter(|root| root.symlink_metadata().is_ok_and(|m| m.is_dir()))
+        .map(|root| count_below(root, ctx, progress))
+        .sum();
+    roots.len() as u64 + below
+}
+
+fn count_below(dir: &Path, ctx: &WalkContext, progress: &Progress) -> u64 {
+    if ctx.excluded.contains(dir) || ctx.budget.is_exhausted() {
+        return 0;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut count = 0;
+    let mut subdirs = vec![];
+    for entry in entries.flatten() {
+        if ctx.system_filter.skips(&entry.file_name()) {
+            continue;
+        }
+        count += 1;
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            subdirs.push(dir.join(entry.file_name()));
+        }
+    }
+    progress.add_counted(count);
+    count
+        + subdirs
+            .par_iter()
+            .map(|subdir| count_below(subdir, ctx, progress))
+            .sum::<u64>()
+}
+
+/// An entry to scan, and the listing it comes from. The listing keeps its directory open, so the
+/// metadata of the entry is read relative to it (fstatat) instead of resolving its whole path
+/// from the root once more, which adds up on deep trees.
+struct Child {
+    path: PathBuf,
+    listing: Option<fs::DirEntry>,
+}
+
+impl Child {
+    fn symlink_metadata(&self) -> io::Result<fs::Metadata> {
+        match &self.listing {
+            // Doesn't follow links either
+            Some(listing) => listing.metadata(),
+            None => self.path.symlink_metadata(),
+        }
+    }
+}
+
+/// Bounds the number of directories kept open by the listings of their entries, past it the
+/// entries are read by path
+struct OpenDirs {
+    open: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+/// A directory counted as open, until dropped
+struct OpenDir<'a>(&'a AtomicUsize);
+
+impl Drop for OpenDir<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// File descriptors set aside for each thread, for the directory it lists and the file it
+/// reads, and for the process itself
+const FDS_PER_THREAD: usize = 2;
+const FDS_RESERVED: usize = 16;
+
+impl OpenDirs {
+    /// Half the file descriptors left once every thread has its own, the other half is left
+    /// for the rest of the process
+    #[cfg(unix)]
+    fn new(num_threads: usize) -> Self {
+        // Unlimited is as good as a large limit
+        let fd_limit = threads::fd_limit().unwrap_or(4096);
+        let spare = fd_limit.saturating_sub(num_threads * FDS_PER_THREAD + FDS_RESERVED);
+        OpenDirs {
+            open: AtomicUsize::new(0),
+            limit: AtomicUsize::new(spare / 2),
+        }
+    }
 
-            let size = filesize_type.size(&metadata);
+    /// Only Unix reads the metadata relative to the listing
+    #[cfg(not(unix))]
+    fn new(_num_threads: usize) -> Self {
+        OpenDirs {
+            open: AtomicUsize::new(0),
+            limit: AtomicUsize::new(0),
+        }
+    }
+
+    fn try_open(&self) -> Option<OpenDir<'_>> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.open
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| {
+                (open < limit).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| OpenDir(&self.open))
+    }
+
+    /// Keep at most half the directories open now, the file descriptors ran out
+    fn shrink(&self) {
+        let open = self.open.load(Ordering::Relaxed);
+        self.limit.fetch_min(open / 2, Ordering::Relaxed);
+    }
+}
+
+/// Whether `metadata` is the one of a FIFO, a socket or a device
+#[cfg(unix)]
+fn is_special(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = metadata.file_type();
+    file_type.is_fifo()
+        || file_type.is_socket()
+        || file_type.is_block_device()
+        || file_type.is_char_device()
+}
+
+#[cfg(not(unix))]
+fn is_special(_metadata: &fs::Metadata) -> bool {
+    false
+}
 
-            if metadata.is_dir() {
-                let mut children = vec![];
-                match fs::read_dir(entry) {
-                    Ok(child_entries) => {
-                        for child_entry in child_entries.flatten() {
-                            children.push(child_entry.path());
+/// Whether skipping `path` leaves out anything: every entry but the directories with nothing in
+/// them, unless directories count themselves
+fn leaves_out(path: &Path, count_dirs: bool) -> bool {
+    count_dirs
+        || !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+        || fs::read_dir(path).map_or(true, |mut entries| entries.next().is_some())
+}
+
+fn walk(tx: channel::Sender<Message>, entries: &[Child], ctx: &WalkContext) {
+    entries.into_par_iter().for_each_with(tx, |tx_ref, child| {
+        if ctx.budget.is_exhausted() {
+            return;
+        }
+        if ctx.budget.is_done() {
+            if leaves_out(&child.path, ctx.count_dirs) {
+                ctx.budget.exhausted.store(true, Ordering::Relaxed);
+            }
+            return;
+        }
+        if let Some(progress) = &ctx.progress {
+            progress.inc();
+        }
+        let entry = &child.path;
+        match retry(&ctx.open_dirs, || child.symlink_metadata()) {
+            Ok(mut metadata) => {
+                // Where the contents of `entry` are read from, differs for followed links
+                let mut dir = entry.clone();
+                let mut reparse = reparse::reparse_kind(entry, &metadata);
+                let is_link = metadata.is_symlink() || reparse == Reparse::Link;
+                let mut followed = false;
+                if is_link && ctx.follow_links {
+                    match follow_link(entry, ctx) {
+                        Some(Ok((target, target_metadata))) => {
+                            reparse = reparse::reparse_kind(&target, &target_metadata);
+                            metadata = target_metadata;
+                            dir = target;
+                            followed = true;
                         }
+                        None => return,
+                        // A dangling link is counted as a link
+                        Some(Err(_)) => {}
+                    }
+                }
+
+                let unique_id = if ctx.dedup {
+                    generate_unique_id(&dir, &metadata, ctx.dedup_every_file)
+                } else {
+                    None
+                };
+
+                let dehydrated = matches!(reparse, Reparse::CloudPlaceholder { dehydrated: true });
+                let size_as = |filesize_type: FilesizeType| {
+                    if dehydrated {
+                        filesize_type.dehydrated_size(&metadata)
+                    } else {
+                        filesize_type.size(&dir, &metadata)
+                    }
+                };
+                let size = size_as(ctx.filesize_type);
+
+                // Links that are not followed are counted like files, whatever they point to
+                if metadata.is_dir() && (!is_link || followed) {
+                    if ctx.excluded.contains(entry) {
+                        return;
                     }
-                    Err(_) => {
+                    if ctx.count_dirs {
                         tx_ref
-                            .send(Message::Error {
-                                error: Error::CouldNotReadDir(entry.clone()),
-                            })
+                            .send(Message::SizeEntry(FileEntry {
+                                modified: metadata.modified().ok(),
+                                created: metadata.created().ok(),
+                                is_dir: true,
+                                ..FileEntry::new(entry.to_owned(), size)
+                            }))
                             .unwrap();
                     }
-                }
+                    // Listing the contents of a dehydrated placeholder downloads them, and
+                    // none of it is on disk anyway
+                    if dehydrated && matches!(ctx.filesize_type, FilesizeType::DiskUsage) {
+                        return;
+                    }
 
-                walk(tx_ref.clone(), &children[..], filesize_type);
-            } else {
+                    // Dropped after `children`
+                    let open_dir = ctx.open_dirs.try_open();
+                    let mut children = vec![];
+                    match retry(&ctx.open_dirs, || fs::read_dir(&dir)) {
+                        Ok(child_entries) => {
+                            for child_entry in child_entries.flatten() {
+                                if ctx.system_filter.skips(&child_entry.file_name()) {
+                                    continue;
+                                }
+                                let path = entry.join(child_entry.file_name());
+                                // Every directory is descended into, only files are sampled.
+                                // The listing tells the type without reading the metadata.
+                                if let Some(sampler) = &ctx.sampler {
+                                    let is_dir = child_entry.file_type().is_ok_and(|t| t.is_dir());
+                                    if !is_dir && !sampler.keeps(&path) {
+                                        continue;
+                                    }
+                                }
+                                children.push(Child {
+                                    path,
+                                    listing: open_dir.is_some().then_some(child_entry),
+                                });
+                            }
+                            // The directory could have been swapped for another entry after we
+                            // read its metadata. Checking once more after listing costs a stat
+                            // per directory, not per file.
+                            if !is_same_dir(&dir, &metadata) {
+                                children.clear();
+                                tx_ref
+                                    .send(Message::Error {
+                                        error: Error::ChangedDuringScan(entry.clone()),
+                                    })
+                                    .unwrap();
+                            }
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::NotADirectory => {
+                            tx_ref
+                                .send(Message::Error {
+                                    error: Error::ChangedDuringScan(entry.clone()),
+                                })
+                                .unwrap();
+                        }
+                        Err(err) => {
+                            tx_ref
+                                .send(Message::Error {
+                                    error: Error::CouldNotReadDir(entry.clone(), err),
+                                })
+                                .unwrap();
+                        }
+                    }
+
+                    walk(tx_ref.clone(), &children[..], ctx);
+                } else {
+                    if is_special(&metadata) {
+                        ctx.special_files.fetch_add(1, Ordering::Relaxed);
+                        if ctx.regular_only {
+                            return;
+                        }
+                    }
+                    if !ctx.ext_filter.is_empty() {
+                        let mut ext = String::new();
+                        push_ext(entry, &mut ext, false);
+                        if !ctx.ext_filter.keeps(&ext) {
+                            return;
+                        }
+                    }
+                    if !ctx.budget.take_file() {
+                        return;
+                    }
+                    let streams = if ctx.include_streams {
+                        filesize::stream_size(&dir).unwrap_or(0)
+                    } else {
+                        0
+                    };
+                    tx_ref
+                        .send(Message::SizeEntry(FileEntry {
+                            unique_id,
+                            path: entry.to_owned(),
+                            size: size.saturating_add(streams),
+                            modified: metadata.modified().ok(),
+                            created: metadata.created().ok(),
+                            shared: if ctx.dedupe_extents && metadata.is_file() {
+                                extents::shared_bytes(&dir).ok()
+                            } else {
+                                Some(0)
+                            },
+                            streams,
+                            usage: ctx.measure_both.then(|| Usage {
+                                apparent: size_as(FilesizeType::ApparentSize),
+                                allocated: size_as(FilesizeType::DiskUsage),
+                            }),
+                            is_dir: false,
+                            access: ctx
+                                .user
+                                .as_ref()
+                                .map_or(Access::Unknown, |user| user.access(&metadata)),
+                        }))
+                        .unwrap();
+                };
+            }
+            Err(err) => {
                 tx_ref
-                    .send(Message::SizeEntry(unique_id, entry.to_owned(), size))
+                    .send(Message::Error {
+                        error: Error::NoMetadataForPath(entry.clone(), err),
+                    })
                     .unwrap();
-            };
-        } else {
-            tx_ref
-                .send(Message::Error {
-                    error: Error::NoMetadataForPath(entry.clone()),
-                })
-                .unwrap();
+            }
         };
     });
 }
 
-#[inline(always)]
-fn get_ext(path: &Path) -> String {
-    path.extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_ascii_lowercase()
-}
-
-#[inline(always)]
-fn get_filename(path: &Path) -> String {
-    path.file_name()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_owned()
-}
-
-#[inline(always)]
-fn get_parent_directory(path: &Path) -> String {
-    path.parent()
-        .unwrap_or(Path::new(""))
-        .file_name()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_owned()
-}
-
 pub struct Walk<'a> {
     root_dirs: &'a Vec<PathBuf>,
     num_threads: usize,
     filesize_type: FilesizeType,
-    group_by: GroupBy,
+    aggregator: Aggregator,
+    excluded: Vec<PathBuf>,
+    follow_links: bool,
+    include_streams: bool,
+    measure_both: bool,
+    count_dirs: bool,
+    regular_only: bool,
+    force_dedup: bool,
+    max_files: Option<u64>,
+    timeout: Option<Duration>,
+    ext_filter: ExtFilter,
+    system_filter: SystemFilter,
+    sampler: Option<Sampler>,
+    progress: bool,
+    accurate_progress: bool,
+    files: Option<Vec<PathBuf>>,
 }
 
 impl<'a> Walk<'a> {
@@ -108,78 +683,981 @@ impl<'a> Walk<'a> {
         root_dirs: &'a Vec<PathBuf>,
         num_threads: usize,
         filesize_type: FilesizeType,
-        group_by: GroupBy,
+        aggregator: Aggregator,
     ) -> Walk<'a> {
         Walk {
             root_dirs,
             num_threads,
             filesize_type,
-            group_by,
+            aggregator,
+            excluded: Vec::new(),
+            follow_links: false,
+            include_streams: false,
+            measure_both: false,
+            count_dirs: false,
+            regular_only: false,
+            force_dedup: false,
+            max_files: None,
+            timeout: None,
+            ext_filter: ExtFilter::default(),
+            system_filter: SystemFilter::default(),
+            sampler: None,
+            progress: false,
+            accurate_progress: false,
+            files: None,
         }
     }
 
-    pub fn run(&self) -> anyhow::Result<(u64, HashMap<String, u64>, Vec<Error>)> {
+    /// Don't descend into these directories, typically mount points. They may be spelled
+    /// differently than the roots, e.g. through a symlink.
+    pub fn exclude(mut self, excluded: Vec<PathBuf>) -> Self {
+        self.excluded = excluded;
+        self
+    }
+
+    /// Count what symlinks and junctions point to instead of the links themselves
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Track every file to count it once, not only the ones with several hardlinks, so that a
+    /// file reached twice through bind mounts or overlapping inputs is counted once. Does
+    /// nothing when the aggregator doesn't deduplicate.
+    pub fn force_dedup(mut self, force_dedup: bool) -> Self {
+        self.force_dedup = force_dedup;
+        self
+    }
+
+    /// Add what is stored beside the data of each file to its size: extended attributes on
+    /// macOS, alternate data streams on Windows
+    #[cfg_attr(not(any(target_os = "macos", windows)), allow(dead_code))]
+    pub fn include_streams(mut self, include_streams: bool) -> Self {
+        self.include_streams = include_streams;
+        self
+    }
+
+    /// Measure the apparent size and the disk usage of every file, whichever is counted, to
+    /// tell how much space sparse and compressed files save. This is free on Unix but costs a
+    /// system call per file on Windows.
+    pub fn measure_both(mut self, measure_both: bool) -> Self {
+        self.measure_both = measure_both;
+        self
+    }
+
+    /// Count the size of every directory itself, like `du` does
+    pub fn count_dirs(mut self, count_dirs: bool) -> Self {
+        self.count_dirs = count_dirs;
+        self
+    }
+
+    /// Skip FIFOs, sockets and devices, whose sizes mean nothing. They are counted either way.
+    pub fn regular_only(mut self, regular_only: bool) -> Self {
+        self.regular_only = regular_only;
+        self
+    }
+
+    /// Stop after counting this many files
+    pub fn max_files(mut self, max_files: Option<u64>) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Stop after scanning for this long
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Only count the files this filter keeps
+    pub fn ext_filter(mut self, ext_filter: ExtFilter) -> Self {
+        self.ext_filter = ext_filter;
+        self
+    }
+
+    /// Skip the files and directories this filter names, with everything below them
+    pub fn system_filter(mut self, system_filter: SystemFilter) -> Self {
+        self.system_filter = system_filter;
+        self
+    }
+
+    /// Only read the files this sampler keeps, and scale the sizes up to estimates
+    pub fn sample(mut self, sampler: Option<Sampler>) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Show how many entries were scanned so far on stderr
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Count the entries before scanning them, so the progress is shown as a percentage. This
+    /// reads every directory twice.
+    pub fn accurate_progress(mut self, accurate_progress: bool) -> Self {
+        self.accurate_progress = accurate_progress;
+        self
+    }
+
+    /// Only scan these entries, found below the roots, instead of the roots themselves
+    pub fn files(mut self, files: Option<Vec<PathBuf>>) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Walk to the end, fss itself always runs cancellable by Ctrl-C
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn run(self) -> anyhow::Result<ScanResult> {
+        self.run_cancellable(None)
+    }
+
+    /// Like [`Walk::run`], but the walk stops early once `token` is set, from another thread or
+    /// a signal handler. What was found until then is returned, with `partial` and
+    /// `interrupted` set on the result.
+    pub fn run_with_cancel(self, token: &AtomicBool) -> anyhow::Result<ScanResult> {
+        self.run_cancellable(Some(token))
+    }
+
+    fn run_cancellable(self, cancel: Option<&AtomicBool>) -> anyhow::Result<ScanResult> {
         let (tx, rx) = channel::unbounded();
-        let group_by = self.group_by;
+        let roots: Vec<PathBuf> = self
+            .root_dirs
+            .iter()
+            .map(|root| long_path::to_extended(root))
+            .collect();
+        let dedup = self.aggregator.is_dedup();
+        let dedupe_extents = self.aggregator.is_dedupe_extents();
+        let done = self.aggregator.done_flag();
+        let user = self
+            .aggregator
+            .groups_by(GroupBy::Access)
+            .then(User::current);
+        let mut aggregator = self.aggregator.sampler(self.sampler).roots(roots.clone());
 
         let receiver_thread = thread::spawn(move || {
-            let mut total = 0;
-            let mut ids = HashSet::new();
-            let mut sizes: HashMap<String, u64> = HashMap::new();
-            let mut error_messages: Vec<Error> = Vec::new();
-
             for msg in rx {
                 match msg {
-                    Message::SizeEntry(unique_id, path, size) => {
-                        if let Some(unique_id) = unique_id {
-                            // Only count this entry if the ID has not been seen
-                            if !ids.insert(unique_id) {
-                                continue;
-                            }
-                        }
-
-                        total += size;
-                        match group_by {
-                            GroupBy::Type => {
-                                let filetype = FileType::get_filetype(&get_ext(&path)).to_string();
-                                sizes
-                                    .entry(filetype)
-                                    .and_modify(|s| *s += size)
-                                    .or_insert(size);
-                            }
-                            GroupBy::Extension => {
-                                let ext = get_ext(&path);
-                                sizes.entry(ext).and_modify(|s| *s += size).or_insert(size);
-                            }
-                            GroupBy::FileName => {
-                                let filename = get_filename(&path);
-                                sizes
-                                    .entry(filename)
-                                    .and_modify(|s| *s += size)
-                                    .or_insert(size);
-                            }
-                            GroupBy::Directory => {
-                                let parent = get_parent_directory(&path);
-                                sizes
-                                    .entry(parent)
-                                    .and_modify(|s| *s += size)
-                                    .or_insert(size);
-                            }
-                        }
-                    }
-                    Message::Error { error } => {
-                        error_messages.push(error);
-                    }
+                    Message::SizeEntry(entry) => aggregator.add_file(entry),
+                    Message::Error { error } => aggregator.add_error(error),
                 }
             }
-            (total, sizes, error_messages)
+            aggregator.finish()
         });
 
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(self.num_threads)
             .build()?;
-        pool.install(|| walk(tx, self.root_dirs, self.filesize_type));
+        let ctx = WalkContext {
+            filesize_type: self.filesize_type,
+            excluded: resolve_excluded(&roots, &self.excluded),
+            follow_links: self.follow_links,
+            dedup,
+            dedup_every_file: dedup && self.force_dedup,
+            dedupe_extents,
+            include_streams: self.include_streams,
+            measure_both: self.measure_both,
+            user,
+            count_dirs: self.count_dirs,
+            regular_only: self.regular_only,
+            special_files: AtomicU64::new(0),
+            budget: Budget {
+                max_files: self.max_files,
+                deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+                cancel,
+                done: Some(&done),
+                ..Default::default()
+            },
+            ext_filter: self.ext_filter,
+            system_filter: self.system_filter,
+            sampler: self.sampler,
+            open_dirs: OpenDirs::new(self.num_threads),
+            progress: (self.progress || self.accurate_progress).then(Progress::new),
+            // A root that is a link is added once followed
+            seen_targets: Mutex::new(
+                roots
+                    .iter()
+                    .filter(|root| root.symlink_metadata().is_ok_and(|m| !m.is_symlink()))
+                    .filter_map(|root| root.canonicalize().ok())
+                    .collect(),
+            ),
+        };
+        let starts: Vec<PathBuf> = match self.files {
+            Some(files) => files
+                .iter()
+                .map(|file| long_path::to_extended(file))
+                .collect(),
+            None => roots,
+        };
+        let root_children: Vec<Child> = starts
+            .iter()
+            .map(|start| Child {
+                path: start.clone(),
+                listing: None,
+            })
+            .collect();
+        pool.install(|| match &ctx.progress {
+            Some(progress) => progress.show_while(|| {
+                if self.accurate_progress {
+                    progress.set_total(count_entries(&starts, &ctx, progress));
+                }
+                walk(tx, &root_children, &ctx)
+            }),
+            None => walk(tx, &root_children, &ctx),
+        });
+
+        let mut result = receiver_thread.join().unwrap();
+        result.threads = self.num_threads;
+        // The aggregator marks it partial itself if it left out files past its limit
+        result.partial |= ctx.budget.exhausted.into_inner();
+        result.interrupted = cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+        result.special_files = ctx.special_files.into_inner();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::{DIRECTORIES, FileList};
+    use crate::cli::GroupBy;
+    use crate::filter::system;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn write_file(path: &Path, len: usize) {
+        File::create(path)
+            .unwrap()
+            .write_all(&vec![0; len])
+            .unwrap();
+    }
+
+    fn scan(root: &Path, configure: impl FnOnce(Walk) -> Walk) -> ScanResult {
+        scan_with(root, Aggregator::new(GroupBy::Extension), configure)
+    }
+
+    fn scan_with(
+        root: &Path,
+        aggregator: Aggregator,
+        configure: impl FnOnce(Walk) -> Walk,
+    ) -> ScanResult {
+        let roots = vec![root.to_path_buf()];
+        let walk = Walk::new(&roots, 2, FilesizeType::ApparentSize, aggregator);
+        configure(walk).run().unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sparse_files_are_found_in_one_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dense = File::create(dir.path().join("dense.txt")).unwrap();
+        dense.write_all(&[1; 100_000]).unwrap();
+        dense.sync_all().unwrap();
+        File::create(dir.path().join("sparse.img"))
+            .unwrap()
+            .set_len(64 * 1024 * 1024)
+            .unwrap();
+
+        let result = scan(dir.path(), |w| w.measure_both(true));
+        assert_eq!(result.total, 100_000 + 64 * 1024 * 1024);
+        assert_eq!(result.apparent_total, result.total);
+        assert!(result.allocated_total < result.apparent_total);
+        assert_eq!(result.sparse_files, 1);
+        let image = result.usage_sizes["img"];
+        assert_eq!(image.apparent, 64 * 1024 * 1024);
+        assert!(image.is_sparse());
+        assert_eq!(result.usage_sizes["txt"].apparent, 100_000);
+
+        let result = scan(dir.path(), |w| w);
+        assert_eq!(result.apparent_total, 0);
+        assert_eq!(result.sparse_files, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_sizes_are_counted_like_du() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        write_file(&root.join("a.txt"), 1000);
+        write_file(&root.join("sub/b.bin"), 300);
+        fs::hard_link(root.join("a.txt"), root.join("sub/a-link.txt")).unwrap();
+        std::os::unix::fs::symlink("a.txt", root.join("link")).unwrap();
+
+        // What `du -sb` adds up: each directory, each file once, each link by its own length
+        let dirs =
+            fs::metadata(root).unwrap().len() + fs::metadata(root.join("sub")).unwrap().len();
+        let expected = dirs + 1000 + 300 + "a.txt".len() as u64;
+
+        let result = scan(root, |w| w.count_dirs(true));
+        assert_eq!(result.total, expected);
+        assert_eq!(result.sizes[DIRECTORIES], dirs);
+        assert_eq!(result.file_count, 3);
+        assert_eq!(result.deduplicated, 1);
+
+        let result = scan(root, |w| w);
+        assert_eq!(result.total, expected - dirs);
+        assert!(!result.sizes.contains_key(DIRECTORIES));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_are_reported_and_can_be_skipped() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), 10);
+        let fifo = CString::new(dir.path().join("pipe").as_os_str().as_bytes()).unwrap();
+        // SAFETY: `fifo` is a valid NUL-terminated path
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let result = scan(dir.path(), |w| w);
+        assert_eq!(result.file_count, 2);
+        assert_eq!(result.special_files, 1);
+
+        let result = scan(dir.path(), |w| w.regular_only(true));
+        assert_eq!(result.file_count, 1);
+        assert_eq!(result.total, 10);
+        assert_eq!(result.special_files, 1);
+        assert!(!result.sizes.contains_key(""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_names_are_not_dropped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Some filesystems, like APFS, only accept UTF-8 names
+        let name = OsStr::from_bytes(b"r\xe9sum\xe9.txt");
+        if File::create(dir.path().join(name)).is_err() {
+            return;
+        }
+        write_file(&dir.path().join(name), 30);
+        write_file(&dir.path().join("other.txt"), 5);
+
+        let aggregator = Aggregator::new(GroupBy::FileName).escape_nonutf8(true);
+        let result = scan_with(dir.path(), aggregator, |w| w);
+        assert_eq!(result.file_count, 2);
+        assert_eq!(result.sizes["r\\xE9sum\\xE9.txt"], 30);
+    }
+
+    #[test]
+    fn filtered_extensions_are_not_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.jpg"), 100);
+        write_file(&dir.path().join("b.PNG"), 20);
+        write_file(&dir.path().join("c.log"), 3);
+
+        let exts = |exts: &[&str]| exts.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        let result = scan(dir.path(), |w| {
+            w.ext_filter(ExtFilter::new(&exts(&["jpg", "png"]), &exts(&["jpg"])))
+        });
+        assert_eq!(result.total, 20);
+        assert_eq!(result.file_count, 1);
+        assert_eq!(result.sizes.keys().collect::<Vec<_>>(), ["png"]);
+    }
+
+    #[test]
+    fn deep_files_roll_up_to_their_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("photos/2023/trip")).unwrap();
+        fs::create_dir_all(dir.path().join("code/fss/src")).unwrap();
+        write_file(&dir.path().join("photos/2023/trip/a.jpg"), 100);
+        write_file(&dir.path().join("photos/2023/b.jpg"), 20);
+        write_file(&dir.path().join("photos/c.jpg"), 3);
+        write_file(&dir.path().join("code/fss/src/main.rs"), 40);
+        write_file(&dir.path().join("notes.txt"), 5);
+
+        let scan_at = |depth| {
+            let aggregator = Aggregator::new(GroupBy::Directory).flat_depth(Some(depth));
+            scan_with(dir.path(), aggregator, |w| w).sizes
+        };
+        let group = |relative: &str| dir.path().join(relative).to_string_lossy().into_owned();
+        let root = dir.path().to_string_lossy().into_owned();
+
+        let sizes = scan_at(1);
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes[group("photos").as_str()], 123);
+        assert_eq!(sizes[group("code").as_str()], 40);
+        assert_eq!(sizes[root.as_str()], 5);
+
+        let sizes = scan_at(2);
+        assert_eq!(sizes[group("photos/2023").as_str()], 120);
+        assert_eq!(sizes[group("photos").as_str()], 3);
+        assert_eq!(sizes[group("code/fss").as_str()], 40);
+
+        let sizes = scan_at(0);
+        assert_eq!(sizes.len(), 1);
+        assert_eq!(sizes[root.as_str()], 168);
+    }
+
+    #[test]
+    fn same_named_directories_are_told_apart_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        for (root, size) in [("project", 100), ("other", 30)] {
+            fs::create_dir_all(dir.path().join(root).join("src/bin")).unwrap();
+            write_file(&dir.path().join(root).join("src/lib.rs"), size);
+            write_file(&dir.path().join(root).join("src/bin/main.rs"), 7);
+        }
+        write_file(&dir.path().join("project/README"), 5);
+
+        let roots = vec![dir.path().join("project"), dir.path().join("other")];
+        let aggregator = Aggregator::new(GroupBy::Path);
+        let result = Walk::new(&roots, 2, FilesizeType::ApparentSize, aggregator)
+            .run()
+            .unwrap();
+        let label = |path: &str| PathBuf::from(path).to_string_lossy().into_owned();
+
+        assert_eq!(result.sizes.len(), 5);
+        assert_eq!(result.sizes[label("project/src").as_str()], 100);
+        assert_eq!(result.sizes[label("other/src").as_str()], 30);
+        assert_eq!(result.sizes[label("other/src/bin").as_str()], 7);
+        assert_eq!(result.sizes["project"], 5);
+        assert_eq!(result.depths["project"], 0);
+        assert_eq!(result.depths[label("project/src").as_str()], 1);
+        assert_eq!(result.depths[label("other/src/bin").as_str()], 2);
+    }
+
+    #[test]
+    fn files_are_grouped_by_creation_time_when_known() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), 10);
+        write_file(&dir.path().join("b.txt"), 5);
+        let created = fs::metadata(dir.path().join("a.txt")).unwrap().created();
+
+        for (group_by, len) in [(GroupBy::CreatedYear, 4), (GroupBy::CreatedMonth, 7)] {
+            let result = scan_with(dir.path(), Aggregator::new(group_by), |w| w);
+            assert_eq!(result.sizes.len(), 1, "{:?}", result.sizes);
+            let (group, size) = result.sizes.iter().next().unwrap();
+            assert_eq!(*size, 15);
+            // Not recorded on every platform and filesystem
+            match created {
+                Ok(_) => assert_eq!(group.len(), len, "{group}"),
+                Err(_) => assert_eq!(group, "Unknown"),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_grouped_by_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, len: usize, mode: u32| {
+            let path = dir.path().join(name);
+            write_file(&path, len);
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        file("mine.txt", 10, 0o600);
+        file("also_mine.txt", 5, 0o644);
+        file("shared.txt", 20, 0o666);
+        file("locked.txt", 7, 0o400);
+
+        // The files are ours, or we are root who may write anything
+        let is_root = unsafe { libc::geteuid() } == 0;
+        let mut expected = vec![("World-writable", 20)];
+        if is_root {
+            expected.push(("Read-write", 22));
+        } else {
+            expected.extend([("Read-write", 15), ("Read-only", 7)]);
+        }
+
+        let aggregator =
+            Aggregator::new(GroupBy::Extension).sections(vec![Aggregator::new(GroupBy::Access)]);
+        let result = scan_with(dir.path(), aggregator, |w| w);
+        let access = &result.sections[0];
+        assert_eq!(access.sizes.len(), expected.len(), "{:?}", access.sizes);
+        for (group, size) in expected {
+            assert_eq!(access.sizes[group], size, "{group}");
+        }
+    }
+
+    #[test]
+    fn system_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("System Volume Information")).unwrap();
+        write_file(
+            &dir.path().join("System Volume Information/tracking.log"),
+            100,
+        );
+        write_file(&dir.path().join(".DS_Store"), 20);
+        write_file(&dir.path().join("._photo.jpg"), 4);
+        write_file(&dir.path().join("photo.jpg"), 3);
+
+        let names = [system::MACOS_NAMES, system::WINDOWS_NAMES].concat();
+        let result = scan(dir.path(), |w| w.system_filter(SystemFilter::new(&names)));
+        assert_eq!(result.total, 3);
+        assert_eq!(result.file_count, 1);
+        assert_eq!(scan(dir.path(), |w| w).file_count, 4);
+    }
+
+    #[test]
+    fn sampling_everything_is_exact() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for i in 0..20 {
+            write_file(&dir.path().join(format!("sub/{i}.txt")), 10 + i);
+        }
+
+        let exact = scan(dir.path(), |w| w);
+        let sampled = scan(dir.path(), |w| {
+            w.sample(Some(Sampler { rate: 1.0, seed: 7 }))
+        });
+        assert_eq!(sampled.total, exact.total);
+        assert_eq!(sampled.file_count, 20);
+        assert_eq!(sampled.sizes, exact.sizes);
+
+        let sampler = Sampler { rate: 0.5, seed: 7 };
+        let sampled = scan(dir.path(), |w| w.sample(Some(sampler)));
+        assert!(sampled.file_count < 20);
+        assert_eq!(sampled.sampler, Some(sampler));
+        // Whole tree read again with the same seed gives the same estimate
+        let again = scan(dir.path(), |w| w.sample(Some(sampler)));
+        assert_eq!(sampled.total, again.total);
+    }
+
+    #[test]
+    fn open_directories_are_bounded() {
+        let open_dirs = OpenDirs {
+            open: AtomicUsize::new(0),
+            limit: AtomicUsize::new(2),
+        };
+        let first = open_dirs.try_open();
+        let second = open_dirs.try_open();
+        assert!(first.is_some() && second.is_some());
+        assert!(open_dirs.try_open().is_none());
+        drop(first);
+        let third = open_dirs.try_open();
+        assert!(third.is_some());
+
+        // Two are open, so one is left
+        open_dirs.shrink();
+        drop((second, third));
+        let fourth = open_dirs.try_open();
+        assert!(fourth.is_some());
+        assert!(open_dirs.try_open().is_none());
+    }
+
+    #[test]
+    fn budget_stops_the_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            write_file(&dir.path().join(name), 10);
+        }
+
+        let result = scan(dir.path(), |w| w.max_files(Some(2)));
+        assert_eq!(result.file_count, 2);
+        assert!(result.partial);
+
+        let result = scan(dir.path(), |w| w.max_files(Some(3)));
+        assert_eq!(result.file_count, 3);
+        assert!(!result.partial);
+
+        let result = scan(dir.path(), |w| w.timeout(Some(Duration::ZERO)));
+        assert_eq!(result.file_count, 0);
+        assert!(result.partial);
+        assert!(!result.interrupted);
+    }
+
+    #[test]
+    fn the_walk_stops_once_enough_files_are_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            let sub = dir.path().join(format!("sub{i}"));
+            fs::create_dir(&sub).unwrap();
+            for name in ["a.txt", "b.bin", "c.txt"] {
+                write_file(&sub.join(name), 10);
+            }
+        }
+        let list = |limit| {
+            let aggregator = Aggregator::new(GroupBy::Extension).list_files(Some(FileList {
+                limit: Some(limit),
+                ..Default::default()
+            }));
+            scan_with(dir.path(), aggregator, |w| w)
+        };
+
+        let result = list(3);
+        assert_eq!(result.listed.len(), 3);
+        // Files read after the limit are not counted either
+        assert_eq!(result.file_count, 3);
+        assert!(result.partial);
+        assert!(!result.interrupted);
+
+        let result = list(100);
+        assert_eq!(result.listed.len(), 60);
+        assert!(!result.partial);
+
+        // Reaching the limit with the last file leaves nothing out
+        let result = list(60);
+        assert_eq!(result.listed.len(), 60);
+        assert!(!result.partial);
+
+        // The walk dispatches nothing more once the aggregator is done
+        let done = AtomicBool::new(false);
+        let budget = Budget {
+            done: Some(&done),
+            ..Default::default()
+        };
+        assert!(!budget.is_done());
+        done.store(true, Ordering::Relaxed);
+        assert!(budget.is_done());
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn empty_directories_left_after_the_limit_are_not_missed() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.bin", "c.txt"] {
+            write_file(&dir.path().join(name), 10);
+        }
+        for i in 0..30 {
+            fs::create_dir(dir.path().join(format!("empty{i}"))).unwrap();
+        }
+        for _ in 0..20 {
+            let aggregator = Aggregator::new(GroupBy::Extension).list_files(Some(FileList {
+                limit: Some(3),
+                ..Default::default()
+            }));
+            let result = scan_with(dir.path(), aggregator, |w| w);
+            assert_eq!(result.listed.len(), 3);
+            assert!(!result.partial);
+        }
+
+        // What the walk checks of the entries it skips once the aggregator is done
+        let empty = dir.path().join("empty0");
+        assert!(!leaves_out(&empty, false));
+        assert!(leaves_out(&empty, true));
+        assert!(leaves_out(&dir.path().join("a.txt"), false));
+        assert!(leaves_out(dir.path(), false));
+    }
+
+    #[test]
+    fn an_interrupted_walk_keeps_what_it_found() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        write_file(&dir.path().join("a.txt"), 10);
+        write_file(&dir.path().join("sub/b.txt"), 10);
+
+        let roots = vec![dir.path().to_path_buf()];
+        let cancel = |token: &AtomicBool| {
+            let aggregator = Aggregator::new(GroupBy::Extension);
+            let walk = Walk::new(&roots, 2, FilesizeType::ApparentSize, aggregator);
+            walk.run_with_cancel(token).unwrap()
+        };
+        let result = cancel(&AtomicBool::new(true));
+        assert_eq!(result.file_count, 0);
+        assert!(result.partial);
+        assert!(result.interrupted);
+
+        let result = cancel(&AtomicBool::new(false));
+        assert_eq!(result.file_count, 2);
+        assert!(!result.partial);
+        assert!(!result.interrupted);
+    }
+
+    #[test]
+    fn counting_first_does_not_change_the_result() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        write_file(&dir.path().join("a.txt"), 100);
+        write_file(&dir.path().join("sub/b.txt"), 50);
+
+        let result = scan(dir.path(), |w| w.accurate_progress(true));
+        assert_eq!(result.total, 150);
+        assert_eq!(result.file_count, 2);
+    }
+
+    #[test]
+    fn without_hardlinks_nothing_is_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), 100);
+        write_file(&dir.path().join("b.txt"), 50);
+
+        let result = scan(dir.path(), |w| w);
+        assert_eq!(result.total, 150);
+        assert_eq!(result.file_count, 2);
+        assert_eq!(result.deduplicated, 0);
+    }
+
+    #[test]
+    fn hardlinks_are_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), 100);
+        fs::hard_link(dir.path().join("a.txt"), dir.path().join("b.txt")).unwrap();
+        fs::hard_link(dir.path().join("a.txt"), dir.path().join("c.txt")).unwrap();
+
+        let result = scan(dir.path(), |w| w);
+        assert_eq!(result.total, 100);
+        assert_eq!(result.file_count, 1);
+        assert_eq!(result.deduplicated, 2);
+
+        let result = scan_with(
+            dir.path(),
+            Aggregator::new(GroupBy::Extension).dedup(false),
+            |w| w,
+        );
+        assert_eq!(result.total, 300);
+        assert_eq!(result.file_count, 3);
+        assert_eq!(result.deduplicated, 0);
+    }
+
+    #[test]
+    fn overlapping_inputs_are_deduplicated_when_forced() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        write_file(&dir.path().join("a.txt"), 10);
+        write_file(&dir.path().join("sub/b.txt"), 100);
+        let roots = vec![dir.path().to_path_buf(), dir.path().join("sub")];
+        let scan = |aggregator: Aggregator, force_dedup| {
+            let walk = Walk::new(&roots, 2, FilesizeType::ApparentSize, aggregator);
+            walk.force_dedup(force_dedup).run().unwrap()
+        };
+
+        // With a single link each, the files are not tracked by default
+        let result = scan(Aggregator::new(GroupBy::Extension), false);
+        assert_eq!(result.total, 210);
+        assert_eq!(result.deduplicated, 0);
+
+        let result = scan(Aggregator::new(GroupBy::Extension), true);
+        assert_eq!(result.total, 110);
+        assert_eq!(result.file_count, 2);
+        assert_eq!(result.deduplicated, 1);
+
+        let result = scan(Aggregator::new(GroupBy::Extension).dedup(false), true);
+        assert_eq!(result.total, 210);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlinks_across_roots_are_counted_in_one_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        write_file(&first.join("shared.bin"), 1000);
+        fs::hard_link(first.join("shared.bin"), second.join("shared.bin")).unwrap();
+        write_file(&first.join("a.txt"), 10);
+        write_file(&second.join("b.txt"), 100);
+        let roots = vec![first.clone(), second.clone()];
+
+        for _ in 0..10 {
+            let aggregator = Aggregator::new(GroupBy::Extension).per_root(true);
+            let walk = Walk::new(&roots, 4, FilesizeType::ApparentSize, aggregator);
+            let result = walk.run().unwrap();
+            assert_eq!(result.total, 1110);
+            assert_eq!(result.deduplicated, 1);
+            let (roots_seen, sizes): (Vec<PathBuf>, Vec<u64>) =
+                result.root_sizes.into_iter().unzip();
+            assert_eq!(roots_seen, roots);
+            // In the first root, whichever thread found the shared file first
+            assert_eq!(sizes, [1010, 100]);
+        }
+    }
+
+    #[test]
+    fn error_kinds_are_classified() {
+        let kind = |err: io::Error| ErrorKind::from(&err);
+        assert_eq!(
+            kind(io::ErrorKind::PermissionDenied.into()),
+            ErrorKind::PermissionDenied
+        );
+        assert_eq!(kind(io::ErrorKind::NotFound.into()), ErrorKind::NotFound);
+        assert_eq!(kind(io::ErrorKind::InvalidData.into()), ErrorKind::Other);
+        #[cfg(unix)]
+        assert_eq!(
+            kind(io::Error::from_raw_os_error(libc::EMFILE)),
+            ErrorKind::TooManyOpenFiles
+        );
+    }
+
+    #[test]
+    fn missing_root_is_an_error_not_a_vanished_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = scan(&dir.path().join("missing"), |w| w);
+        assert_eq!(result.vanished, 0);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].kind(), ErrorKind::NotFound);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn errors_include_the_os_message() {
+        let err = Error::CouldNotReadDir(
+            PathBuf::from("/root/secret"),
+            io::Error::from_raw_os_error(libc::EACCES),
+        );
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(
+            err.to_string(),
+            "could not read contents of directory '/root/secret': \
+             Permission denied (os error 13)"
+        );
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn replaced_directories_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        fs::create_dir(&path).unwrap();
+        let before = path.symlink_metadata().unwrap();
+        assert!(is_same_dir(&path, &before));
+
+        fs::remove_dir(&path).unwrap();
+        write_file(&path, 10);
+        assert!(!is_same_dir(&path, &before));
+
+        // read_dir follows a link that replaced the directory, the check must not
+        #[cfg(unix)]
+        {
+            let elsewhere = dir.path().join("elsewhere");
+            fs::create_dir(&elsewhere).unwrap();
+            fs::remove_file(&path).unwrap();
+            std::os::unix::fs::symlink(&elsewhere, &path).unwrap();
+            let before = elsewhere.symlink_metadata().unwrap();
+            assert!(!is_same_dir(&path, &before));
+        }
+
+        let err = Error::ChangedDuringScan(path);
+        assert_eq!(err.kind(), ErrorKind::Changed);
+        assert!(std::error::Error::source(&err).is_none());
+    }
+
+    #[test]
+    fn excluded_directories_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::create_dir_all(root.join("proc/self")).unwrap();
+        fs::create_dir_all(root.join("net")).unwrap();
+        write_file(&root.join("data/a.txt"), 100);
+        write_file(&root.join("proc/self/status"), 10);
+        write_file(&root.join("net/share.bin"), 1000);
+
+        let result = scan(&root, |w| w.exclude(vec![root.join("proc")]));
+        assert_eq!(result.total, 1100);
+
+        // Exclusions stack, and are matched whatever way they are spelled
+        let result = scan(&root, |w| {
+            w.exclude(vec![root.join("proc"), root.join("data/../net")])
+        });
+        assert_eq!(result.total, 100);
+
+        // Paths outside the roots or missing are ignored
+        let result = scan(&root, |w| {
+            w.exclude(vec![dir.path().to_path_buf(), root.join("missing")])
+        });
+        assert_eq!(result.total, 1110);
+
+        let result = scan(&root, |w| w.exclude(vec![root.clone()]));
+        assert_eq!(result.total, 0);
+    }
+
+    #[test]
+    fn files_are_grouped_by_age() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir().unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        for (name, len, age) in [
+            ("new.txt", 1, day),
+            ("week.txt", 10, 10 * day),
+            ("month.txt", 100, 90 * day),
+            ("old.txt", 1000, 3 * 365 * day),
+            ("future.txt", 10000, Duration::ZERO),
+        ] {
+            let path = dir.path().join(name);
+            write_file(&path, len);
+            let mtime = if name == "future.txt" {
+                SystemTime::now() + 30 * day
+            } else {
+                SystemTime::now() - age
+            };
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+
+        let result = scan_with(dir.path(), Aggregator::new(GroupBy::AgeBucket), |w| w);
+        let mut sizes: Vec<_> = result.sizes.into_iter().collect();
+        sizes.sort();
+        assert_eq!(
+            sizes,
+            vec![
+                ("Last 30 days".to_string(), 10),
+                ("Last 7 days".to_string(), 10001),
+                ("Last year".to_string(), 100),
+                ("Older".to_string(), 1000),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_followed_only_on_request() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        write_file(&root.join("sub/a.txt"), 100);
+        write_file(&outside.join("b.txt"), 1000);
+        symlink(&outside, root.join("to_outside")).unwrap();
+        symlink(root.join("sub"), root.join("to_sub")).unwrap();
+        symlink(&root, root.join("sub/to_root")).unwrap();
+        symlink(dir.path().join("missing"), root.join("dangling")).unwrap();
+
+        let result = scan(&root, |w| w);
+        // a.txt and the 4 links themselves
+        assert_eq!(result.file_count, 5);
+        assert!(result.total < 1000);
+
+        // The target outside is counted, links inside the root and the cycle are not
+        let result = scan(&root, |w| w.follow_links(true));
+        assert_eq!(result.file_count, 3);
+        assert!(result.total > 1100 && result.total < 2000);
+        assert!(result.errors.is_empty());
+
+        // A root that is a link is followed
+        let result = scan(&root.join("to_outside"), |w| w.follow_links(true));
+        assert_eq!(result.total, 1000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_directories_are_not_descended_by_default() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir(&root).unwrap();
+        fs::create_dir(&outside).unwrap();
+        write_file(&outside.join("b.txt"), 1000);
+        write_file(&outside.join("c.txt"), 1000);
+        symlink(&outside, root.join("to_outside")).unwrap();
+        let link_len = root.join("to_outside").symlink_metadata().unwrap().len();
+
+        for accurate_progress in [false, true] {
+            let result = scan(&root, |w| w.accurate_progress(accurate_progress));
+            assert_eq!(result.file_count, 1);
+            assert_eq!(result.total, link_len);
+            assert_eq!(result.sizes.len(), 1);
+        }
 
-        Ok(receiver_thread.join().unwrap())
+        // Not even a root that is a link
+        let result = scan(&root.join("to_outside"), |w| w);
+        assert_eq!(result.file_count, 1);
+        assert_eq!(result.total, link_len);
     }
 }
@@ -0,0 +1,1180 @@
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command as StdCommand, Stdio};
+
+use assert_cmd::Command;
+use assert_cmd::cargo::CommandCargoExt;
+use predicates::prelude::*;
+
+fn fss() -> Command {
+    Command::from_std(fss_std())
+}
+
+/// fss, away from the `FSS_*` variables and the config file of whoever runs the tests
+fn fss_std() -> StdCommand {
+    let mut cmd = StdCommand::cargo_bin("fss").unwrap();
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("FSS_") {
+            cmd.env_remove(key);
+        }
+    }
+    // The config directory is looked up there first, and has no config file
+    let home = std::env::temp_dir().join("fss-tests-home");
+    cmd.env("XDG_CONFIG_HOME", &home).env("HOME", &home);
+    cmd
+}
+
+fn write_file(path: &Path, len: usize) {
+    std::fs::write(path, vec![b'x'; len]).unwrap();
+}
+
+/// Create a directory nested so deep that its path exceeds PATH_MAX, which makes reading it fail
+/// even when running as root
+#[cfg(unix)]
+fn unreadable_tree(root: &Path) {
+    // The shell stops being able to cd once the path gets too long, but by then the directory
+    // it failed to enter has been created
+    let component = "d".repeat(250);
+    let script =
+        format!("for i in $(seq 20); do mkdir {component} && cd {component} || break; done");
+    StdCommand::new("sh")
+        .arg("-c")
+        .arg(script)
+        .current_dir(root)
+        .output()
+        .unwrap();
+}
+
+#[test]
+fn success_exits_with_zero() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+
+    fss().arg(dir.path()).assert().code(0);
+    fss().arg("--strict").arg(dir.path()).assert().code(0);
+}
+
+#[test]
+fn invalid_arguments_exit_with_one() {
+    fss()
+        .args(["--group-by", "nonsense"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("nonsense"));
+    fss().arg("--no-such-flag").assert().code(1);
+}
+
+#[test]
+fn help_and_version_exit_with_zero() {
+    fss()
+        .arg("--help")
+        .assert()
+        .code(0)
+        .stdout(predicate::str::contains("Exit status:"));
+    fss().arg("--version").assert().code(0);
+}
+
+#[test]
+fn no_readable_input_exits_with_one() {
+    let dir = tempfile::tempdir().unwrap();
+    fss().arg(dir.path().join("missing")).assert().code(1);
+}
+
+#[test]
+fn missing_inputs_fail_before_scanning() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    let missing = dir.path().join("tpyo");
+
+    fss()
+        .arg(dir.path())
+        .arg(&missing)
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("tpyo' does not exist"));
+}
+
+#[test]
+fn ignore_missing_skips_absent_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    let missing = dir.path().join("tpyo");
+
+    fss()
+        .args([
+            "--ignore-missing",
+            "--apparent-size",
+            "--compact",
+            "-s",
+            "bytes",
+        ])
+        .arg(dir.path())
+        .arg(&missing)
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("10 (1 files)"));
+    fss()
+        .args(["--ignore-missing", "--compact", "-s", "bytes"])
+        .arg(&missing)
+        .assert()
+        .code(0)
+        .stdout("0 (0 files)\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn strict_exits_with_two_on_partial_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    unreadable_tree(dir.path());
+
+    fss()
+        .args(["-s", "bytes"])
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stderr(predicate::str::contains("may be tainted"));
+    fss()
+        .args(["--strict", "-s", "bytes"])
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("txt"));
+    fss()
+        .args(["--strict", "-q", "-s", "bytes"])
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("txt"))
+        .stderr("");
+    fss()
+        .args(["-q", "-s", "bytes"])
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stderr("");
+    fss()
+        .args(["-q", "-v"])
+        .arg(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[cfg(unix)]
+#[test]
+fn errors_are_appended_to_the_error_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data");
+    std::fs::create_dir(&data).unwrap();
+    unreadable_tree(&data);
+    let log = dir.path().join("errors.log");
+
+    for _ in 0..2 {
+        fss()
+            .args(["--strict", "--error-log"])
+            .arg(&log)
+            .arg(&data)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("tainted by 1 errors"));
+    }
+    let contents = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(contents.lines().count(), 2);
+    assert!(contents.lines().all(|line| line.split('\t').count() == 3));
+
+    fss()
+        .arg("--error-log")
+        .arg(dir.path().join("missing/errors.log"))
+        .arg(&data)
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("could not open error log"));
+}
+
+#[test]
+fn fail_on_empty_exits_with_four_on_an_empty_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("empty")).unwrap();
+
+    fss().arg(dir.path()).assert().code(0);
+    fss()
+        .args(["--fail-on-empty", "-c"])
+        .arg(dir.path())
+        .assert()
+        .code(4)
+        .stdout("0 B (0 files)\n");
+
+    write_file(&dir.path().join("empty/a.txt"), 10);
+    fss()
+        .args(["--fail-on-empty", "--apparent-size"])
+        .arg(dir.path())
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn filters_matching_nothing_exit_with_five() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    write_file(&dir.path().join("b.jpg"), 2000);
+
+    fss()
+        .args(["--ext", "txt"])
+        .arg(dir.path())
+        .assert()
+        .code(0);
+    fss().args(["-S", "+1K"]).arg(dir.path()).assert().code(0);
+    fss()
+        .args(["--ext", "png"])
+        .arg(dir.path())
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "no files matched the given filters",
+        ));
+    fss()
+        .args(["--exclude-ext", "txt,jpg"])
+        .arg(dir.path())
+        .assert()
+        .code(5);
+    // The groups are filtered, neither of them is over 1M
+    fss()
+        .args(["-S", "+1M", "-q"])
+        .arg(dir.path())
+        .assert()
+        .code(5)
+        .stderr("");
+    fss()
+        .args(["--list-files", "-S", "+1M"])
+        .arg(dir.path())
+        .assert()
+        .code(5);
+
+    // Errors come first
+    #[cfg(unix)]
+    {
+        unreadable_tree(dir.path());
+        fss()
+            .args(["--strict", "--ext", "png"])
+            .arg(dir.path())
+            .assert()
+            .code(2);
+    }
+
+    // Without filters, an empty scan is a success
+    let empty = tempfile::tempdir().unwrap();
+    fss().arg(empty.path()).assert().code(0);
+}
+
+#[test]
+fn colors_follow_the_color_flag_and_the_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    let output = |color: &str, env: &[(&str, &str)]| {
+        let mut cmd = fss();
+        cmd.env_remove("NO_COLOR").env_remove("CLICOLOR_FORCE");
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+        let output = cmd
+            .args(["--stats", "--color", color])
+            .arg(dir.path())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let has_ansi = |out: &str| out.contains('\x1b');
+
+    // Not a terminal
+    assert!(!has_ansi(&output("auto", &[])));
+    assert!(has_ansi(&output("auto", &[("CLICOLOR_FORCE", "1")])));
+    assert!(has_ansi(&output("always", &[("NO_COLOR", "1")])));
+    assert!(!has_ansi(&output("never", &[("CLICOLOR_FORCE", "1")])));
+    assert!(!has_ansi(&output("never", &[])));
+
+    // Group names are colored by type
+    let colored = output("always", &[("FSS_COLORS", "document=yellow")]);
+    assert!(colored.contains("\x1b[33mtxt\x1b[0m"), "{colored:?}");
+}
+
+#[test]
+fn groups_past_the_thresholds_are_highlighted() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    write_file(&dir.path().join("b.bin"), 20);
+    write_file(&dir.path().join("c.md"), 2);
+    let output = |color: &str| {
+        let output = fss()
+            .env("FSS_COLORS", "document=none")
+            .args(["-b", "-s", "bytes", "--highlight", "15b", "--warn", "5b"])
+            .args(["--color", color])
+            .arg(dir.path())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(
+        output("always").lines().take(3).collect::<Vec<_>>(),
+        [
+            " 2\tmd",
+            "\x1b[33m10\x1b[0m\t\x1b[33mtxt\x1b[0m",
+            "\x1b[1;31m20\x1b[0m\t\x1b[1;31mbin\x1b[0m",
+        ]
+    );
+    assert!(!output("never").contains('\x1b'));
+}
+
+#[test]
+fn the_summary_can_go_to_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    write_file(&dir.path().join("b.bin"), 20);
+
+    fss()
+        .args(["-b", "-s", "bytes", "--summary-to-stderr", "--stats"])
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stdout("10\ttxt\n20\tbin\n")
+        .stderr(
+            predicate::str::starts_with("\nTotal: \n30\n").and(predicate::str::contains("files")),
+        );
+}
+
+#[test]
+fn stopped_scans_exit_with_three() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    write_file(&dir.path().join("b.txt"), 10);
+
+    fss()
+        .args(["--max-files", "1", "-s", "bytes"])
+        .arg(dir.path())
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("Total (partial)"))
+        .stderr(predicate::str::contains("the results are partial"));
+    fss()
+        .args(["--max-files", "2", "--timeout", "1h"])
+        .arg(dir.path())
+        .assert()
+        .code(0);
+    fss()
+        .args(["--timeout", "10 minutes"])
+        .arg(dir.path())
+        .assert()
+        .code(1);
+
+    let output = fss()
+        .args(["--list-files", "--limit", "1", "-s", "bytes"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches(".txt").count(), 1, "{stdout}");
+    fss()
+        .args(["--list-files", "--limit", "3"])
+        .arg(dir.path())
+        .assert()
+        .code(0);
+    fss()
+        .args(["--limit", "1"])
+        .arg(dir.path())
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn git_tracked_counts_only_tracked_files() {
+    let git = |dir: &Path, args: &[&str]| {
+        StdCommand::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .is_ok_and(|output| output.status.success())
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    if !git(&repo, &["init", "-q"]) {
+        eprintln!("skipped, git is not available");
+        return;
+    }
+    write_file(&repo.join("a.txt"), 10);
+    std::fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    assert!(git(&repo, &["add", "."]));
+    std::fs::create_dir(repo.join("target")).unwrap();
+    write_file(&repo.join("target/app"), 1000);
+    write_file(&repo.join("draft.txt"), 100);
+
+    fss()
+        .args(["--git-tracked", "-b", "-c", "-s", "bytes"])
+        .arg(&repo)
+        .assert()
+        .code(0)
+        .stdout("18 (2 files)\n");
+    fss()
+        .arg("--git-tracked")
+        .arg(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("is not inside a git repository"));
+}
+
+/// The total of `du -s <ARGS> <PATH>`, `None` where du lacks the arguments (e.g. not GNU du)
+#[cfg(unix)]
+fn du_total(args: &[&str], path: &Path) -> Option<String> {
+    let output = StdCommand::new("du")
+        .arg("-s")
+        .args(args)
+        .arg(path)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let total = stdout.split('\t').next()?;
+    output.status.success().then(|| total.to_string())
+}
+
+#[cfg(unix)]
+#[test]
+fn block_counts_match_du() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    write_file(&dir.path().join("a.txt"), 5000);
+    write_file(&dir.path().join("sub/b.bin"), 1);
+    write_file(&dir.path().join("sub/c.bin"), 3 << 20);
+    std::fs::hard_link(dir.path().join("a.txt"), dir.path().join("sub/a.txt")).unwrap();
+
+    for (du_args, block_size) in [
+        (["--apparent-size", "-k"], "1Ki"),
+        (["--apparent-size", "-m"], "1Mi"),
+    ] {
+        let Some(expected) = du_total(&du_args, dir.path()) else {
+            eprintln!("skipped, du does not support {du_args:?}");
+            return;
+        };
+        fss()
+            .args([
+                "--du-compat",
+                "-c",
+                "-s",
+                "blocks",
+                "--output-block-size",
+                block_size,
+            ])
+            .arg(dir.path())
+            .assert()
+            .code(0)
+            .stdout(predicate::str::starts_with(format!("{expected} (3 files)")));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn inode_counts_match_du() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+    write_file(&dir.path().join("a.txt"), 5000);
+    write_file(&dir.path().join("sub/b.bin"), 1);
+    write_file(&dir.path().join("sub/d.bin"), 1);
+    write_file(&dir.path().join("sub/deeper/c.bin"), 10);
+    std::fs::hard_link(dir.path().join("a.txt"), dir.path().join("b.txt")).unwrap();
+
+    // 4 files and 3 directories
+    let expected = du_total(&["--inodes"], dir.path()).unwrap_or_else(|| "7".to_string());
+    assert_eq!(expected, "7");
+    fss()
+        .args(["--inodes", "-c"])
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stdout(format!("{expected} (4 files)\n"));
+
+    // Every directory is counted in its own group
+    let output = fss()
+        .args(["--inodes", "-g", "d"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let root = dir.path().file_name().unwrap().to_str().unwrap();
+    let mut groups: Vec<&str> = stdout.lines().take(3).collect();
+    groups.sort();
+    assert_eq!(
+        groups,
+        [
+            format!("2\t{root}"),
+            "2\tdeeper".to_string(),
+            "3\tsub".to_string()
+        ],
+        "{stdout}"
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn paths_longer_than_max_path_are_counted() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut deep = std::path::PathBuf::from(format!(r"\\?\{}", dir.path().display()));
+    for _ in 0..10 {
+        deep.push("d".repeat(40));
+    }
+    std::fs::create_dir_all(&deep).unwrap();
+    assert!(deep.as_os_str().len() > 260);
+    write_file(&deep.join("deep.txt"), 10);
+
+    fss()
+        .args(["--compact", "-s", "bytes", "-v"])
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stdout(predicate::str::ends_with("(1 files)\n"))
+        .stderr("");
+}
+
+/// Run fss with a hard limit of `max_fds` file descriptors, which it can't raise
+#[cfg(unix)]
+fn fss_with_fd_limit(max_fds: u32, args: &[&str]) -> std::process::Output {
+    let bin = assert_cmd::cargo::cargo_bin("fss");
+    StdCommand::new("sh")
+        .arg("-c")
+        .arg(format!("ulimit -n {max_fds} && exec \"$0\" \"$@\""))
+        .arg(bin)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn scans_recover_from_running_out_of_file_descriptors() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..40 {
+        let mut sub = dir.path().join(format!("d{i}"));
+        for depth in 0..8 {
+            sub.push(format!("l{depth}"));
+            std::fs::create_dir_all(&sub).unwrap();
+            write_file(&sub.join("f.txt"), 10);
+        }
+    }
+    let root = dir.path().to_str().unwrap();
+
+    let output = fss_with_fd_limit(8, &["-b", "-j", "64", "-c", "-s", "bytes", root]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "3200 (320 files)\n",
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn defaults_are_read_from_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data");
+    std::fs::create_dir(&data).unwrap();
+    write_file(&data.join("a.txt"), 2000);
+    let config = dir.path().join("fss.toml");
+    std::fs::write(
+        &config,
+        "size_format = \"bytes\"\ncompact = true\napparent_size = true\n",
+    )
+    .unwrap();
+
+    fss()
+        .arg("--config")
+        .arg(&config)
+        .arg(&data)
+        .assert()
+        .code(0)
+        .stdout("2000 (1 files)\n");
+    fss()
+        .arg("--config")
+        .arg(&config)
+        .args(["-s", "binary"])
+        .arg(&data)
+        .assert()
+        .code(0)
+        .stdout("1.95 KiB (1 files)\n");
+    fss()
+        .args(["--no-config", "--apparent-size", "-c"])
+        .env("FSS_SIZE_FORMAT", "bytes")
+        .arg(&data)
+        .assert()
+        .code(0)
+        .stdout("2 kB (1 files)\n");
+
+    std::fs::write(&config, "compact = true\nthreds = 8\n").unwrap();
+    fss()
+        .arg("--config")
+        .arg(&config)
+        .arg(&data)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "fss.toml:2: unknown option 'threds'",
+        ));
+    fss()
+        .arg("--config")
+        .arg(dir.path().join("missing.toml"))
+        .arg(&data)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("could not read config file"));
+
+    // The project file of the current directory wins over the given one
+    std::fs::write(&config, "size_format = \"bytes\"\ncompact = true\n").unwrap();
+    std::fs::write(dir.path().join(".fss.toml"), "size_format = \"binary\"\n").unwrap();
+    fss()
+        .current_dir(&data)
+        .arg("--config")
+        .arg(&config)
+        .args(["--apparent-size", "."])
+        .assert()
+        .code(0)
+        .stdout("1.95 KiB (1 files)\n");
+    fss()
+        .current_dir(&data)
+        .args(["--no-config", "--apparent-size", "-c", "."])
+        .assert()
+        .code(0)
+        .stdout("2 kB (1 files)\n");
+}
+
+#[test]
+fn inputs_can_be_read_from_a_list() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("movies")).unwrap();
+    write_file(&dir.path().join("movies/a.mkv"), 1000);
+    write_file(&dir.path().join("b.mkv"), 200);
+    write_file(&dir.path().join("c.txt"), 30);
+
+    let list = format!(
+        "{}\0{}\0",
+        dir.path().join("movies").display(),
+        dir.path().join("b.mkv").display()
+    );
+    fss()
+        .args(["--files0-from", "-", "--apparent-size", "-c", "-s", "bytes"])
+        .write_stdin(list.clone())
+        .assert()
+        .code(0)
+        .stdout("1200 (2 files)\n");
+    let list_file = dir.path().join("list.txt");
+    std::fs::write(&list_file, list.replace('\0', "\n")).unwrap();
+    fss()
+        .args(["--apparent-size", "-c", "-s", "bytes", "--files-from"])
+        .arg(&list_file)
+        .assert()
+        .code(0)
+        .stdout("1200 (2 files)\n");
+
+    fss()
+        .args(["--files0-from", "-"])
+        .write_stdin("")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "the input list from stdin has no paths",
+        ));
+    fss()
+        .args(["--files-from", "-", "-c"])
+        .write_stdin("missing\n")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("input 'missing' does not exist"));
+    fss()
+        .args(["--files-from", "-", "."])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn the_largest_files_are_listed_once() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("big.iso"), 5000);
+    std::fs::hard_link(dir.path().join("big.iso"), dir.path().join("link.iso")).unwrap();
+    write_file(&dir.path().join("small.txt"), 10);
+    write_file(&dir.path().join("tiny.txt"), 1);
+
+    let output = fss()
+        .args(["--largest", "2", "--apparent-size", "-s", "bytes"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (_groups, largest) = stdout.split_once("Largest files:\n").unwrap();
+    // The note about the deduplicated hardlink follows
+    let lines: Vec<&str> = largest
+        .lines()
+        .take_while(|line| !line.starts_with('('))
+        .collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    assert!(lines[0].starts_with("5000\t"), "{stdout}");
+    assert!(lines[1].starts_with("  10\t"), "{stdout}");
+    assert!(lines[1].ends_with("small.txt"), "{stdout}");
+}
+
+#[test]
+fn scan_is_the_default_command() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("docs")).unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+    write_file(&dir.path().join("b.jpeg"), 2000);
+    write_file(&dir.path().join("docs/c.jpg"), 2000);
+    write_file(&dir.path().join("docs/.bashrc"), 300);
+
+    let flags: &[&[&str]] = &[
+        &[],
+        &["-g", "t"],
+        &["-g", "type2"],
+        &["-g", "f"],
+        &["-g", "d"],
+        &["-g", "p", "--by-depth"],
+        &["--dotfile-as-ext", "--normalize-ext", "--escape-nonutf8"],
+        &["-S", "+100B"],
+        &["--list-files", "--top", "2"],
+        &["--explain"],
+        &["--duplicates"],
+        &["--largest", "2"],
+        &["--split-outliers", "1K", "--max-groups", "2"],
+        &["--flat-depth", "1", "-g", "d"],
+        &["--ext", "jpg,jpeg", "--exclude-ext", "jpeg"],
+        &["-s", "binary", "--precision", "2"],
+        &["--unit", "kib", "--unit-suffix"],
+        &["--show-bytes", "--group-digits"],
+        &["--output-block-size", "1K", "--block-size", "4k"],
+        &["-L", "-b", "-j", "1"],
+        &["-c"],
+        &["--top-by", "count:2"],
+        &["--highlight", "1K", "--warn", "3K"],
+        &["--chart", "--filtered-total"],
+        &["--color", "never", "-o", "table", "--separator", ","],
+        &["--both-sizes"],
+        &[
+            "--regular-only",
+            "--skip-system",
+            "--count-system",
+            "--no-dedup",
+        ],
+        &["--du-compat", "--strict", "--fail-on-empty"],
+        // Enough files not to stop, which files a stopped scan counts depends on the threads
+        &["--max-files", "100", "--timeout", "60"],
+        &["--sample", "0.5", "--seed", "7"],
+        &["-q", "--errors", "json"],
+        &["--stats", "--timings"],
+        &["--no-config"],
+    ];
+    for flags in flags {
+        let output = |command: &[&str]| {
+            let output = fss()
+                .args(command)
+                .args(*flags)
+                .arg(dir.path())
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{flags:?}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            output.stdout
+        };
+        let stdout = output(&[]);
+        assert!(!stdout.is_empty(), "{flags:?}");
+        assert_eq!(stdout, output(&["scan"]), "{flags:?}");
+    }
+}
+
+#[test]
+fn types_lists_the_known_extensions() {
+    let output = fss().arg("types").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("jpg\tImage\tImage\n"), "{stdout}");
+    assert!(stdout.contains("pdf\tDocument\tOffice\n"), "{stdout}");
+    assert!(stdout.lines().all(|line| line.split('\t').count() == 3));
+
+    fss().args(["types", "src"]).assert().code(1);
+}
+
+#[test]
+fn diff_compares_two_saved_scans() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.tsv");
+    let new = dir.path().join("new.tsv");
+    std::fs::write(&old, "100\ttxt\n50\tmd\n").unwrap();
+    std::fs::write(&new, "300\ttxt\n7\tjpg\n").unwrap();
+
+    fss()
+        .args(["-s", "bytes", "diff"])
+        .arg(&old)
+        .arg(&new)
+        .assert()
+        .code(0)
+        .stdout(
+            "  0\tremoved\tmd\n  7\t    new\tjpg\n300\t↑ +200%\ttxt\n\nTotal: \n307\t↑ +105%\n",
+        );
+    fss()
+        .arg("diff")
+        .arg(&old)
+        .arg(dir.path().join("missing.tsv"))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("could not open saved scan"));
+}
+
+#[test]
+fn several_groupings_are_printed_in_sections() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.jpg"), 1000);
+    write_file(&dir.path().join("b.png"), 500);
+    write_file(&dir.path().join("c.rs"), 10);
+    #[cfg(unix)]
+    std::fs::hard_link(dir.path().join("a.jpg"), dir.path().join("d.jpg")).unwrap();
+
+    let output = fss()
+        .args(["-g", "e", "-g", "t", "-b", "-s", "bytes"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (by_ext, by_type) = stdout.split_once("\n\nBy type:\n").unwrap();
+    assert!(by_ext.starts_with("By extension:\n"), "{stdout}");
+    assert!(by_ext.contains("1000\tjpg\n") && by_ext.ends_with("\nTotal: \n1510"));
+    assert!(by_type.starts_with("  10\tCode\n1500\tImage\n"), "{stdout}");
+    assert!(by_type.contains("\nTotal: \n1510\n"), "{stdout}");
+
+    fss()
+        .args(["-g", "e", "-g", "t", "--largest", "1"])
+        .arg(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("with --largest"));
+}
+
+#[test]
+fn bench_mode_prints_a_single_line() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.jpg"), 1000);
+    write_file(&dir.path().join("b.png"), 500);
+
+    let output = fss()
+        .args(["--bench-mode", "--no-config", "-b"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("2 files\t1500 bytes\t0 errors\t"),
+        "{stdout}"
+    );
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+}
+
+#[test]
+fn files_can_be_counted_instead_of_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.jpg"), 5000);
+    for name in ["b.txt", "c.txt", "d.txt"] {
+        write_file(&dir.path().join(name), 10);
+    }
+
+    let output = fss()
+        .args(["--count-mode", "files", "-g", "e"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("1\tjpg\n3\ttxt\n"), "{stdout}");
+    assert!(stdout.contains("Total: \n4\n"), "{stdout}");
+
+    // -S filters the groups by their number of files
+    fss()
+        .args(["--count-mode", "files", "-g", "e", "-S", "+2b"])
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("txt").and(predicate::str::contains("jpg").not()));
+
+    fss()
+        .args(["--count-mode", "files", "--list-files"])
+        .arg(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("with --list-files"));
+}
+
+#[test]
+fn snapshots_can_be_compared_with() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    write_file(&tree.join("a.txt"), 100);
+    write_file(&tree.join("b.txt"), 100);
+    let snapshot = dir.path().join("scan.snapshot");
+
+    fss()
+        .args(["-b", "-s", "bytes", "--save-snapshot"])
+        .arg(&snapshot)
+        .arg(&tree)
+        .assert()
+        .code(0);
+    let saved = std::fs::read_to_string(&snapshot).unwrap();
+    assert!(saved.starts_with("fss-snapshot\t2\n"), "{saved}");
+    assert!(saved.contains("\ngroup_by\textension\n"), "{saved}");
+    assert!(saved.contains("\ngroup\t200\t2\ttxt\n"), "{saved}");
+
+    write_file(&tree.join("c.txt"), 200);
+    fss()
+        .args(["-b", "-s", "bytes", "--compare"])
+        .arg(&snapshot)
+        .arg(&tree)
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("400\t↑ +100%\ttxt\n"));
+    fss()
+        .args(["-g", "type", "--compare"])
+        .arg(&snapshot)
+        .arg(&tree)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "the snapshot groups the files by extension, not by type",
+        ));
+    // Disk usage can't be compared with apparent sizes
+    fss()
+        .args(["-s", "bytes", "--compare"])
+        .arg(&snapshot)
+        .arg(&tree)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "the snapshot has size_type apparent, not disk",
+        ));
+}
+
+#[test]
+fn sizes_can_be_piped_in() {
+    let sizes = "100\tphotos/a.jpg\n250\tphotos/b.JPG\n40\tnotes/todo.txt\n\n5\tsrc/main.rs\n";
+    fss()
+        .args(["--stdin-sizes", "-g", "type", "-s", "bytes", "--no-config"])
+        .write_stdin(sizes)
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with(
+            "  5\tCode\n 40\tDocument\n350\tImage\n\nTotal: \n395\n",
+        ));
+
+    fss()
+        .args(["--stdin-sizes", "--no-config"])
+        .write_stdin("100\ta.jpg\nmany\tb.jpg\n")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "line 2: expected '<SIZE>\\t<PATH>', got 'many\tb.jpg'",
+        ));
+
+    fss()
+        .args(["--stdin-sizes", "."])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn piped_lines_arrive_before_fss_exits() {
+    let dir = tempfile::tempdir().unwrap();
+    // Far more groups than a pipe holds, so fss can only finish once they are read
+    const FILES: usize = 10_000;
+    for i in 0..FILES {
+        write_file(
+            &dir.path().join(format!("a-rather-long-file-name-{i:05}")),
+            1,
+        );
+    }
+
+    let mut child = fss_std()
+        .args(["-g", "f", "-s", "bytes"])
+        .arg(dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let first = lines.next().unwrap().unwrap();
+    assert!(first.contains("a-rather-long-file-name-"), "{first}");
+    assert!(child.try_wait().unwrap().is_none(), "fss exited first");
+
+    let groups = lines
+        .map(Result::unwrap)
+        .filter(|line| line.contains("a-rather-long-file-name-"))
+        .count();
+    assert_eq!(groups + 1, FILES);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn only_groups_are_laid_out_as_a_table() {
+    let dir = tempfile::tempdir().unwrap();
+    write_file(&dir.path().join("a.txt"), 10);
+
+    for flag in ["--list-files", "--explain", "--duplicates", "--compact"] {
+        fss()
+            .args(["-o", "table", flag])
+            .arg(dir.path())
+            .assert()
+            .code(1)
+            .stderr(format!("fss: -o table can't be combined with {flag}\n"));
+    }
+    fss()
+        .args(["-o", "plain", "--list-files"])
+        .arg(dir.path())
+        .assert()
+        .success();
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_while_reading_stdin_exits_with_130() {
+    use std::io::Write;
+
+    let mut child = fss_std()
+        .args(["--stdin-sizes", "-s", "bytes"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"100\ta.txt\n").unwrap();
+    // Time to install the handler, a Ctrl-C before it would kill fss
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    StdCommand::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    // The handler sets the flag from a thread of its own, give it time before the input ends
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    // The producer dies of the same Ctrl-C, which fss sees as the end of its input
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("interrupted, the results are partial"),
+        "{stderr}"
+    );
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("100\ttxt")
+    );
+}
+
+#[test]
+fn commands_named_like_a_directory_warn() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["scan", "types"] {
+        std::fs::create_dir(dir.path().join(name)).unwrap();
+        write_file(&dir.path().join(name).join("a.txt"), 10);
+    }
+
+    for (args, word) in [(&["scan"][..], "scan"), (&["-b", "types"][..], "types")] {
+        fss()
+            .args(args)
+            .current_dir(dir.path())
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(format!(
+                "'{word}' is read as the {word} command"
+            )));
+    }
+    fss()
+        .args(["-b", "-s", "bytes", "./types"])
+        .current_dir(dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("10\ttxt\n"))
+        .stderr("");
+}
+
+#[test]
+fn hardlinks_shared_by_inputs_are_grouped_in_the_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+    std::fs::create_dir(&first).unwrap();
+    std::fs::create_dir(&second).unwrap();
+    write_file(&first.join("shared.bin"), 1000);
+    std::fs::hard_link(first.join("shared.bin"), second.join("shared.bin")).unwrap();
+    write_file(&second.join("b.txt"), 100);
+
+    // Given first, `second` gets the shared file in its total and in its group
+    fss()
+        .args(["-g", "directory", "--per-root", "-b", "-s", "bytes"])
+        .arg(&second)
+        .arg(&first)
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("1100\tsecond\n"))
+        .stdout(predicate::str::contains(format!(
+            "Per input:\n1100\t{}\n   0\t{}\n",
+            second.display(),
+            first.display()
+        )));
+}
+
+#[test]
+fn snapshots_are_diffed_with_their_own_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    write_file(&tree.join("a.txt"), 100);
+    let save = |name: &str, args: &[&str]| {
+        let snapshot = dir.path().join(name);
+        fss()
+            .args(args)
+            .arg("--save-snapshot")
+            .arg(&snapshot)
+            .arg(&tree)
+            .assert()
+            .code(0);
+        snapshot
+    };
+    let old = save("old.snapshot", &["-g", "type", "-b"]);
+    write_file(&tree.join("b.txt"), 100);
+    let new = save("new.snapshot", &["-g", "type", "-b"]);
+
+    // Neither the grouping nor the sizes are given again
+    fss()
+        .args(["-s", "bytes", "diff"])
+        .arg(&old)
+        .arg(&new)
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("200\t↑ +100%\tDocument\n"));
+
+    let disk = save("disk.snapshot", &["-g", "type"]);
+    fss()
+        .arg("diff")
+        .arg(&old)
+        .arg(&disk)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "the snapshots have size_type apparent and disk",
+        ));
+}